@@ -1,9 +1,13 @@
 // system configuration and command line processing
 
 use argh::FromArgs;
+use std::env;
+use std::path::PathBuf;
 
 pub const VERSION: &str = "alpha.25.6.19";
 pub const DEFAULT_CORE: &[&str] = &["./corelib.fs", "~/.f2/corelib.fs", "src/forth/corelib.fs"];
+pub const CORE_FILE: &str = "corelib.fs"; // the core library looked for in each F3PATH directory
+pub const PATH_VARIABLE: &str = "F3PATH"; // colon-separated directories to search for Forth files
 
 #[derive(FromArgs)]
 /// command line arguments for f3.
@@ -12,6 +16,10 @@ pub struct Config {
     #[argh(option, short = 'f')]
     pub loaded_file: Option<String>,
 
+    /// start from a dictionary image saved by save-image, instead of loading the core files.
+    #[argh(option, short = 'i')]
+    pub image: Option<String>,
+
     /// skip loading the core files.
     #[argh(switch, short = 'n')]
     pub no_core: bool,
@@ -19,21 +27,47 @@ pub struct Config {
     /// run the interpreter.
     #[argh(switch, short = 'r')]
     pub run: bool,
+
+    /// don't print the welcome banner or exit message, and hide the boot messages.
+    #[argh(switch, short = 'q')]
+    pub quiet: bool,
+
+    /// arguments passed through to Forth, read with argc and arg.
+    #[argh(positional, greedy)]
+    pub args: Vec<String>,
 }
 
 impl Config {
     pub fn new() -> Self {
         Self {
             loaded_file: None,
+            image: None,
             no_core: false,
             run: true,
+            quiet: false,
+            args: Vec::new(),
         }
     }
 
     pub fn process_args(&mut self) {
         let args: Config = argh::from_env();
         self.loaded_file = args.loaded_file;
+        self.image = args.image;
         self.no_core = args.no_core;
         self.run = args.run;
+        self.quiet = args.quiet;
+        self.args = args.args;
     }
 }
+
+/// search_path returns the directories listed in F3PATH, in order, skipping empty entries
+///
+pub fn search_path() -> Vec<PathBuf> {
+    env::var_os(PATH_VARIABLE)
+        .map(|paths| {
+            env::split_paths(&paths)
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
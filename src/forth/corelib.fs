@@ -11,9 +11,8 @@
 
 : (close) ( -- )                      \ terminate a definition, writing a back pointer and updating context, last, and here
         last @ 1 - here @ !           \ write the new back pointer
-        here @ 1 + here !             \ update HERE
-        last @ context !              \ update CONTEXT
-        ;
+        here @ 1 +  last @            \ HERE moves past it, CONTEXT catches up with LAST
+        (set-dictionary) ;
 
 : constant ( n -- ) create 100002 , ,      \ v constant <name> creates a constant with value v
     (close) ;
@@ -43,12 +42,16 @@
 100010 constant BREAK
 100011 constant EXEC
 100012 constant ARRAY
+100013 constant DEFER
+100014 constant DOES
 
 200000 constant MARK_BEGIN
 200001 constant MARK_WHILE
 200002 constant MARK_FOR
 200003 constant MARK_CASE
 200004 constant MARK_OF
+200005 constant MARK_DO
+200006 constant MARK_QDO
 
 \ Flags and masks used to identify special words and extract addresses
 72057594037927935 constant ADDRESS_MASK                      \ wipes any flags
@@ -56,8 +59,12 @@
 1 62 lshift constant IMMEDIATE_FLAG
 
 \ ASCII symbols that are useful for text processing
+9   constant '\t'
 10  constant '\n'
+13  constant '\r'
+27  constant ESC
 32  constant BL
+32  constant bl
 34  constant '"'
 39  constant '''
 41  constant ')'
@@ -87,6 +94,19 @@
 : variable ( -- ) create VARIABLE , 0 ,
     (close) ;
 
+\ Double-cell values are stored with the top cell (hi) at the lower address
+: 2! ( lo hi addr -- ) swap over ! 1 + ! ;
+: 2@ ( addr -- lo hi ) dup 1 + @ swap @ ;
+
+\ 2variable <name> creates a two-cell variable, initialized to zero
+: 2variable ( -- ) create VARIABLE , 0 , 0 ,
+    (close) ;
+
+\ lo hi 2constant <name> compiles <name> as a definition that pushes lo and hi
+: 2constant ( lo hi -- ) create DEFINITION ,
+    swap LITERAL , , LITERAL , , EXIT ,
+    (close) ;
+
 : decimal 10 base ! ;
 : hex 16 base ! ;
 : binary 2 base ! ;
@@ -100,6 +120,9 @@
 \ : trace ( n -- )    abs stepper-depth ! ;
 : trace-off         0 stepper ! ; immediate
 
+: optimize-on       -1 optimize ! ; immediate       \ Fold constant expressions such as 2 3 + as they are compiled
+: optimize-off      0 optimize ! ; immediate
+
 : dbg-debug         3 dbg ;
 : dbg-info          2 dbg ;
 : dbg-warning       1 dbg ;
@@ -114,7 +137,7 @@
 : text              BL parse ;                      \ Parser shortcut for space-delimited tokens
 : s-parse           tmp @ swap parse-to ;           \ Same as text, but loads to tmp instead of pad
 : (s") ( -- s u )   tmp @ '"' parse-to ; immediate  \ Parses a double-quoted string into tmp, returning the address and length
-: s" ( -- s u ")    tmp @ '"' parse-to ;            \ Places a double-quoted string in tmp
+: s" ( -- s u ")    tmp @ '"' parse-to swap 1 + swap ;  \ Places a double-quoted string in tmp, returning its text
 
 \ File reader functions
 
@@ -127,8 +150,7 @@
                     tmp @ BL parse-to
                     drop include-file drop ;        \ include-file only needs the address
 
-: [ FALSE state ! ; immediate                       \ Turns compile mode off
-: ] TRUE state ! ;                                  \ Turns compile mode on
+\ [ and ] switch between interpret and compile mode inside a definition. They are builtins.
 
 : recurse ( -- )                                    \ Simply compiles the cfa of the word being defined
                     last @ 1 + , ; immediate        \ last points to the latest nfa, so increment
@@ -147,7 +169,7 @@
 
 \ Finding the address of defined words
 
-: ' (') dup @ dup DEFINITION = if drop else nip then ;
+: ' (') dup @ dup BUILTIN_FLAG and if nip else drop then ; \ builtins are referenced by index, other words by cfa
 
 : [']               LITERAL , ' , ; immediate             \ compiles a word's cfa into a definition as a literal
 
@@ -158,50 +180,41 @@
 : nfa>cfa           1 + ;                                 \ converts an nfa to a cfa
 : bp>nfa            1 + ;                                 \ from preceding back pointer to nfa
 : bp>cfa            2 + ;
+: >body ( xt -- addr ) 1 + ;                           \ parameter field of a created word or variable
+: >name ( xt -- nfa ) 1 - ;                           \ name field of a word
+: name> ( nfa -- xt ) 1 + ;                           \ execution token from a name field
 
 \ Arithmetic and logical operations
 
 : 1- ( n -- n-1 )   1 - ;
 : 1+ ( n -- n+1 )   1 + ;
 : negate ( n -- -n ) 0 swap - ;
-: not               0= ;
 : exit ( -- )       BREAK , ; immediate                 \ Pop out of the current definition and reset the Program Counter
-: >                 swap < ;
-: <=                1 + < ;
-: <> ( n -- n )     = 0= ;
 : 0>                0 > ;
-: 0<>               0= 0= ;
 
 \ Stack operations
 
 : pop ( a -- )      drop ;
 : 2dup ( a b -- a b a b ) over over ;
 : 2drop ( a b -- )  drop drop ;
-: ?dup              dup 0= if else dup then ;
+
+\ Deferred words
+
+\ defer <name> creates a word that runs whatever execution token is stored in it by is
+: defer ( -- )      create DEFER , 0 , (close) ;
+
+\ xt is <name> sets the execution token run by a deferred word
+: is ( xt -- )      (') dup if 1 + ! else 2drop then ;
 
 \ Return stack operations
 
 : rdrop  ( -- )     r> drop ;
 : 2rdrop ( -- )     rdrop rdrop ;
-: 2>r ( m n -- )    >r >r ;
-: 2r> ( -- n m )    r> r> ;
-: 2r@ ( -- n m )    r> r> 2dup >r >r ;
 
 \ \\\\\\\\\\\\\\\\\\\\\\\\\\\\\
 \ Memory allocation
 \
-\ If n > 0, reserve n cells of data space. If n < 0, release |n| cells. If n is zero, no action is taken.
-\            Allot updates `here`, but does not create a back pointer.
-: allot ( n -- )
-   dup 0= if
-     drop exit
-   then
-   dup 0> if
-     here @ + here !
-   else
-     here @ swap - here !
-     then
-   ;
+\ allot ( n -- ) is a builtin, so it can check that data space doesn't run into the stack.
 
 \ Used to convert to specify cells in Forths that have byte or other addressing modes (e.g. 8 bit processors with 16-bit addresses)
 \           Not required, but included for completeness.
@@ -220,7 +233,7 @@
 
 : begin ( -- )      here @ MARK_BEGIN >c ; immediate
 
-: while ( -- )      BRANCH0 ,
+: while ( -- )      BRANCH0 , (while)                   \ (while) marks it so see can tell it from if ... again then
                     here @ MARK_WHILE >c
                     999 ,                ; immediate
 : repeat ( -- )     c> drop                             \ pop while branch placeholder address
@@ -229,18 +242,13 @@
                     c> drop                             \ pop begin address
                     here @ - ,           ; immediate    \ save negative offset to BEGIN                          \ emit negative offset
 
-: for               here @ MARK_FOR >c
-                    ['] >r ,             ; immediate
+: for               ['] (for) ,                         \ move the count to the return stack
+                    here @ MARK_FOR >c   ; immediate
 
 : next ( -- )       c>  drop                            \ get FOR addr
-                    ['] r> ,                            \ compile saving the loop index
-                    LITERAL , 1 ,
-                    ['] - ,                             \ compile decrementing the index
-                    ['] dup ,
-                    ['] 0= ,                            \ compile dup and 0 test
+                    ['] (next) ,                        \ compile decrementing the count
                     BRANCH0 ,                           \ compile branch0 backwards
-                    here @ - ,                          \ patch the backwards branch0
-                    ['] drop ,           ; immediate    \ patch forward branch0
+                    here @ - ,           ; immediate    \ patch the backwards branch0
 
 : until             c> drop BRANCH0 ,
                     here @ - ,           ; immediate
@@ -248,69 +256,55 @@
 : again             c> drop BRANCH ,
                     here @ - ,           ; immediate
 
-\ \\\ Under development
-
-: do ( m n -- )     \ Start an iterative loop from m to n
-   ['] 2>r ,        \ Put both values on the return stack
-   here @ MARK_BEGIN >c
-   ; immediate
-
-: loop ( -- )       \ Close an iterative loop, incrementing and checking the loop variable
-    ['] 2r@ ,       \ Get the loop values, leaving them on the stack
-    ['] <           \ If in range...
-    BRANCH0 , 8 ,   \ Jump forward, over the increment and branch code
-    LITERAL , 1 ,   \ increment the loop variable
-    ['] r> ,
-    ['] + ,
-    ['] >r
-    c> drop
-    BRANCH , here @ swap - ,
-    ; immediate
+\ Counted loops: limit start do ... loop runs the body with i from start up to limit-1.
+\     ?do skips the body entirely when limit = start. +loop adds a step each time around.
+
+: do ( limit start -- )
+                    ['] (do) ,
+                    here @ MARK_DO >c    ; immediate
+
+: ?do ( limit start -- )
+                    ['] (?do) ,
+                    BRANCH0 ,
+                    here @ MARK_QDO >c 0 ,              \ forward branch over the loop, patched by loop
+                    here @ MARK_DO >c    ; immediate
+
+: _resolve-do ( -- qdo-addr | 0 , do-addr )             \ pops the markers left by do or ?do
+                    c> MARK_QDO = if c> drop else 0 swap then ;
+
+: _close-do ( -- ) \ compiles the backward branch and loop exit, given the loop test has been compiled
+                    _resolve-do
+                    BRANCH0 ,
+                    here @ - ,                          \ branch back to the start of the body
+                    ['] unloop ,
+                    ?dup if _patch-here then ;          \ ?do skips to here
+
+: loop ( -- )       ['] (loop) , _close-do ; immediate
+: +loop ( n -- )    ['] (+loop) , _close-do ; immediate
 
 \ Case statement
+\     x case 1 of ... endof 2 of ... endof ( default: x is still on the stack ) endcase
+\     The selector stays on the stack. of drops it when it matches, otherwise endcase drops it.
+
+: case ( x -- x )   0 MARK_CASE >c ; immediate          \ marks where endcase stops resolving
+
+: of ( x n -- | x ) ['] over , ['] = ,
+                    BRANCH0 ,
+                    here @ MARK_OF >c 0 ,               \ skip this clause if there's no match
+                    ['] drop , ; immediate
 
-: case      ( val -- val )
-    ['] >r ,                \ put the key on the return stack for later use
-    here @ MARK_CASE >c     \ issues a marker to be retrieved by 'endcase'
-    ;
-    immediate
-
-: of        ( -- )          \ the "if" part
-    ['] r@ ,                \ compile getting the key from the return stack
-    ['] = ,                 \ compile the test
-    BRANCH0 , 999 ,         \ compile the branch. If the key doesn't match, we will skip
-    here @ MARK_OF >c       \ push a marker
-    ;
-    immediate
-
-: endof     ( -- )          \ the "then" part
-    BRANCH ,            \ compile the branch
-    here @ MARK_OF >c       \ push a second marker for this clause
-    888 ,
-    ;
-    immediate
-
-: endcase   ( -- )          \ patch the branches, exiting when the MARK_CASE is found
-    begin
-        c> MARK_CASE =          \ is the first marker a CASE marker?
-        if
-            ['] r> ,            \ pop the key off the return stack
-            ['] drop ,          \ dump the key
-            drop                \ the address
-            exit                \ we've finished
-        else                    \ it's an 'endof' marker, so patch jump to the end. patch_addr is on the stack
-            dup dup             \ save extra copies of the address for the second patch operation
-            here @ swap -
-            swap
-            !                   \ store the patch
-            c>                  \ get the corresponding 'of' marker
-            drop 1 -            \ we could test the type...
-            dup rot swap - 1 + swap
-            !                   \ store the delta between 'of' and 'endof'
-        then
-    again
-    ;
-    immediate
+: endof ( -- )      c> drop                             \ the of placeholder
+                    BRANCH , here @ 0 ,                 \ jump to the end of the case
+                    swap _patch-here                    \ a failed of continues after the jump
+                    MARK_OF >c ; immediate
+
+: endcase ( x -- )  ['] drop ,                          \ only reached when nothing matched
+                    begin
+                        c> MARK_OF =
+                    while
+                        _patch-here                     \ resolve each endof jump to here
+                    repeat
+                    drop ; immediate
 
 \ Takes a typical descending for - next loop, and simplifies reversing the direction of the loop variable
 \     usage is : word incr-for for dup i - ... next .. ;
@@ -324,25 +318,32 @@
 
 : sec ( n -- )      1000 * ms ;  \ sleep for n seconds
 
-: abort" STRLIT , s" .s drop s-create , ['] type , ['] abort , ; immediate \ abort with a message. Use inside another word.
+: abort" STRLIT , s" drop 1- s-create , ['] (abort") , ; immediate \ ( flag -- ) abort with a message if flag is true. Use inside another word.
+
+: buffer: ( n -- )  dup 0< abort" buffer: size must not be negative"  \ n buffer: <name> makes a buffer of n cells,
+    create VARIABLE , dup , allot (close) ; \ laid out like an array; <name> returns the address of the length for b@ and b!
 
 \ Printing words
 
-: emit ( c -- )     \ print a character if in the printable range
-                    128 mod dup 31 > if (emit) else drop then ;
+\ emit and key run the xts in 'emit and 'key, so a program can install its own character I/O
+: emit ( c -- )     \ print a character if in the printable range (including Unicode)
+                    dup 31 > if 'emit @ execute else drop then ;
+: key ( -- c )      'key @ execute ;
 
 : space ( -- )      BL emit ;
 
 : spaces ( n -- )   dup 0> if for space next else drop then ;
-: cr ( -- )         '\n' (emit) ;
+: zeros ( n -- )    dup 0> if for '0' emit next else drop then ;
+: cr ( -- )         '\n' 'emit @ execute ;
 
 : tell ( s l -- )                               \ like type, but length is provided: useful for substrings
-                    swap ADDRESS_MASK and
-                    swap 2dup + rot drop swap
-                    for
-                        dup i - c@ emit
-                    next
-                        drop ;
+                    swap ADDRESS_MASK and swap
+                    begin dup 0> while          \ l is in bytes, so decode each UTF-8 character
+                        2dup utf8@              \ ( s l c n )
+                        swap emit
+                        tuck - >r + r>          \ step past the character
+                    repeat
+                    2drop ;
 
 : type ( s -- )                                 \ Print from the string pointer on the stack
                     ADDRESS_MASK and            \ Wipe out any flags
@@ -369,22 +370,22 @@
 : ."  ( -- )        state @                                    \ Compile or print a string
                     if
                         STRLIT ,                               \ Compilation section
-                        s" drop s-create ,
+                        s" drop 1- s-create ,
                         ['] type ,
                     else
-                        s" drop type                           \ Execution (print) section
+                        s" drop 1- type                        \ Execution (print) section
                     then ; immediate
 
 : stop"  ( -- )     state @                                    \ Compile or print a string
                     if
                         STRLIT ,                               \ Compilation section
-                        s" drop s-create ,
+                        s" drop 1- s-create ,
                         ['] type ,
                         ['] .s ,
                         ['] flush ,
                         ['] key , ['] drop ,
                     else
-                        s" drop type                           \ Execution (print) section
+                        s" drop 1- type                        \ Execution (print) section
                         .s
                         ." Stopped: "
                         flush key drop
@@ -411,6 +412,10 @@
                     swap dup uwidth rot swap -
                     spaces u. ;
 
+: u.0r ( u width -- )                           \ like u.r, but padded with leading zeros
+                    swap dup uwidth rot swap -
+                    zeros u. ;
+
 : .r ( n width -- )
                     swap dup 0<
                     if
@@ -459,22 +464,20 @@
 \                     (traverse-words) ;
 
 : forget-last ( -- )                            \ delete the most recent definitions
-                    here @ 1- @ dup 1+ here !   \ resets HERE to the previous back pointer
-                    @ 1+ dup context ! last !   \ resets CONTEXT and LAST
-                    ;
+                    here @ 1- @ dup 1+          \ HERE goes back to the previous back pointer
+                    swap @ 1+                   \ CONTEXT and LAST to the word before
+                    (set-dictionary) ;
 
 : forget ( <name> )                             \ delete <name> and any words since
                     trace-off step-off          \ we're messing with the dictionary, so we don't want to run FIND
                     (') dup
                     if
-                        1- dup dup here ! @ s-here !            \ move to nfa and set HERE and S-HERE
-                        1- @ 1+ dup context ! last !            \ go back a link and set CONTEXT and LAST
+                        1- dup dup (s-forget)                   \ move to nfa and reset S-HERE
+                        1- @ 1+ (set-dictionary)                \ HERE to the nfa, CONTEXT and LAST back a link
                     else
                         drop
                     then ;
 
-\ : ?stack depth 0= if abort" Stack underflow" then ;
-
 : kkey ( -- c )     >in @ c@ 1 >in +! ;                         \ Get the next character from the TIB
 
 : ?key ( -- c T | F )                                           \ If there's a character in TIB, push it and TRUE
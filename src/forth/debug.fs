@@ -107,7 +107,7 @@ variable word-counter
     ." 100000=BUILTIN  100001=VARIABLE    100002=CONSTANT  100003=LITERAL" cr
     ." 100004=STRLIT   100005=DEFINITION  100006=BRANCH    100007=BRANCH0" cr
     ." 100008=ABORT    100009=EXIT        100010=BREAK     100011=EXEC" cr
-    ." 100012=ARRAY" cr
+    ." 100012=ARRAY    100013=DEFER" cr
     ;
 
 : dump-addr  ( addr -- addr ) dup 7 .r ;
@@ -119,7 +119,7 @@ variable word-counter
 : dump-char  ( val -- val )
     dup
     space ''' emit
-    emit
+    128 mod emit                        \ emit prints any Unicode character, so keep to 7 bits
     ''' emit
     space space
     128 mod dup 32 126 range not if space then drop ;
@@ -142,6 +142,7 @@ variable word-counter
     dup LITERAL    = if ." LITERAL              " exit then
     dup STRLIT     = if ." STRLIT               " exit then
     dup ARRAY      = if ." ARRAY                " exit then
+    dup DEFER      = if ." DEFER                " exit then
     dup DEFINITION = if ." DEFINITION           " exit then
     dup BRANCH     = if ." BRANCH               " exit then
     dup BRANCH0    = if ." BRANCH0              " exit then
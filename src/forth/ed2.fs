@@ -378,7 +378,8 @@ variable ed-cursor-position   \ location for edit and move functions
             ^F  of ed-forward     endof  \ Move forward one character
             ^K  of ed-del-to-eol  endof  \ Delete to end of line
             \ default case stores the character and increments the counter
-            ed-insert
+            \ the selector is still on top, so keep it for endcase
+            >r ed-insert r>
         endcase
     again
 ; 
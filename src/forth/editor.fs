@@ -308,7 +308,7 @@ variable ed-hist-tmp          \ text buffer for swap;
             ^F  of ed-forward     endof  \ Move forward one character
             ^K  of ed-del-to-eol  endof  \ Delete to end of line
             ^P  of ed-history     endof  \ Swap current buffer with history
-            ed-insert                    \ default: stores the character and increments the counter
+            >r ed-insert r>              \ default: stores the character and increments the counter
         endcase
     again
     ; 
@@ -319,5 +319,5 @@ variable ed-hist-tmp          \ text buffer for swap;
         query
         eval
         .s
-        ." ok "
+        prompt @ type
     again ;
@@ -62,9 +62,9 @@ variable test-num 0 test-num !
 12 12 constant months months test-single \ a constant with the value 12
 
 ."        Engine" cr
-264 s" does-not-exist" drop ?unique test-single
-264 s" *" drop ?unique test-single
-264 s" min" drop ?unique test-single
+264 s" does-not-exist" drop 1- ?unique test-single
+264 s" *" drop 1- ?unique test-single
+264 s" min" drop 1- ?unique test-single
 : exit-test 22 33 exit 44 ;
 22 33 exit-test test-dual
 
@@ -162,7 +162,7 @@ FALSE 16 12 15 range test-single
         1 of 111 endof
         2 of 222 endof
         3 of 111 3 * endof
-        222 222 +
+        222 222 + swap                  \ default: the selector stays on top for endcase
     endcase ;
 
 : test-until begin 1- dup dup 0= until drop ;
@@ -28,9 +28,11 @@
 \        For a general array:
 \            Reference the name, and provide an index to access a specific element
 \            Definition:     n array name       Creates a new array with n items accessed from 0 to n - 1
-\            Read access:    n name a@          Returns the value at index n. Undefined if n < 0 or > n - 1
-\            Write access: v n name a!          Stores the value v at index n.
-\            Get length:       name ac@         Returns the number of items the array allocates.
+\            Element address:  i name           Returns the address of item i. Aborts if i < 0 or i > n - 1
+\            Read access:      i name @         Returns the value at index i.
+\            Write access:   v i name !         Stores the value v at index i.
+\            Get length:       ' name >body @   Returns the number of items the array allocates.
+\            a@ and a! are kept for older code: i name a@ and v i name a! work as before.
 \
 \        For a stack:
 \            Definition:     n stack name        Creates a new stack with a maximum depth of n
@@ -53,32 +55,32 @@
 \            Pull from front:  name q>           Removes an element from the front of the queue
 \            Count:            name qc@          Returns the number of elements in the queue
 
-\ Create an array of n cells, with associated pointers and count.
+\ Create an array of n cells, preceded by its length.
 \       Usage:    n array <name>
-: array ( n -- )       
+\       Executing <name> takes an index and returns the address of that element, checking the bounds.
+: array ( n -- )
         create ARRAY ,          \ install the nfa and type token
         dup ,                   \ the number of elements
-        0 , 0 ,                 \ the two pointers used for stacks, deques etc.
         allot (close)           \ allocate the required cells, and update pointers
 ;
 
-\ Get the number of elements in an array
-: ac@ ( addr -- n )
-        @ ;                     \ the use of the array name returns the address of the count
-
-\ Return the value of cell n in an array
-: a@ ( n addr -- v )
-        + 2 +                   \ advance past the count and the two pointers
-        @ ;                     \ this does no bounds checking!
+\ Read or write the element at the address i <name> returns, as in i name a@ and v i name a!
+\       These are @ and !, kept so code written before array names took an index still works.
+alias a@ @
+alias a! !
 
-\ Set cell n in an array
-: a! ( v n addr -- )
-        + 2 + ! ;
+\ Get the number of elements in a stack, deque or queue
+: ac@ ( addr -- n )
+        @ ;                     \ the use of the name returns the address of the count
 
 \ Create a stack of n cells. Stacks use pointer1 as the stack pointer
 \    The stack pointer is an offset into the array, zero based
+\    The name returns the address of the count, followed by the two pointers and the data.
 : stack ( n -- )
-        array ;
+        create VARIABLE ,       \ install the nfa and type token
+        dup ,                   \ the number of elements
+        0 , 0 ,                 \ the two pointers used for stacks, deques etc.
+        allot (close) ;
 
 \ Increment the stack pointer by the value on the stack
 : (s+!) ( v addr -- )     \ addr is the address of the array (counter), not the stack pointer
@@ -19,9 +19,20 @@
  13 constant CR
  14 constant ^N     \ Control-N - move forward in history
  16 constant ^P     \ Control-P - move backwards in history
- 27 constant ESC    \ Escape key
 127 constant DEL    \ Backspace key
 
+\ Codes returned by key for keys that aren't characters, after special-keys-on
+ -1 constant K-UP
+ -2 constant K-DOWN
+ -3 constant K-LEFT
+ -4 constant K-RIGHT
+ -5 constant K-HOME
+ -6 constant K-END
+ -7 constant K-PGUP
+ -8 constant K-PGDN
+ -9 constant K-INS
+: K-F ( n -- code ) -20 swap - ;      \ code for function key Fn
+
 \ Utility functions
 
 \ Generic
@@ -1,13 +1,53 @@
 // Compiler and Interpreter
 
 use crate::internals::general::u_is_integer;
+use crate::internals::messages::ForthError;
+use crate::kernel::{Kernel, DATA_SIZE, STRING_SIZE};
 use crate::runtime::{
-    ForthRuntime, ABORT, ADDRESS_MASK, ARRAY, BRANCH, BRANCH0, BREAK, BUILTIN, CONSTANT,
-    DEFINITION, EXEC, EXIT, LITERAL, STRLIT, VARIABLE,
+    ForthRuntime, ABORT, ADDRESS_MASK, ALIAS, ARRAY, BRANCH, BRANCH0, BREAK, BUILTIN, CONSTANT,
+    DEFER, DEFINITION, DOES, EXEC, EXIT, LITERAL, STRLIT, VARIABLE,
 };
-use crate::runtime::{BUILTIN_FLAG, FALSE, IMMEDIATE_FLAG, TRUE};
+use crate::runtime::{
+    BUILTIN_FLAG, FALSE, IMMEDIATE_FLAG, INLINE_FLAG, MAX_LOCALS, TRUE, WORDBUF_START,
+};
+use std::cmp::min;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// The prompt shown while a definition continues onto another line
+const CONTINUATION_PROMPT: &str = "... ";
+
+/// Builtins that work on the return stack, which a body can't be inlined with, since
+///     inlining removes the call frame they would see
+const RETURN_STACK_WORDS: [&str; 15] = [
+    ">r", "r>", "r@", "2>r", "2r>", "2r@", "(do)", "(?do)", "(loop)", "(+loop)", "(for)", "(next)",
+    "unloop", "(does>)", "(locals)",
+];
+
+/// u_fold_value computes a op b for a builtin whose result depends only on its two arguments
+///     Returns None for other builtins, and where the result would overflow or the shift is out
+///     of range, so the code is left as it was written.
+///
+fn u_fold_value(op: &str, a: i64, b: i64) -> Option<i64> {
+    match op {
+        "+" => a.checked_add(b),
+        "-" => a.checked_sub(b),
+        "*" => a.checked_mul(b),
+        "and" => Some(a & b),
+        "or" => Some(a | b),
+        "lshift" if (0..64).contains(&b) => Some(a << b),
+        "rshift" if (0..64).contains(&b) => Some(a >> b),
+        _ => None,
+    }
+}
 
 impl ForthRuntime {
+    /// no-fold ( -- ) immediate: turns off constant folding for the rest of the current definition
+    ///
+    pub fn f_no_fold(&mut self) {
+        self.no_fold = true;
+    }
+
     /// immediate ( -- ) sets the immediate flag on the most recently defined word
     ///     Context pointer links to the most recent name field
     ///
@@ -19,6 +59,80 @@ impl ForthRuntime {
         self.kernel.set(addr, str_addr as i64);
     }
 
+    /// inline ( -- ) marks the most recent definition to be copied into later definitions instead of called
+    ///     Only leaf words can be inlined: literals and builtins, with no control flow or locals.
+    ///
+    pub fn f_inline(&mut self) {
+        let nfa = self.kernel.get(self.context_ptr) as usize;
+        if self.u_inline_body(nfa + 1).is_none() {
+            let name = self.u_word_name(nfa as i64 + 1);
+            self.msg.error(
+                "inline",
+                "Only leaf words without control flow can be inlined",
+                Some(name),
+            );
+            self.set_error(ForthError::CompileError);
+            self.f_abort();
+            return;
+        }
+        let flags = self.kernel.get(nfa) as usize | INLINE_FLAG;
+        self.kernel.set(nfa, flags as i64);
+    }
+
+    /// u_inline_body returns the cells of the definition at cfa up to its EXIT, or None if it can't be inlined
+    ///     Control flow, calls to other definitions, and builtins in RETURN_STACK_WORDS prevent inlining.
+    ///
+    fn u_inline_body(&mut self, cfa: usize) -> Option<Vec<i64>> {
+        if self.kernel.get(cfa) != DEFINITION {
+            return None;
+        }
+        let mut body = Vec::new();
+        let mut addr = cfa + 1;
+        while addr < self.here() {
+            let cell = self.kernel.get(addr);
+            match cell {
+                EXIT => return Some(body),
+                LITERAL | STRLIT => {
+                    body.push(cell);
+                    body.push(self.kernel.get(addr + 1));
+                    addr += 2;
+                }
+                _ if cell as usize & BUILTIN_FLAG != 0 => {
+                    let index = cell as usize & ADDRESS_MASK;
+                    if index > self.kernel.max_builtin()
+                        || RETURN_STACK_WORDS
+                            .contains(&self.kernel.get_builtin(index).name.as_str())
+                    {
+                        return None;
+                    }
+                    body.push(cell);
+                    addr += 1;
+                }
+                _ => return None, // control flow, or a call to another definition
+            }
+        }
+        None
+    }
+
+    /// u_compile_inline copies the body of an inline word into the current definition
+    ///     Returns false if the word at cfa isn't inline, so it should be compiled as a call.
+    ///
+    fn u_compile_inline(&mut self, cfa: usize) -> bool {
+        if self.kernel.get(cfa - 1) as usize & INLINE_FLAG == 0 {
+            return false;
+        }
+        match self.u_inline_body(cfa) {
+            Some(body) => {
+                for cell in body {
+                    self.emit_cell(cell);
+                }
+                self.literal_run.clear();
+                true
+            }
+            None => false,
+        }
+    }
+
     /// immediate? ( cfa -- flag ) checks if the word at cfa is immediate
     ///
     pub fn f_immediate_q(&mut self) {
@@ -45,30 +159,66 @@ impl ForthRuntime {
             if self.should_exit() {
                 break;
             } else {
-                self.set_abort_flag(false);
-                self.f_query();
-                self.f_eval(); // interpret the contents of the line
-                if self.reader.len() == 1 {
-                    if self.show_stack {
-                        self.f_dot_s();
-                    }
-                    print!("ok ");
-                }
-                self.f_flush();
+                self.u_quit_line();
             }
         }
     }
 
+    /// u_quit_line reads and interprets one line, then prompts if input is interactive again
+    ///     Returns true if it printed the prompt. Lines from an included file get no prompt,
+    ///     and the prompt comes back once when the file has been read to the end.
+    ///
+    pub fn u_quit_line(&mut self) -> bool {
+        self.check_interrupt(); // a Ctrl-C that arrived outside a definition
+        self.set_abort_flag(false);
+        self.clear_error();
+        self.f_query();
+        self.f_eval(); // interpret the contents of the line
+        let interactive = self.reader.len() == 1;
+        if interactive {
+            // the stack isn't interesting halfway through a definition
+            if self.show_stack && !self.get_compile_mode() {
+                self.f_dot_s();
+            }
+            let prompt = self.u_line_prompt();
+            let _ = write!(self.out, "{prompt}");
+        }
+        self.f_flush();
+        interactive
+    }
+
+    /// u_line_prompt returns the prompt for the next line: PROMPT, or a continuation
+    ///     prompt if a definition is still open
+    ///
+    pub fn u_line_prompt(&mut self) -> String {
+        if self.get_compile_mode() {
+            CONTINUATION_PROMPT.to_string()
+        } else {
+            self.u_prompt()
+        }
+    }
+
+    /// u_prompt returns the text of the prompt, the counted string that PROMPT points to
+    ///
+    pub fn u_prompt(&mut self) -> String {
+        let addr = self.kernel.get(self.prompt_ptr) as usize & ADDRESS_MASK;
+        self.kernel.string_get(addr)
+    }
+
     /// EXECUTE ( cfa -- ) interpret a word with addr on the stack
     /// stack value is the address of an inner interpreter
     ///
-
     pub fn f_execute(&mut self) {
         if self.kernel.stack_check(1, "execute") {
             // call the appropriate inner interpreter
             let xt = self.kernel.pop();
+            if xt as usize & BUILTIN_FLAG != 0 {
+                // a compiled builtin reference, as left by ' or stored by IS
+                self.builtin(xt as usize & ADDRESS_MASK);
+                return;
+            }
             self.kernel.push(xt + 1);
-            let opcode = self.kernel.get(xt as usize & ADDRESS_MASK) as i64;
+            let opcode = self.kernel.get(xt as usize & ADDRESS_MASK);
             // println!("f_execute: opcode = {opcode} xt = {xt}");
             match opcode {
                 BUILTIN => self.msg.error("f_execute", "BUILTIN found", Some(xt)), //self.i_builtin(),
@@ -83,6 +233,9 @@ impl ForthRuntime {
                 EXIT => self.i_exit(),
                 BREAK => self.i_exit(),
                 ARRAY => self.i_array(),
+                DEFER => self.i_defer(),
+                ALIAS => self.i_defer(), // runs the word named by the next cell, like DEFER
+                DOES => self.i_does(),
                 _ => {
                     self.kernel.pop();
                     let cfa = self.kernel.get(xt as usize) as usize & ADDRESS_MASK;
@@ -98,6 +251,9 @@ impl ForthRuntime {
             let val = self.kernel.get(self.pad_ptr);
             self.kernel.push(val);
             self.kernel.push(' ' as i64);
+            if self.u_stack_overflow() {
+                break; // no room to parse the rest of the line
+            }
             self.f_parse_to(); //  ( -- b u ) get a token
             let len = self.kernel.pop();
             if len == FALSE {
@@ -111,15 +267,59 @@ impl ForthRuntime {
                 } else {
                     self.f_d_interpret();
                 }
+                if self.u_stack_overflow() {
+                    break; // e.g. a number pushed onto a full stack
+                }
             }
         }
     }
 
+    /// eval_str interprets Rust-supplied text one line at a time, as if it had been typed at the prompt
+    ///     Used by the tests to drive the interpreter without a terminal.
+    ///
+    #[cfg(test)]
+    pub fn eval_str(&mut self, text: &str) {
+        self.clear_error();
+        for line in text.lines() {
+            let tib = self.kernel.get(self.tib_ptr) as usize;
+            self.kernel.string_save(line, tib);
+            self.kernel.set(self.tib_size_ptr, line.len() as i64);
+            self.kernel.set(self.tib_in_ptr, 1);
+            self.set_abort_flag(false);
+            self.f_eval();
+        }
+    }
+
+    /// load_core includes corelib.fs and runs the readers until the file stack is back to stdin
+    ///
+    #[cfg(test)]
+    pub fn load_core(&mut self) {
+        let addr = self.kernel.get(self.tmp_ptr) as usize;
+        self.kernel.string_set(addr, "src/forth/corelib.fs");
+        self.kernel.push(addr as i64);
+        self.f_include_file();
+        assert_eq!(self.kernel.pop(), TRUE, "corelib.fs not found");
+        while self.reader.len() > 1 {
+            self.set_abort_flag(false);
+            self.f_query();
+            self.f_eval();
+        }
+    }
+
     /// $COMPILE ( s -- ) compiles a token whose string address is on the stack
     ///            If not a word, try to convert to a number
     ///            If not a number, ABORT.
     pub fn f_d_compile(&mut self) {
         if self.kernel.stack_check(1, "$compile") {
+            if !self.compile_locals.is_empty() {
+                // locals hide dictionary words with the same name
+                let name = self.kernel.string_get(self.kernel.top() as usize);
+                if let Some(k) = self.compile_locals.iter().rposition(|l| *l == name) {
+                    self.kernel.pop();
+                    self.u_compile_local(k, "(local@)");
+                    return;
+                }
+            }
             self.f_find();
             if self.kernel.pop() == TRUE {
                 let cfa = self.kernel.top();
@@ -127,36 +327,139 @@ impl ForthRuntime {
                 // if it's immediate, we need to execute it; otherwise continue compiling
                 self.f_immediate_q();
                 if self.kernel.pop() == TRUE {
+                    // an immediate word may leave a branch target here, so nothing before it is folded
+                    self.literal_run.clear();
                     // call the interpreter for this word
                     let val = self.kernel.get(self.pad_ptr);
                     self.kernel.push(val);
                     self.f_d_interpret();
-                } else {
-                    // check if it's a builtin, and compile appropriately
-                    let indirect = self.kernel.get(cfa as usize) as usize;
-                    if indirect & BUILTIN_FLAG != 0 {
-                        self.kernel.push(indirect as i64);
-                    } else {
-                        self.kernel.push(cfa);
+                } else if !self.u_compile_inline(cfa as usize) {
+                    let xt = self.u_compiled_ref(cfa);
+                    if !self.u_fold_literals(xt) {
+                        self.kernel.push(xt);
+                        self.f_comma(); // uses the cfa on the stack
                     }
-                    self.f_comma(); // uses the cfa on the stack
                 }
             } else {
                 self.f_number_q();
                 if self.kernel.pop() == TRUE {
-                    self.f_literal(); // compile the literal
+                    self.u_compile_number(); // compile the literal
                 } else {
                     self.kernel.pop(); // lose the failed number
                     let addr = self.kernel.get(self.pad_ptr) as usize;
-                    let word = &self.kernel.string_get(addr);
-                    self.msg
-                        .warning("$interpret", "token not recognized", Some(word));
+                    let word = self.kernel.string_get(addr);
+                    self.u_not_found(&word);
                     self.f_abort();
                 }
             }
         }
     }
 
+    /// u_not_found reports a token that is neither a word nor a number, with its file and line
+    ///     when it comes from a file
+    ///
+    fn u_not_found(&mut self, word: &str) {
+        self.set_error(ForthError::NotFound {
+            name: word.to_string(),
+        });
+        let text = match &self.error_source {
+            Some(location) => format!("token not recognized at {location}"),
+            None => "token not recognized".to_string(),
+        };
+        self.msg.warning("$interpret", &text, Some(word));
+    }
+
+    /// u_compiled_ref returns what a definition holds to call the word at cfa
+    ///     Builtins are referenced by their flagged index, other words by their cfa.
+    ///
+    fn u_compiled_ref(&mut self, cfa: i64) -> i64 {
+        let indirect = self.kernel.get(cfa as usize) as usize;
+        if indirect & BUILTIN_FLAG != 0 {
+            indirect as i64
+        } else {
+            cfa
+        }
+    }
+
+    /// u_compile_number ( n -- ) compiles a literal, remembering it for constant folding
+    ///
+    fn u_compile_number(&mut self) {
+        let here = self.here();
+        if self
+            .literal_run
+            .last()
+            .is_some_and(|&last| last + 2 != here)
+        {
+            self.literal_run.clear(); // something else was compiled since the last literal
+        }
+        self.literal_run.push(here);
+        self.f_literal();
+    }
+
+    /// u_fold_literals replaces LITERAL a LITERAL b and the operator xt with LITERAL (a op b)
+    ///     Only happens when optimize is set and no-fold hasn't been used in this definition,
+    ///     and only for literals compiled back to back since the last immediate word, so the
+    ///     fold never spans a branch target. Nothing is folded if the result would overflow.
+    ///
+    fn u_fold_literals(&mut self, xt: i64) -> bool {
+        let count = self.literal_run.len();
+        if self.kernel.get(self.optimize_ptr) == FALSE
+            || self.no_fold
+            || count < 2
+            || xt as usize & BUILTIN_FLAG == 0
+        {
+            return false;
+        }
+        let index = xt as usize & ADDRESS_MASK;
+        if index > self.kernel.max_builtin() {
+            return false;
+        }
+        let (first, second) = (self.literal_run[count - 2], self.literal_run[count - 1]);
+        if first + 2 != second || second + 2 != self.here() {
+            return false;
+        }
+        let a = self.kernel.get(first + 1);
+        let b = self.kernel.get(second + 1);
+        let Some(value) = u_fold_value(&self.kernel.get_builtin(index).name, a, b) else {
+            return false;
+        };
+        self.kernel.push(value);
+        self.literal_run.truncate(count - 2);
+        self.kernel.set(self.here_ptr, first as i64);
+        self.u_compile_number(); // the result is on the stack
+        true
+    }
+
+    /// postpone <name> ( -- ) compiles the compilation behaviour of name into the current definition
+    ///     An immediate word is compiled as a call, so it runs when the new word is used
+    ///     inside another definition. Any other word gets code that compiles a reference to it.
+    ///
+    pub fn f_postpone(&mut self) {
+        self.f_tick_p();
+        let cfa = self.kernel.pop();
+        if cfa == FALSE {
+            let addr = self.kernel.get(self.pad_ptr) as usize;
+            let name = self.kernel.string_get(addr);
+            self.msg.error("postpone", name.trim(), None::<bool>);
+            self.set_error(ForthError::NotFound {
+                name: name.trim().to_string(),
+            });
+            self.f_abort();
+            return;
+        }
+        let xt = self.u_compiled_ref(cfa);
+        self.kernel.push(cfa);
+        self.f_immediate_q();
+        if self.kernel.pop() == TRUE {
+            self.emit_cell(xt);
+        } else {
+            let comma = self.u_builtin_xt(",").unwrap_or(0);
+            self.emit_cell(LITERAL);
+            self.emit_cell(xt);
+            self.emit_cell(comma);
+        }
+    }
+
     /// $INTERPRET ( s -- ) executes a token whose string address is on the stack.
     ///            If not a word, try to convert to a number
     ///            If not a number, ABORT.
@@ -175,42 +478,173 @@ impl ForthRuntime {
                     // leave the converted number on the stack
                 } else {
                     self.kernel.pop(); // lose the failed number
-                    let word = &self.kernel.string_get(token_addr as usize);
-                    self.msg
-                        .warning("$interpret", "token not recognized", Some(word));
+                    let word = self.kernel.string_get(token_addr as usize);
+                    self.u_not_found(&word);
                 }
             }
         }
     }
 
     /// FIND (s -- cfa T | s F ) Search the dictionary for the token indexed through s.
-    ///     If not found, return the string address so NUMBER? can look at it
+    ///     If not found, return the string address so NUMBER? can look at it.
+    ///     The word index is tried first. On a miss the dictionary is walked back from CONTEXT,
+    ///     so a word is still found if the index has fallen out of step with the dictionary.
     ///
     pub fn f_find(&mut self) {
         if self.kernel.stack_check(1, "find") {
-            let mut result = false;
             let source_addr = self.kernel.pop() as usize;
-            let mut link = self.kernel.get(self.context_ptr) as usize - 1;
-            // link = self.data[link] as usize; // go back to the beginning of the top word
-            while link > 0 {
-                // name field is immediately after the link
-                let nfa_val = self.kernel.get(link + 1);
-                let str_addr = nfa_val as usize & ADDRESS_MASK;
-                if self.kernel.string_equal(source_addr, str_addr) {
-                    result = true;
-                    break;
+            let found = self
+                .u_find_indexed(source_addr)
+                .or_else(|| self.u_find_linked(source_addr));
+            if let Some(nfa) = found {
+                let mut cfa = nfa as i64 + 1;
+                if self.kernel.get(cfa as usize) == ALIAS {
+                    cfa = self.kernel.get(cfa as usize + 1); // an alias is found as its word
                 }
-                link = self.kernel.get(link) as usize;
-            }
-            if result {
-                self.kernel.push(link as i64 + 2);
+                self.kernel.push(cfa);
                 self.kernel.push(TRUE);
             } else {
                 self.kernel.push(source_addr as i64);
                 self.kernel.push(FALSE);
             }
-        } else {
-            // stack error
+        }
+    }
+
+    /// u_find_linked walks the back links from CONTEXT looking for the name at source_addr,
+    ///     returning its nfa
+    ///
+    fn u_find_linked(&mut self, source_addr: usize) -> Option<usize> {
+        let mut link = self.kernel.get(self.context_ptr) as usize - 1;
+        while link > 0 {
+            // name field is immediately after the link
+            let str_addr = self.kernel.get(link + 1) as usize & ADDRESS_MASK;
+            if self.kernel.string_equal(source_addr, str_addr) {
+                return Some(link + 1);
+            }
+            link = self.kernel.get(link) as usize;
+        }
+        None
+    }
+
+    /// find-word ( s -- s 0 | cfa 1 | cfa -1 ) Search the dictionary for the counted string at s
+    ///     The standard form of FIND: 1 means the word is immediate, -1 that it isn't.
+    ///
+    pub fn f_find_word(&mut self) {
+        if self.kernel.stack_check(1, "find-word") {
+            self.f_find();
+            if self.kernel.pop() == FALSE {
+                self.kernel.push(0);
+            } else {
+                let cfa = self.kernel.peek(0) as usize & ADDRESS_MASK;
+                let immediate = self.kernel.get(cfa - 1) as usize & IMMEDIATE_FLAG != 0;
+                self.kernel.push(if immediate { 1 } else { -1 });
+            }
+        }
+    }
+
+    /// u_find_indexed looks up the name at source_addr in the word index, returning its nfa
+    ///     Entries can refer to words that aren't visible yet (still being defined), or to a header
+    ///     abandoned without an abort, whose space may since have been reused. So each candidate,
+    ///     newest first, is checked against CONTEXT and the name stored in the dictionary.
+    ///
+    fn u_find_indexed(&mut self, source_addr: usize) -> Option<usize> {
+        let name = self.kernel.string_get(source_addr);
+        let context = self.kernel.get(self.context_ptr) as usize;
+        let kernel = &mut self.kernel;
+        self.word_index
+            .get(&name)?
+            .iter()
+            .rev()
+            .copied()
+            .find(|&nfa| {
+                let str_addr = kernel.get(nfa) as usize & ADDRESS_MASK;
+                nfa <= context && kernel.string_equal(source_addr, str_addr)
+            })
+    }
+
+    /// u_index_word adds the word whose name field is at nfa to the word index
+    ///
+    pub fn u_index_word(&mut self, nfa: usize) {
+        let str_addr = self.kernel.get(nfa) as usize & ADDRESS_MASK;
+        let name = self.kernel.string_get(str_addr);
+        self.word_index.entry(name).or_default().push(nfa);
+    }
+
+    /// u_unindex_above drops the index entries of words whose name field is past nfa,
+    ///     which have been forgotten or abandoned
+    ///
+    pub fn u_unindex_above(&mut self, nfa: usize) {
+        self.word_index.retain(|_, nfas| {
+            nfas.retain(|&n| n <= nfa);
+            !nfas.is_empty()
+        });
+    }
+
+    /// u_rebuild_index indexes the whole dictionary from scratch, e.g. after loading an image
+    ///
+    pub fn u_rebuild_index(&mut self) {
+        self.word_index.clear();
+        let mut nfas = Vec::new();
+        let mut link = self.kernel.get(self.context_ptr) as usize - 1;
+        while link > 0 {
+            nfas.push(link + 1);
+            link = self.kernel.get(link) as usize;
+        }
+        for nfa in nfas.into_iter().rev() {
+            self.u_index_word(nfa);
+        }
+    }
+
+    /// (set-dictionary) ( a nfa -- ) sets HERE to a, and CONTEXT and LAST to nfa
+    ///     The dictionary pointers are below the watermark, so ! cannot move them.
+    ///
+    pub fn f_set_dictionary(&mut self) {
+        if self.kernel.stack_check(2, "(set-dictionary)") {
+            let nfa = self.kernel.pop();
+            let here = self.kernel.pop();
+            if here < self.watermark as i64 || !self.kernel.addr_check(here as usize) {
+                self.msg.error(
+                    "(set-dictionary)",
+                    "HERE would leave the dictionary",
+                    Some(here),
+                );
+                self.set_error(ForthError::Protected);
+                self.f_abort();
+                return;
+            }
+            self.kernel.set(self.here_ptr, here);
+            self.kernel.set(self.context_ptr, nfa);
+            self.kernel.set(self.last_ptr, nfa);
+            self.while_branches.retain(|&addr| addr < here as usize);
+            self.u_unindex_above(nfa as usize);
+        }
+    }
+
+    /// (while) ( -- ) records that the BRANCH0 just compiled, at HERE - 1, was compiled by while
+    ///     begin ... while ... repeat compiles to the same code as begin ... if ... again then,
+    ///     so this is the only way see can show the one that was written.
+    ///
+    pub fn f_while_p(&mut self) {
+        let addr = self.here() - 1;
+        self.while_branches.insert(addr);
+    }
+
+    /// (s-forget) ( nfa -- ) releases string space from the name of a word being forgotten
+    ///     Names are interned, so the space is kept if an older word shares the name.
+    ///
+    pub fn f_s_forget(&mut self) {
+        if self.kernel.stack_check(1, "(s-forget)") {
+            let nfa = self.kernel.pop() as usize;
+            let str_addr = self.kernel.get(nfa) as usize & ADDRESS_MASK;
+            let name = self.kernel.string_get(str_addr);
+            let shared = self.word_index.get(&name).is_some_and(|nfas| {
+                nfas.iter()
+                    .any(|&n| n < nfa && self.kernel.get(n) as usize & ADDRESS_MASK == str_addr)
+            });
+            if !shared {
+                self.kernel
+                    .set(self.kernel.get_string_ptr(), str_addr as i64);
+            }
         }
     }
 
@@ -220,7 +654,7 @@ impl ForthRuntime {
     pub fn f_number_q(&mut self) {
         let buf_addr = self.kernel.pop();
         let numtext = self.kernel.string_get(buf_addr as usize);
-        if u_is_integer(&numtext.as_str()) {
+        if u_is_integer(numtext.as_str()) {
             let result = numtext.parse().unwrap();
             self.kernel.push(result);
             self.kernel.push(TRUE);
@@ -233,10 +667,36 @@ impl ForthRuntime {
     /// f_comma ( n -- ) compile a value into a definition
     ///     Takes the top of the stack and writes it to the next free location in data space
     pub fn f_comma(&mut self) {
-        let addr = self.kernel.get(self.here_ptr) as usize;
         let val = self.kernel.pop();
-        self.kernel.set(addr, val);
-        self.kernel.incr(self.here_ptr); // increment HERE pointer to first free cell
+        if self.u_dictionary_room(",", 1) {
+            let addr = self.kernel.get(self.here_ptr) as usize;
+            if !self.u_writable(",", addr) {
+                return;
+            }
+            self.kernel.set(addr, val);
+            self.kernel.incr(self.here_ptr); // increment HERE pointer to first free cell
+        }
+    }
+
+    /// allot ( n -- ) reserves n cells of data space, or releases them if n is negative
+    ///     Allot updates HERE, but does not create a back pointer.
+    ///
+    pub fn f_allot(&mut self) {
+        if self.kernel.stack_check(1, "allot") {
+            let n = self.kernel.pop();
+            let last = self.kernel.get(self.last_ptr);
+            if n < 0
+                && (self.here() as i64 + n <= last
+                    || self.here() as i64 + n < self.watermark as i64)
+            {
+                self.msg
+                    .error("allot", "Can't free space below the current word", Some(n));
+                self.set_error(ForthError::Protected);
+                self.f_abort();
+            } else if self.u_dictionary_room("allot", n) {
+                self.kernel.delta(self.here_ptr, n);
+            }
+        }
     }
 
     /// f_literal ( n -- ) compile a literal number with it's inner interpreter code pointer
@@ -244,25 +704,71 @@ impl ForthRuntime {
     ///     The value comes from the stack.
     ///
     pub fn f_literal(&mut self) {
-        self.emit_cell(LITERAL);
-        self.f_comma(); // write the value passed in from the stack
+        if self.kernel.stack_check(1, "literal") {
+            self.emit_cell(LITERAL);
+            self.f_comma(); // write the value passed in from the stack
+        }
+    }
+
+    /// [ ( -- ) switches to interpret mode inside a definition, e.g. to compute a value for literal
+    ///
+    pub fn f_left_bracket(&mut self) {
+        self.set_compile_mode(false);
+    }
+
+    /// ] ( -- ) switches back to compile mode after [
+    ///
+    pub fn f_right_bracket(&mut self) {
+        self.set_compile_mode(true);
     }
 
     /// UNIQUE? (s -- s )
     ///     Checks the dictionary to see if the word pointed to is defined.
-    ///     No stack impact - it's just offering a warning.
+    ///     No stack impact - it's just offering a warning, unless builtins are protected.
     pub fn f_q_unique(&mut self) {
+        self.u_unique();
+    }
+
+    /// u_unique does the work of UNIQUE?, returning false if the name may not be redefined
+    ///     A rejected name aborts, which also empties the stack and discards the rest of the line.
+    ///
+    fn u_unique(&mut self) -> bool {
         self.f_dup();
         self.f_find();
         let result = self.kernel.pop();
-        let s_addr = self.kernel.pop() as usize - 1 & ADDRESS_MASK;
+        let cfa = self.kernel.pop() as usize & ADDRESS_MASK;
         if result == TRUE {
+            let s_addr = cfa - 1;
+            if self.protect_builtins && self.kernel.get(cfa) as usize & BUILTIN_FLAG != 0 {
+                let str_addr = self.kernel.get(s_addr) as usize & ADDRESS_MASK;
+                let name = self.kernel.string_get(str_addr);
+                self.msg
+                    .error("unique?", "Builtins are protected", Some(name));
+                self.set_error(ForthError::Protected);
+                self.f_abort();
+                self.kernel.set(self.tib_size_ptr, 0);
+                self.kernel.set(self.tib_in_ptr, 1);
+                return false;
+            }
             self.msg.warning(
                 "unique?",
                 "Overwriting existing definition name at ",
                 Some(self.kernel.get(s_addr)),
             );
         }
+        true
+    }
+
+    /// protect-builtins ( -- ) makes redefining a builtin an error instead of a warning
+    ///
+    pub fn f_protect_builtins(&mut self) {
+        self.protect_builtins = true;
+    }
+
+    /// unprotect-builtins ( -- ) allows builtins to be redefined again, with a warning
+    ///
+    pub fn f_unprotect_builtins(&mut self) {
+        self.protect_builtins = false;
     }
 
     /// (') (TICK) <name> ( -- a | FALSE ) Searches for a word, places cfa on stack if found; otherwise FALSE
@@ -290,6 +796,23 @@ impl ForthRuntime {
         // self.kernel.pop();
     }
 
+    /// word ( c -- s ) Get the next c-delimited token from TIB as a counted string
+    ///     Leading delimiters are skipped, and >IN is updated. If there is no token,
+    ///     the string is empty. The result has its own buffer, because the interpreter
+    ///     reuses PAD for every token it reads.
+    ///
+    pub fn f_word(&mut self) {
+        if self.kernel.stack_check(1, "word") {
+            let delim = self.kernel.pop();
+            self.kernel.push(WORDBUF_START as i64);
+            self.kernel.push(delim);
+            self.f_parse_to(); // ( -- b u )
+            if self.kernel.pop() == 0 {
+                self.kernel.byte_set(WORDBUF_START, 0);
+            }
+        }
+    }
+
     /// (parse) - ( b u c -- b u delta )
     ///     Find a c-delimited token in the string buffer at b, buffer len u.
     ///     This is the heart of the parsing engine.
@@ -350,7 +873,6 @@ impl ForthRuntime {
                 let val = self.kernel.get(self.tib_ptr);
                 self.kernel.push(val);
                 self.kernel.push(0); // indicates nothing found, TIB is empty
-                return;
             } else {
                 let addr = self.kernel.get(self.tib_ptr) + self.kernel.get(self.tib_in_ptr);
                 self.kernel.push(addr); // Starting address in the string
@@ -384,8 +906,14 @@ impl ForthRuntime {
     ///     the word is to be processed at run time.
     ///
     pub fn f_colon(&mut self) {
+        self.compile_locals.clear();
+        self.literal_run.clear();
+        self.no_fold = false;
+        // get the name and make a new dictionary entry
+        if !self.u_create() {
+            return;
+        }
         self.set_compile_mode(true);
-        self.f_create(); // gets the name and makes a new dictionary entry
         self.emit_cell(DEFINITION);
     }
 
@@ -402,32 +930,242 @@ impl ForthRuntime {
         self.kernel.set(here, back - 1); // write the back pointer
         self.kernel.incr(self.here_ptr); // over EXIT and back pointer
         self.kernel.set(self.context_ptr, back); // adds the new definition to FIND
+        self.definition_mark = None; // the definition is complete, so its strings are in use
+        self.compile_locals.clear();
         self.set_compile_mode(false);
     }
 
+    /// does> ( -- ) ends the creating part of a defining word; the rest of the definition is
+    ///     what the words it creates will do, starting with their data address on the stack.
+    ///     Compiles LITERAL <address of the does> code> (does>) EXIT
+    ///
+    pub fn f_does(&mut self) {
+        if !self.get_compile_mode() {
+            self.msg
+                .error("does>", "Only valid inside a definition", None::<bool>);
+            self.set_error(ForthError::CompileError);
+            self.f_abort();
+            return;
+        }
+        let does_p = self.u_builtin_xt("(does>)").unwrap_or(0);
+        self.emit_cell(LITERAL);
+        let code_addr = self.here();
+        self.emit_cell(0); // patched below, once we know where the does> code starts
+        self.emit_cell(does_p);
+        self.emit_cell(EXIT);
+        let here = self.here() as i64;
+        self.kernel.set(code_addr, here);
+    }
+
+    /// (does>) ( addr -- ) gives the most recently created word the does> behaviour at addr
+    ///     The word's data moves up two cells to make room for the DOES opcode and the code address.
+    ///     So an address of the data taken in the creating part, before does>, is two cells short.
+    ///     If the word hasn't been closed, it is closed here, so create ... does> needs no (close).
+    ///
+    pub fn f_does_p(&mut self) {
+        if self.kernel.stack_check(1, "(does>)") {
+            let code = self.kernel.pop();
+            let last = self.kernel.get(self.last_ptr) as usize;
+            let cfa = last + 1;
+            if self.kernel.get(cfa) == DOES {
+                self.kernel.set(cfa + 1, code);
+                return;
+            }
+            let closed = self.kernel.get(self.context_ptr) as usize == last;
+            if !self.u_dictionary_room("does>", if closed { 2 } else { 3 }) {
+                return;
+            }
+            if !closed {
+                let here = self.here();
+                self.kernel.set(here, last as i64 - 1); // the back pointer
+                self.kernel.incr(self.here_ptr);
+                self.kernel.set(self.context_ptr, last as i64);
+            }
+            let here = self.here();
+            for addr in (cfa..here).rev() {
+                let val = self.kernel.get(addr);
+                self.kernel.set(addr + 2, val);
+            }
+            self.kernel.set(cfa, DOES);
+            self.kernel.set(cfa + 1, code);
+            self.kernel.set(self.here_ptr, here as i64 + 2);
+        }
+    }
+
+    /// { a b | c -- comment } declares locals for the definition being compiled
+    ///     Names before | or -- are initialized from the stack, with the last name taking the top.
+    ///     Names after | start at zero. Everything after -- is a comment, up to the closing }.
+    ///     Inside the definition, a local's name pushes its value, and TO <name> stores into it.
+    ///
+    pub fn f_locals(&mut self) {
+        if !self.get_compile_mode() || !self.compile_locals.is_empty() {
+            self.msg.error(
+                "{",
+                "Locals must be declared once, inside a definition",
+                None::<bool>,
+            );
+            self.set_error(ForthError::CompileError);
+            self.f_abort();
+            return;
+        }
+        let mut args = 0;
+        let mut initialized = true;
+        let mut comment = false;
+        loop {
+            match self.u_next_token().as_deref() {
+                None => {
+                    self.msg.error("{", "Missing }", None::<bool>);
+                    self.compile_locals.clear();
+                    self.set_error(ForthError::CompileError);
+                    self.f_abort();
+                    return;
+                }
+                Some("}") => break,
+                Some(_) if comment => {}
+                Some("--") => comment = true,
+                Some("|") => initialized = false,
+                Some(_) if self.compile_locals.len() == MAX_LOCALS => {
+                    self.msg.error("{", "Too many locals", Some(MAX_LOCALS));
+                    self.compile_locals.clear();
+                    self.set_error(ForthError::CompileError);
+                    self.f_abort();
+                    return;
+                }
+                Some(name) => {
+                    self.compile_locals.push(name.to_string());
+                    if initialized {
+                        args += 1;
+                    }
+                }
+            }
+        }
+        self.emit_cell(LITERAL);
+        self.emit_cell(args);
+        self.emit_cell(LITERAL);
+        self.emit_cell(self.compile_locals.len() as i64);
+        if let Some(xt) = self.u_builtin_xt("(locals)") {
+            self.emit_cell(xt);
+        }
+    }
+
+    /// to <name> ( n -- ) compiles a store into the local <name>
+    ///
+    pub fn f_to(&mut self) {
+        let name = self.u_next_token().unwrap_or_default();
+        match self.compile_locals.iter().rposition(|l| *l == name) {
+            Some(k) if self.get_compile_mode() => self.u_compile_local(k, "(local!)"),
+            _ => {
+                self.msg.error("to", "Not a local", Some(name));
+                self.set_error(ForthError::CompileError);
+                self.f_abort();
+            }
+        }
+    }
+
+    /// u_compile_local compiles local k as an argument to the builtin that accesses it
+    ///
+    fn u_compile_local(&mut self, k: usize, word: &str) {
+        self.emit_cell(LITERAL);
+        self.emit_cell(k as i64);
+        if let Some(xt) = self.u_builtin_xt(word) {
+            self.emit_cell(xt);
+        }
+    }
+
+    /// u_next_token parses the next space-delimited token from the input into PAD
+    ///
+    fn u_next_token(&mut self) -> Option<String> {
+        let pad = self.kernel.get(self.pad_ptr);
+        self.kernel.push(pad);
+        self.kernel.push(' ' as i64);
+        self.f_parse_to();
+        let length = self.kernel.pop();
+        let addr = self.kernel.pop() as usize;
+        if length == 0 {
+            None
+        } else {
+            Some(self.kernel.string_get(addr))
+        }
+    }
+
     /// CREATE <name> ( -- ) makes a new dictionary entry, using a postfix name
     ///     References HERE, and assumes back pointer is in place already
     ///     create updates the three definition-related pointers: HERE, CONTEXT and LAST
     pub fn f_create(&mut self) {
+        self.u_create();
+    }
+
+    /// u_create does the work of CREATE, returning false if the name was rejected
+    ///
+    fn u_create(&mut self) -> bool {
+        match self.u_create_header() {
+            Some(nfa) => {
+                self.u_index_word(nfa);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// u_create_header lays down a new name header without indexing it, returning its nfa
+    ///
+    fn u_create_header(&mut self) -> Option<usize> {
+        self.u_mark_definition();
         let pad = self.kernel.get(self.pad_ptr);
         self.kernel.push(pad);
         self.kernel.push(' ' as i64);
         self.f_parse_to(); // get the word's name
         self.kernel.pop(); // throw away the length, keep the text pointer
-        self.f_q_unique(); // issue a warning if it's already defined
-        let str_addr = self.kernel.get(self.pad_ptr) as usize; // get the string address
-        let length = self.kernel.string_length(str_addr) as u8 as i64;
-        self.kernel.push(length);
-        let val = self.kernel.get(self.kernel.get_string_ptr());
-        self.kernel.push(val);
-        self.f_smove(); // make a new string with the name from PAD
+        if !self.u_unique() {
+            return None; // a protected builtin
+        }
+        let str_addr = self.kernel.pop() as usize; // get the string address
+        let name = self.kernel.string_get(str_addr);
+        let val = self.kernel.string_intern(&name) as i64; // share the name if it's already stored
         let addr = self.kernel.get(self.here_ptr) as usize; // get the current HERE pointer
-        let val = self.kernel.pop(); // get the string address
         self.kernel.set(addr, val); // the string header
-        self.kernel.delta(self.kernel.get_string_ptr(), length + 1); // update the free string pointer
         let here = self.kernel.get(self.here_ptr) as usize;
         self.kernel.set(self.last_ptr, here as i64); // save the last pointer
         self.kernel.incr(self.here_ptr);
+        Some(here)
+    }
+
+    /// alias <newname> <oldname> ( -- ) gives an existing word a second name
+    ///     The new header holds ALIAS and the old word's cfa, and find returns that cfa, so the
+    ///     two names are the same word: ' and >body match, and immediate words stay immediate.
+    ///     Forgetting the alias forgets from the old word.
+    ///
+    pub fn f_alias(&mut self) {
+        let Some(nfa) = self.u_create_header() else {
+            return;
+        };
+        self.f_tick_p();
+        let cfa = self.kernel.pop();
+        if cfa == FALSE {
+            // undo the new header
+            let last = self.kernel.get(self.last_ptr);
+            self.kernel.set(self.here_ptr, last);
+            let context = self.kernel.get(self.context_ptr);
+            self.kernel.set(self.last_ptr, context);
+            let addr = self.kernel.get(self.pad_ptr) as usize;
+            let msg = self.kernel.string_get(addr);
+            let name = msg
+                .trim()
+                .trim_start_matches("Word not found: ")
+                .to_string();
+            self.msg.error("alias", msg.trim(), None::<bool>);
+            self.set_error(ForthError::NotFound { name });
+            self.f_abort();
+            return;
+        }
+        self.u_index_word(nfa); // only a finished alias can be found
+        self.emit_cell(ALIAS);
+        self.emit_cell(cfa);
+        let back = self.kernel.get(self.last_ptr);
+        let here = self.kernel.get(self.here_ptr) as usize;
+        self.kernel.set(here, back - 1); // write the back pointer
+        self.kernel.incr(self.here_ptr);
+        self.kernel.set(self.context_ptr, back);
     }
 
     /// see <name> ( -- ) prints the definition of a word
@@ -439,99 +1177,395 @@ impl ForthRuntime {
         if cfa == FALSE {
             self.msg.warning("see", "Word not found", None::<bool>);
         } else {
-            let mut nfa = self.kernel.get(cfa as usize - 1) as usize;
-            let is_immed = nfa & IMMEDIATE_FLAG;
-            let xt = self.kernel.get(cfa as usize) as usize;
-            let is_builtin = xt & BUILTIN_FLAG;
-            if is_builtin != 0 {
-                println!(
-                    "Builtin: {}",
-                    self.kernel.get_builtin(xt as usize & !BUILTIN_FLAG).doc
-                );
+            self.u_see_cfa(cfa);
+        }
+    }
+
+    /// help <name> ( -- ) prints a builtin's documentation, or decompiles a definition
+    ///
+    pub fn f_help(&mut self) {
+        self.f_tick_p();
+        let cfa = self.kernel.pop();
+        if cfa == FALSE {
+            let addr = self.kernel.get(self.pad_ptr) as usize;
+            let name = self.kernel.string_get(addr);
+            let _ = writeln!(self.out, "{}", name.trim()); // f_tick_p left "Word not found: <name>" in PAD
+        } else {
+            match self.u_builtin_doc(cfa) {
+                Some(doc) => {
+                    let _ = writeln!(self.out, "{doc}");
+                }
+                None => self.u_see_cfa(cfa),
+            }
+        }
+    }
+
+    /// apropos <text> ( -- ) lists the builtins whose name or documentation contains text
+    ///     The search ignores case.
+    ///
+    pub fn f_apropos(&mut self) {
+        let pad = self.kernel.get(self.pad_ptr);
+        self.kernel.push(pad);
+        self.kernel.push(' ' as i64);
+        self.f_parse_to();
+        let length = self.kernel.pop();
+        let addr = self.kernel.pop() as usize;
+        if length == 0 {
+            self.msg
+                .warning("apropos", "Search text required", None::<bool>);
+            return;
+        }
+        let needle = self.kernel.string_get(addr);
+        for name in self.u_apropos(&needle) {
+            let _ = write!(self.out, "{name} ");
+        }
+        let _ = writeln!(self.out);
+    }
+
+    /// u_apropos returns the names of builtins whose name or doc contains needle, ignoring case
+    ///
+    pub fn u_apropos(&mut self, needle: &str) -> Vec<String> {
+        let needle = needle.to_lowercase();
+        let mut names = Vec::new();
+        for index in 0..=self.kernel.max_builtin() {
+            let builtin = self.kernel.get_builtin(index);
+            if builtin.name.to_lowercase().contains(&needle)
+                || builtin.doc.to_lowercase().contains(&needle)
+            {
+                names.push(builtin.name.clone());
+            }
+        }
+        names
+    }
+
+    /// u_builtin_doc returns the doc string if cfa belongs to a builtin
+    ///
+    pub fn u_builtin_doc(&mut self, cfa: i64) -> Option<String> {
+        let xt = self.kernel.get(cfa as usize) as usize;
+        if xt & BUILTIN_FLAG != 0 {
+            Some(self.kernel.get_builtin(xt & !BUILTIN_FLAG).doc.clone())
+        } else {
+            None
+        }
+    }
+
+    /// see>string ( xt dst -- len ) renders what see would print for xt as a counted string at dst
+    ///     Text longer than 255 bytes, or than the rest of string space, is cut at a character
+    ///     boundary so the count always matches; len is the number of bytes written.
+    ///
+    pub fn f_see_to_string(&mut self) {
+        if self.kernel.stack_check(2, "see>string") {
+            let dst = self.kernel.pop();
+            let xt = self.kernel.pop();
+            if !self.u_string_range("see>string", dst, 1) {
+                return;
+            }
+            let valid = if xt < 0 {
+                false
+            } else if xt as usize & BUILTIN_FLAG != 0 {
+                xt as usize & !BUILTIN_FLAG <= self.kernel.max_builtin()
             } else {
-                // It's a definition of some kind
-                nfa &= ADDRESS_MASK; // get rid of any special bits
-                match xt as i64 {
-                    DEFINITION => {
-                        print!(": ");
-                        let name = self.kernel.string_get(nfa);
-                        print!("{name} ");
-                        let mut index = cfa as usize + 1; // skip the inner interpreter
-                        loop {
-                            let xt = self.kernel.get(index);
-                            // print!("(xt is {}) ", xt);
-                            match xt {
-                                LITERAL => {
-                                    print!("{} ", self.kernel.get(index as usize + 1));
-                                    index += 1;
-                                }
-                                ARRAY => {
-                                    print!("ARRAY pointer ");
-                                    index += 1;
-                                }
-                                STRLIT => {
-                                    // println!("Found STRLIT {}:{}", xt, self.kernel.get(index as usize + 1));
-                                    let s_addr = self.kernel.get(index as usize + 1) as usize;
-                                    print!("\" {}\" ", self.kernel.string_get(s_addr));
-                                    index += 1;
-                                }
-                                BRANCH => {
-                                    print!("branch:{} ", self.kernel.get(index as usize + 1));
-                                    index += 1;
-                                }
-                                BRANCH0 => {
-                                    print!("branch0:{} ", self.kernel.get(index as usize + 1));
-                                    index += 1;
-                                }
-                                ABORT => println!("abort "),
-                                BREAK => print!("exit "),
-                                EXIT => {
-                                    print!("; ");
-                                    if is_immed != 0 {
-                                        println!("immediate");
-                                    } else {
-                                        println!();
-                                    }
-                                    break;
-                                }
-                                EXEC => print!("exec "),
-                                _ => {
-                                    // it's a definition or a builtin
-                                    let mut cfa = self.kernel.get(index) as usize;
-                                    let mut mask = cfa & BUILTIN_FLAG;
-                                    if mask == 0 {
-                                        let addr = self.kernel.get(index) as usize - 1;
-                                        let word = ADDRESS_MASK & self.kernel.get(addr) as usize; // nfa address
-                                        let name = self.kernel.string_get(word);
-                                        print!("{name} ");
-                                    } else {
-                                        mask = !BUILTIN_FLAG;
-                                        cfa &= mask;
-                                        let name = &self.kernel.get_builtin(cfa).name;
-                                        print!("{name} ");
-                                    }
-                                }
-                            }
-                            self.f_flush();
-                            index += 1;
-                        }
+                self.kernel.addr_check(xt as usize)
+            };
+            if !valid {
+                self.msg
+                    .error("see>string", "Not an execution token", Some(xt));
+                self.set_error(ForthError::BadXt);
+                self.f_abort();
+                return;
+            }
+            match self.u_see_text(xt) {
+                Some(text) => {
+                    let dst = dst as usize;
+                    let mut len = min(text.len(), min(u8::MAX as usize, STRING_SIZE - dst - 1));
+                    while !text.is_char_boundary(len) {
+                        len -= 1;
                     }
-                    CONSTANT => {
-                        let addr = self.kernel.get(cfa as usize - 1) as usize;
-                        println!("Constant: {} = {}", addr, self.kernel.get(cfa as usize + 1),);
+                    self.kernel.byte_set(dst, len as u8);
+                    for (i, &b) in text.as_bytes()[..len].iter().enumerate() {
+                        self.kernel.byte_set(dst + 1 + i, b);
                     }
-                    VARIABLE => {
-                        let addr = self.kernel.get(cfa as usize - 1) as usize;
-                        println!(
-                            "Variable: {} = {}",
-                            self.kernel.string_get(addr),
-                            self.kernel.get(cfa as usize + 1),
-                        )
+                    self.kernel.push(len as i64);
+                }
+                None => {
+                    self.set_error(ForthError::BadXt);
+                    self.f_abort();
+                }
+            }
+        }
+    }
+
+    /// u_see_cfa prints the decompilation of the word at cfa
+    ///
+    fn u_see_cfa(&mut self, cfa: i64) {
+        if let Some(text) = self.u_see_text(cfa) {
+            let _ = writeln!(self.out, "{text}");
+        }
+    }
+
+    /// u_see_text decompiles the word at cfa, or the builtin referenced by a flagged xt
+    ///     Reports an error and returns None if cfa is not a recognized kind of word.
+    ///
+    pub fn u_see_text(&mut self, cfa: i64) -> Option<String> {
+        let xt = if cfa as usize & BUILTIN_FLAG != 0 {
+            cfa as usize
+        } else {
+            self.kernel.get(cfa as usize) as usize
+        };
+        if xt & BUILTIN_FLAG != 0 {
+            return Some(format!(
+                "Builtin: {}",
+                self.kernel.get_builtin(xt & !BUILTIN_FLAG).doc
+            ));
+        }
+        // It's a definition of some kind
+        let text = match xt as i64 {
+            DEFINITION => self.u_decompile(cfa as usize),
+            CONSTANT => {
+                let addr = self.kernel.get(cfa as usize - 1) as usize;
+                format!(
+                    "Constant: {} = {}",
+                    self.kernel.string_get(addr & ADDRESS_MASK),
+                    self.kernel.get(cfa as usize + 1),
+                )
+            }
+            DEFER => {
+                let addr = self.kernel.get(cfa as usize - 1) as usize;
+                let xt = self.kernel.get(cfa as usize + 1);
+                let target = if xt == 0 {
+                    "nothing".to_string()
+                } else {
+                    self.u_word_name(xt)
+                };
+                format!(
+                    "Deferred: {} is {}",
+                    self.kernel.string_get(addr & ADDRESS_MASK),
+                    target
+                )
+            }
+            VARIABLE => {
+                let addr = self.kernel.get(cfa as usize - 1) as usize;
+                format!(
+                    "Variable: {} = {}",
+                    self.kernel.string_get(addr),
+                    self.kernel.get(cfa as usize + 1),
+                )
+            }
+            DOES => self.u_decompile_does(cfa as usize),
+            ARRAY => {
+                let addr = self.kernel.get(cfa as usize - 1) as usize;
+                format!(
+                    "Array: {} of {} cells",
+                    self.kernel.string_get(addr & ADDRESS_MASK),
+                    self.kernel.get(cfa as usize + 1),
+                )
+            }
+            _ => {
+                self.msg.error("see", "Unrecognized type", None::<bool>);
+                return None;
+            }
+        };
+        Some(text)
+    }
+
+    /// u_decompile renders a colon definition as source text
+    ///     Branches are matched up to reconstruct if/else/then, begin/until, begin/again,
+    ///     begin/while/repeat and for/next. Anything unrecognized is shown as a raw branch.
+    ///
+    pub fn u_decompile(&mut self, cfa: usize) -> String {
+        let nfa = self.kernel.get(cfa - 1) as usize;
+        let name = self.kernel.string_get(nfa & ADDRESS_MASK);
+        format!(": {} {}", name, self.u_decompile_code(cfa + 1, nfa))
+    }
+
+    /// u_decompile_does renders a word made by a defining word with does>: its data, then its code
+    ///
+    pub fn u_decompile_does(&mut self, cfa: usize) -> String {
+        let nfa = self.kernel.get(cfa - 1) as usize;
+        let name = self.kernel.string_get(nfa & ADDRESS_MASK);
+        let data: Vec<String> = (cfa + 2..self.u_body_end(cfa - 1))
+            .map(|addr| self.kernel.get(addr).to_string())
+            .collect();
+        let code = self.kernel.get(cfa + 1) as usize;
+        format!(
+            "Created: {} data: {} does> {}",
+            name,
+            data.join(" "),
+            self.u_decompile_code(code, nfa)
+        )
+    }
+
+    /// u_body_end returns the address of the back pointer that ends the word at nfa
+    ///     That's the link field of the next word, or the cell below HERE for the latest word.
+    ///
+    fn u_body_end(&mut self, nfa: usize) -> usize {
+        let mut end = self.here() - 1;
+        let mut link = self.kernel.get(self.context_ptr) as usize - 1;
+        while link > nfa {
+            end = link;
+            link = self.kernel.get(link) as usize;
+        }
+        end
+    }
+
+    /// u_decompile_code renders compiled code from start up to its final EXIT
+    ///
+    fn u_decompile_code(&mut self, start: usize, nfa: usize) -> String {
+        let mut text = String::new();
+
+        // Collect the start of each token, skipping over inline arguments
+        // LITERAL <address> (does>) EXIT is does>, which the rest of the definition follows
+        let does_p = self.u_builtin_xt("(does>)");
+        let mut does = Vec::new();
+        let mut tokens = Vec::new();
+        let mut index = start;
+        while index < DATA_SIZE {
+            tokens.push(index);
+            match self.kernel.get(index) {
+                LITERAL | STRLIT | BRANCH | BRANCH0 | ARRAY => index += 2,
+                EXIT if tokens.len() >= 3
+                    && tokens[tokens.len() - 3] == index - 3
+                    && self.kernel.get(index - 3) == LITERAL
+                    && Some(self.kernel.get(index - 1)) == does_p =>
+                {
+                    does.push(index - 3);
+                    index += 1;
+                }
+                EXIT => break,
+                _ => index += 1,
+            }
+        }
+        let is_token = |addr: usize| tokens.binary_search(&addr).is_ok();
+        let target =
+            |kernel: &mut Kernel, addr: usize| (addr as i64 + 1 + kernel.get(addr + 1)) as usize;
+
+        // Work out the structure words. Closers print before openers at the same address.
+        let mut replace: HashMap<usize, &str> = HashMap::new();
+        let mut openers: HashMap<usize, Vec<&str>> = HashMap::new();
+        let mut closers: HashMap<usize, Vec<&str>> = HashMap::new();
+        for &addr in &does {
+            replace.insert(addr, "does>");
+            replace.insert(addr + 2, "");
+            replace.insert(addr + 3, "");
+        }
+        let for_p = self.u_builtin_xt("(for)");
+        let next_p = self.u_builtin_xt("(next)");
+        for &addr in tokens.iter().rev() {
+            if self.kernel.get(addr) != BRANCH0 {
+                continue;
+            }
+            let dest = target(&mut self.kernel, addr);
+            if dest > addr {
+                let before = dest - 2;
+                if before > addr && is_token(before) && self.kernel.get(before) == BRANCH {
+                    let end = target(&mut self.kernel, before);
+                    if end > before {
+                        replace.insert(addr, "if");
+                        replace.insert(before, "else");
+                        closers.entry(end).or_default().push("then");
+                    } else if self.while_branches.contains(&addr) {
+                        replace.insert(addr, "while");
+                        replace.insert(before, "repeat");
+                        openers.entry(end).or_default().push("begin");
+                    } else {
+                        // begin ... if ... again then, which leaves the again for the loop below
+                        replace.insert(addr, "if");
+                        closers.entry(dest).or_default().push("then");
                     }
-                    _ => self.msg.error("see", "Unrecognized type", None::<bool>),
+                } else {
+                    replace.insert(addr, "if");
+                    closers.entry(dest).or_default().push("then");
+                }
+            } else {
+                // for ... next compiles to (for) ... (next) branch0
+                let is_next = is_token(dest - 1)
+                    && Some(self.kernel.get(dest - 1)) == for_p
+                    && is_token(addr - 1)
+                    && Some(self.kernel.get(addr - 1)) == next_p;
+                if is_next {
+                    replace.insert(dest - 1, "for");
+                    replace.insert(addr - 1, "next");
+                    replace.insert(addr, "");
+                } else {
+                    replace.insert(addr, "until");
+                    openers.entry(dest).or_default().push("begin");
+                }
+            }
+        }
+        for &addr in tokens.iter().rev() {
+            if self.kernel.get(addr) == BRANCH && !replace.contains_key(&addr) {
+                let dest = target(&mut self.kernel, addr);
+                if dest <= addr {
+                    replace.insert(addr, "again");
+                    openers.entry(dest).or_default().push("begin");
                 }
             }
         }
+
+        for &addr in &tokens {
+            for word in closers.get(&addr).into_iter().flatten() {
+                text.push_str(word);
+                text.push(' ');
+            }
+            for word in openers.get(&addr).into_iter().flatten().rev() {
+                text.push_str(word);
+                text.push(' ');
+            }
+            if let Some(word) = replace.get(&addr) {
+                if !word.is_empty() {
+                    text.push_str(word);
+                    text.push(' ');
+                }
+                continue;
+            }
+            let xt = self.kernel.get(addr);
+            let word = match xt {
+                LITERAL => self.kernel.get(addr + 1).to_string(),
+                ARRAY => "ARRAY pointer".to_string(),
+                STRLIT => {
+                    let s_addr = self.kernel.get(addr + 1) as usize;
+                    format!("\" {}\"", self.kernel.string_get(s_addr))
+                }
+                BRANCH => format!("branch:{}", self.kernel.get(addr + 1)),
+                BRANCH0 => format!("branch0:{}", self.kernel.get(addr + 1)),
+                ABORT => "abort".to_string(),
+                BREAK => "exit".to_string(),
+                EXEC => "exec".to_string(),
+                EXIT => {
+                    if nfa & IMMEDIATE_FLAG != 0 {
+                        "; immediate".to_string()
+                    } else if nfa & INLINE_FLAG != 0 {
+                        "; inline".to_string()
+                    } else {
+                        ";".to_string()
+                    }
+                }
+                _ => self.u_word_name(xt),
+            };
+            text.push_str(&word);
+            text.push(' ');
+        }
+        text.trim_end().to_string()
+    }
+
+    /// u_word_name returns the name of a builtin or defined word given its compiled reference
+    ///
+    fn u_word_name(&mut self, xt: i64) -> String {
+        let xt = xt as usize;
+        if xt & BUILTIN_FLAG != 0 {
+            self.kernel.get_builtin(xt & !BUILTIN_FLAG).name.clone()
+        } else {
+            let nfa = ADDRESS_MASK & self.kernel.get(xt - 1) as usize;
+            self.kernel.string_get(nfa)
+        }
+    }
+
+    /// u_builtin_xt returns the compiled reference for a named builtin, if there is one
+    ///
+    pub fn u_builtin_xt(&mut self, name: &str) -> Option<i64> {
+        (0..=self.kernel.max_builtin())
+            .find(|&i| self.kernel.get_builtin(i).name == name)
+            .map(|i| (i | BUILTIN_FLAG) as i64)
     }
 
     /*  fn f_d_pack(&mut self) {
@@ -564,7 +1598,747 @@ impl ForthRuntime {
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use crate::internals::files::FileHandle;
+    use crate::internals::messages::{DebugLevel, ForthError};
+    use crate::runtime::{ForthRuntime, CONSTANT, FALSE, MAX_LOCALS};
+
+    #[test]
+    fn test_help_builtin_doc() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.eval_str("(') +");
+        let cfa = rt.kernel.pop();
+        assert_eq!(
+            rt.u_builtin_doc(cfa),
+            Some(rt.kernel.get_builtin(0).doc.clone())
+        );
+        rt.eval_str(": sq dup * ;");
+        rt.eval_str("(') sq");
+        let cfa = rt.kernel.pop();
+        assert_eq!(rt.u_builtin_doc(cfa), None);
+        let doc = rt.kernel.get_builtin(0).doc.clone();
+        assert_eq!(
+            rt.with_captured_output(|rt| rt.eval_str("help +")),
+            format!("{doc}\n")
+        );
+        assert_eq!(
+            rt.with_captured_output(|rt| rt.eval_str("help sq")),
+            ": sq dup * ;\n"
+        );
+        let output = rt.with_captured_output(|rt| rt.eval_str("help no-such-word"));
+        assert!(output.contains("no-such-word"), "{output}");
+        assert_eq!(rt.kernel.stack_len(), 0);
+    }
+
+    #[test]
+    fn test_apropos() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        let found = rt.u_apropos("FILE");
+        for name in ["open-file", "close-file", "read-line"] {
+            assert!(found.contains(&name.to_string()), "missing {name}");
+        }
+        assert!(!found.contains(&"+".to_string()));
+        assert!(rt.u_apropos("no such text anywhere").is_empty());
+        rt.eval_str("apropos file");
+        assert_eq!(rt.kernel.stack_len(), 0);
+    }
+
+    fn decompile(rt: &mut ForthRuntime, source: &str, name: &str) -> String {
+        rt.eval_str(source);
+        rt.eval_str(&format!("(') {name}"));
+        let cfa = rt.kernel.pop() as usize;
+        rt.u_decompile(cfa)
+    }
+
+    #[test]
+    fn test_continuation_prompt() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        assert_eq!(rt.u_line_prompt(), "ok> ");
+        rt.eval_str(": sum-squares ( a b -- n )");
+        assert_eq!(rt.u_line_prompt(), "... ");
+        rt.eval_str("    dup * swap");
+        assert_eq!(rt.u_line_prompt(), "... ");
+        rt.eval_str("    dup * + ;");
+        assert_eq!(rt.u_line_prompt(), "ok> ");
+        rt.eval_str("3 4 sum-squares");
+        assert_eq!(stack_values(&mut rt), vec![25]);
+        // an error part way through a definition goes back to the ordinary prompt
+        rt.eval_str(": broken no-such-word");
+        assert!(rt.get_abort_flag());
+        assert_eq!(rt.u_line_prompt(), "ok> ");
+    }
+
+    #[test]
+    fn test_inline() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        assert_eq!(
+            decompile(&mut rt, ": square dup * ; inline", "square"),
+            ": square dup * ; inline"
+        );
+        assert_eq!(
+            decompile(&mut rt, ": cube dup square * ;", "cube"),
+            ": cube dup dup * * ;"
+        );
+        rt.eval_str("3 cube 4 square");
+        assert_eq!(stack_values(&mut rt), vec![27, 16]);
+        // words with control flow or calls to other definitions are still called
+        rt.eval_str(": sign 0< if -1 then ; inline");
+        assert!(rt.get_abort_flag());
+        rt.eval_str(": ninth cube cube ; inline");
+        assert!(rt.get_abort_flag());
+        assert_eq!(decompile(&mut rt, ": n9 ninth ;", "n9"), ": n9 ninth ;");
+        // inlined words are leaves themselves, so they can be inlined in turn
+        rt.eval_str(": fourth square square ; inline");
+        assert_eq!(
+            decompile(&mut rt, ": f4 fourth ;", "f4"),
+            ": f4 dup * dup * ;"
+        );
+        // words that work on the return stack keep their own frame
+        for body in [
+            "r> drop",
+            ">r r>",
+            "r@ drop",
+            "2>r 2r>",
+            "2r@ 2drop",
+            "1 0 (do) unloop",
+        ] {
+            rt.eval_str(&format!(": rs {body} ; inline"));
+            assert!(rt.get_abort_flag(), "{body}");
+            rt.set_abort_flag(false);
+            rt.f_clear();
+        }
+        rt.eval_str(": with-locals { a -- } a ; inline");
+        assert!(rt.get_abort_flag());
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        let start = rt.here();
+        rt.eval_str(": t optimize-off 2 3 + ;");
+        let plain = rt.here() - start;
+        let start = rt.here();
+        rt.eval_str(": u optimize-on 2 3 + ;");
+        let folded = rt.here() - start;
+        assert!(folded < plain);
+        rt.eval_str("t u");
+        assert_eq!(stack_values(&mut rt), vec![5, 5]);
+        assert_eq!(decompile(&mut rt, ": v 2 3 + 4 * 1 - ;", "v"), ": v 19 ;");
+        // only literals that are next to each other are folded
+        assert_eq!(
+            decompile(&mut rt, ": w 2 swap 3 + ;", "w"),
+            ": w 2 swap 3 + ;"
+        );
+        // 3 and 4 are adjacent in the code, but `then` lands between them
+        assert_eq!(
+            decompile(&mut rt, ": x if 2 else 3 then 4 + ;", "x"),
+            ": x if 2 else 3 then 4 + ;"
+        );
+        rt.eval_str("0 x 1 x");
+        assert_eq!(stack_values(&mut rt), vec![7, 6]);
+        // an overflowing expression or an out of range shift is left as written
+        assert_eq!(
+            decompile(&mut rt, ": big 9223372036854775807 1 + ;", "big"),
+            ": big 9223372036854775807 1 + ;"
+        );
+        assert_eq!(
+            decompile(&mut rt, ": sh 1 64 lshift ;", "sh"),
+            ": sh 1 64 lshift ;"
+        );
+        assert_eq!(
+            decompile(&mut rt, ": sh2 1 4 lshift ;", "sh2"),
+            ": sh2 16 ;"
+        );
+        // no-fold opts one definition out, and the next one folds again
+        assert_eq!(
+            decompile(&mut rt, ": nf no-fold 2 3 + ;", "nf"),
+            ": nf 2 3 + ;"
+        );
+        assert_eq!(decompile(&mut rt, ": f2 2 3 + ;", "f2"), ": f2 5 ;");
+        rt.eval_str("optimize-off");
+        assert_eq!(decompile(&mut rt, ": y 2 3 + ;", "y"), ": y 2 3 + ;");
+    }
+
+    #[test]
+    fn test_see_control_structures() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        assert_eq!(
+            decompile(&mut rt, ": t dup 0= if drop 1 then ;", "t"),
+            ": t dup 0= if drop 1 then ;"
+        );
+        assert_eq!(
+            decompile(&mut rt, ": t2 if 1 else 2 then 3 ;", "t2"),
+            ": t2 if 1 else 2 then 3 ;"
+        );
+        assert_eq!(
+            decompile(&mut rt, ": t3 begin 1 - dup 0= until ;", "t3"),
+            ": t3 begin 1 - dup 0= until ;"
+        );
+        assert_eq!(
+            decompile(&mut rt, ": t4 begin dup while 1 - repeat ;", "t4"),
+            ": t4 begin dup while 1 - repeat ;"
+        );
+        assert_eq!(
+            decompile(&mut rt, ": t5 begin dup if drop then again ;", "t5"),
+            ": t5 begin dup if drop then again ;"
+        );
+        // compiles to the same code as t4, but is shown as written
+        assert_eq!(
+            decompile(&mut rt, ": t7 begin dup if 1 - again then ;", "t7"),
+            ": t7 begin dup if 1 - again then ;"
+        );
+        // forgetting a while loop forgets its mark, so a word compiled in its place isn't confused
+        rt.eval_str(": t8 begin dup while 1 - repeat ; forget t8");
+        assert_eq!(
+            decompile(&mut rt, ": t8 begin dup if 1 - again then ;", "t8"),
+            ": t8 begin dup if 1 - again then ;"
+        );
+        assert_eq!(
+            decompile(&mut rt, ": t6 4 for 1 next ;", "t6"),
+            ": t6 4 for 1 next ;"
+        );
+    }
+
+    #[test]
+    fn test_see_to_string() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str(": t dup 0= if drop 1 then ;  ' t 8000 see>string");
+        let text = ": t dup 0= if drop 1 then ;";
+        assert_eq!(stack_values(&mut rt), vec![text.len() as i64]);
+        assert_eq!(rt.kernel.string_get(8000), text);
+        // see prints the same text
+        assert_eq!(
+            rt.with_captured_output(|rt| rt.eval_str("see t")),
+            format!("{text}\n")
+        );
+        rt.eval_str("42 constant answer  ' answer 8000 see>string");
+        rt.f_drop();
+        assert_eq!(rt.kernel.string_get(8000), "Constant: answer = 42");
+        rt.eval_str("' dup 8000 see>string");
+        rt.f_drop();
+        assert!(rt.kernel.string_get(8000).starts_with("Builtin: "));
+        rt.eval_str("-1 8000 see>string");
+        assert!(rt.get_abort_flag());
+        // a long definition is cut to fit the count byte, without splitting a character
+        let line = format!(".\" {}\"", "é".repeat(20));
+        let source = format!(": long\n{}\n;", vec![line; 8].join("\n"));
+        rt.eval_str(&source);
+        let full = rt.with_captured_output(|rt| rt.eval_str("see long"));
+        assert!(!full.is_char_boundary(255));
+        rt.eval_str("' long 8000 see>string");
+        assert_eq!(stack_values(&mut rt), vec![254]);
+        assert_eq!(rt.kernel.byte_get(8000), 254);
+        assert_eq!(rt.kernel.string_get(8000), full[..254]);
+    }
+
+    #[test]
+    fn test_see_does() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        assert_eq!(
+            decompile(&mut rt, ": const create , does> @ ;", "const"),
+            ": const create , does> @ ;"
+        );
+        rt.eval_str("5 const five  : later 1 ;  five ' five execute");
+        assert_eq!(rt.kernel.pop(), 5);
+        assert_eq!(rt.kernel.pop(), 5);
+        rt.eval_str("(') five");
+        let cfa = rt.kernel.pop() as usize;
+        assert_eq!(rt.u_decompile_does(cfa), "Created: five data: 5 does> @ ;");
+    }
+
+    #[test]
+    fn test_does_moves_data() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        // here is taken before does> moves the data, so the stored address is two cells short
+        rt.eval_str(": self-ref create here @ , does> dup @ ;  self-ref me  me");
+        let stored = rt.kernel.pop();
+        let data = rt.kernel.pop();
+        assert_eq!(stored, data - 2);
+        // data laid down by the creating part moves with it, so it is still found through the body
+        rt.eval_str(": pair create , , does> dup @ swap 1 + @ ;  1 2 pair p  p");
+        assert_eq!(stack_values(&mut rt), vec![2, 1]);
+    }
+
+    #[test]
+    fn test_execute_inside_definition() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        // the called word's exit must not discard the callers' return addresses
+        rt.eval_str(": sq dup * ;  : t ['] sq execute 1 + ;  : u t 2 * ;  3 u");
+        assert_eq!(stack_values(&mut rt), vec![20]);
+        assert!(!rt.get_abort_flag());
+    }
+
+    #[test]
+    fn test_postpone() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        // an immediate word runs when the compiling word is used
+        rt.eval_str(": unless postpone 0= postpone if ; immediate");
+        rt.eval_str(": t unless 1 else 2 then ;  0 t 5 t");
+        assert_eq!(stack_values(&mut rt), vec![1, 2]);
+        // a normal word is compiled into the word being built
+        rt.eval_str(": squared postpone dup postpone * ; immediate");
+        rt.eval_str(": t2 squared 1 + ;  3 t2");
+        assert_eq!(rt.kernel.pop(), 10);
+        rt.eval_str(": t3 postpone no-such-word ;");
+        assert!(rt.get_abort_flag());
+    }
+
+    #[test]
+    fn test_compile_time_literal() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        assert_eq!(
+            decompile(&mut rt, ": t [ 2 3 + ] literal ;", "t"),
+            ": t 5 ;"
+        );
+        rt.eval_str("t");
+        assert_eq!(stack_values(&mut rt), vec![5]);
+        assert!(!rt.get_compile_mode());
+    }
+
+    #[test]
+    fn test_dictionary_navigation() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str(": sq dup * ; variable v 42 v !");
+        rt.eval_str("' sq dup >name name>");
+        assert_eq!(rt.kernel.pop(), rt.kernel.pop());
+        rt.eval_str("' sq >name");
+        let nfa = rt.kernel.pop() as usize;
+        let name_addr = rt.kernel.get(nfa) as usize;
+        assert_eq!(rt.kernel.string_get(name_addr), "sq");
+        rt.eval_str("(') v >body @ v @");
+        assert_eq!(rt.kernel.pop(), 42);
+        assert_eq!(rt.kernel.pop(), 42);
+    }
+
+    #[test]
+    fn test_char_literals() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str("char A");
+        assert_eq!(rt.kernel.pop(), 65);
+        rt.eval_str("char hello");
+        assert_eq!(rt.kernel.pop(), 'h' as i64);
+        rt.eval_str(": t [char] Z ; t");
+        assert_eq!(rt.kernel.pop(), 90);
+        assert_eq!(rt.kernel.stack_len(), 0);
+    }
+
+    #[test]
+    fn test_word() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.eval_str("32 word    hello world");
+        let addr = rt.kernel.pop() as usize;
+        assert_eq!(rt.kernel.string_get(addr), "hello");
+        assert_eq!(rt.kernel.stack_len(), 0);
+        rt.eval_str("44 word one,two");
+        let addr = rt.kernel.pop() as usize;
+        assert_eq!(rt.kernel.string_get(addr), "one");
+        rt.eval_str("32 word");
+        let addr = rt.kernel.pop() as usize;
+        assert_eq!(rt.kernel.string_get(addr), "");
+    }
+
+    #[test]
+    fn test_bl_constant() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str("bl");
+        assert_eq!(rt.kernel.pop(), 32);
+        rt.eval_str("(') bl");
+        let cfa = rt.kernel.pop() as usize;
+        assert_eq!(rt.kernel.get(cfa), CONSTANT);
+        rt.eval_str("see bl");
+        assert_eq!(rt.kernel.stack_len(), 0);
+    }
+
+    #[test]
+    fn test_defer_is() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str("defer greet : hi 42 ; : twice greet greet ;");
+        rt.eval_str("greet");
+        assert!(rt.get_abort_flag()); // not set yet
+        rt.eval_str("' hi is greet");
+        rt.eval_str("greet");
+        assert_eq!(rt.kernel.pop(), 42);
+        rt.eval_str("twice");
+        assert_eq!(rt.kernel.pop(), 42);
+        assert_eq!(rt.kernel.pop(), 42);
+        rt.eval_str("' dup is greet 7 twice");
+        assert_eq!(rt.kernel.stack_len(), 3);
+        rt.eval_str("' greet execute");
+        assert_eq!(rt.kernel.stack_len(), 4);
+        rt.eval_str("see greet");
+    }
+
+    #[test]
+    fn test_alias() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str("alias squared dup 3 squared");
+        assert_eq!(rt.kernel.pop(), 3);
+        assert_eq!(rt.kernel.pop(), 3);
+        rt.eval_str(": sq dup * ; alias square sq 5 square : t square 1+ ; 2 t");
+        assert_eq!(rt.kernel.pop(), 5);
+        assert_eq!(rt.kernel.pop(), 25);
+        rt.eval_str("alias fi then : t2 1 if 7 fi ; t2");
+        assert_eq!(rt.kernel.pop(), 7);
+        // both names are the same word
+        rt.eval_str("' squared ' dup  ' square ' sq  ' square >body ' sq >body");
+        let xts = stack_values(&mut rt);
+        assert_eq!(xts[0], xts[1]);
+        assert_eq!(xts[2], xts[3]);
+        assert_eq!(xts[4], xts[5]);
+        // a missing word aborts, clearing the stack
+        rt.eval_str("1 2 alias nothing no-such-word");
+        assert_eq!(rt.kernel.stack_len(), 0);
+        assert!(rt.get_abort_flag());
+        assert_eq!(
+            rt.error,
+            Some(ForthError::NotFound {
+                name: "no-such-word".to_string()
+            })
+        );
+        assert!(!rt.word_index.contains_key("nothing"));
+        assert!(rt.word_index.contains_key("square"));
+    }
+
+    #[test]
+    fn test_case() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str(": t case 1 of 10 endof 2 of 20 endof 3 of 30 endof dup 100 + swap endcase ;");
+        for (n, expected) in [(1, 10), (2, 20), (3, 30), (7, 107)] {
+            rt.kernel.push(n);
+            rt.eval_str("t");
+            assert_eq!(rt.kernel.stack_len(), 1, "case {n} left the wrong depth");
+            assert_eq!(rt.kernel.pop(), expected, "case {n}");
+        }
+        rt.eval_str(": t2 case 5 of 55 endof endcase ; 4 t2 5 t2");
+        assert_eq!(rt.kernel.pop(), 55);
+        assert_eq!(rt.kernel.stack_len(), 0);
+        assert!(rt.control_stack.is_empty());
+    }
+
+    #[test]
+    fn test_double_variables() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str("3 4 2constant point point");
+        assert_eq!(rt.kernel.pop(), 4);
+        assert_eq!(rt.kernel.pop(), 3);
+        rt.eval_str("2variable pair pair 2@");
+        assert_eq!(rt.kernel.pop(), 0);
+        assert_eq!(rt.kernel.pop(), 0);
+        rt.eval_str("point pair 2! pair 2@ pair @");
+        assert_eq!(rt.kernel.pop(), 4);
+        assert_eq!(rt.kernel.pop(), 4);
+        assert_eq!(rt.kernel.pop(), 3);
+        assert_eq!(rt.kernel.stack_len(), 0);
+    }
+
+    #[test]
+    fn test_locals() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str(": avg { a b -- m } a b + 2 / ;  10 20 avg");
+        assert_eq!(rt.kernel.pop(), 15);
+        // order of arguments, uninitialized locals and to
+        rt.eval_str(": t { a b | c -- } a b - to c c c * ;  7 3 t");
+        assert_eq!(rt.kernel.pop(), 16);
+        // each call gets its own frame, including recursive ones and early exits
+        rt.eval_str(": fact { n -- n! } n 2 < if 1 exit then n 1 - recurse n * ;  5 fact");
+        assert_eq!(rt.kernel.pop(), 120);
+        assert!(rt.local_frames.is_empty() && rt.locals.is_empty());
+        // a local hides a word with the same name, but only inside its definition
+        rt.eval_str(": t2 { dup } dup dup + ;  4 t2 5 dup");
+        assert_eq!(stack_values(&mut rt), vec![8, 5, 5]);
+        assert_eq!(rt.kernel.stack_len(), 0);
+        // a frame that takes more than it holds, or is negative or huge, aborts
+        for frame in [
+            "1 2 3 2 (locals)",
+            "-1 2 (locals)",
+            "0 -1 (locals)",
+            "0 1000000 (locals)",
+        ] {
+            rt.eval_str(frame);
+            assert!(rt.get_abort_flag(), "{frame}");
+            assert!(rt.locals.is_empty(), "{frame}");
+        }
+        let names: Vec<String> = (0..=MAX_LOCALS)
+            .map(|i| format!("{}{}", (b'a' + (i % 26) as u8) as char, i / 26))
+            .collect();
+        rt.eval_str(&format!(": many {{ {} }} ;", names.join(" ")));
+        assert!(rt.get_abort_flag());
+    }
+
+    fn stack_values(rt: &mut ForthRuntime) -> Vec<i64> {
+        let mut values: Vec<i64> = (0..rt.kernel.stack_len())
+            .map(|_| rt.kernel.pop())
+            .collect();
+        values.reverse();
+        values
+    }
+
+    #[test]
+    fn test_array() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str("10 array a  42 3 a !  3 a @  ' a >body @");
+        assert_eq!(stack_values(&mut rt), vec![42, 10]);
+        rt.eval_str(": third 3 a @ ; third  9 a 0 a -");
+        assert_eq!(stack_values(&mut rt), vec![42, 9]);
+        rt.eval_str("7 4 a a!  4 a a@");
+        assert_eq!(stack_values(&mut rt), vec![7]);
+        rt.eval_str("10 a");
+        assert!(rt.get_abort_flag());
+        assert_eq!(rt.kernel.stack_len(), 0);
+        rt.set_abort_flag(false);
+        rt.eval_str(": bad -1 a ; bad");
+        assert!(rt.get_abort_flag());
+        assert_eq!(rt.kernel.stack_len(), 0);
+    }
+
+    #[test]
+    fn test_s_quote() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str("s\" hello world\"");
+        let len = rt.kernel.pop() as usize;
+        let addr = rt.kernel.pop() as usize;
+        assert_eq!(rt.kernel.string_slice(addr, len), b"hello world");
+        rt.eval_str(": t .\" compiled\" ; t .\" interpreted\"");
+        assert_eq!(rt.kernel.stack_len(), 0);
+        assert!(!rt.get_abort_flag());
+    }
+
+    #[test]
+    fn test_find_latest_definition() {
+        // without the core, the heap has room for 1000 empty definitions
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        for i in 0..1000 {
+            rt.eval_str(&format!(": w{i} ;"));
+        }
+        assert!(!rt.get_abort_flag());
+        rt.eval_str(": w1 1 ; : w500 500 ; : w500 -500 ;  w1 w500 w999");
+        assert_eq!(stack_values(&mut rt), vec![1, -500]);
+        // a word can call the definition it replaces
+        rt.eval_str(": w1 w1 10 + ;  w1");
+        assert_eq!(rt.kernel.pop(), 11);
+        rt.eval_str("(') w0 (') no-such-word");
+        let found = stack_values(&mut rt);
+        assert_ne!(found[0], FALSE);
+        assert_eq!(found[1], FALSE);
+        // a stale index falls back on walking the dictionary
+        rt.word_index.clear();
+        rt.eval_str("(') w0 (') w1 w1");
+        let stale = stack_values(&mut rt);
+        assert_eq!(stale[0], found[0]);
+        assert_ne!(stale[1], FALSE);
+        assert_eq!(stale[2], 11);
+        rt.eval_str("(') no-such-word");
+        assert_eq!(rt.kernel.pop(), FALSE);
+    }
+
+    #[test]
+    fn test_find_after_forget() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str(": w0 0 ; : w1 1 ; : w1 2 ; : w2 3 ;");
+        // forgotten words are not found, even when their space is reused
+        rt.eval_str("forget w2 forget w1  w1  : fresh 7 ;  fresh  w1");
+        assert_eq!(stack_values(&mut rt), vec![1, 7, 1]);
+        rt.eval_str("forget w0  (') w1 (') fresh");
+        assert_eq!(stack_values(&mut rt), vec![FALSE, FALSE]);
+        rt.eval_str("(') here");
+        assert_ne!(rt.kernel.pop(), FALSE);
+    }
+
+    #[test]
+    fn test_find_word_immediacy() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str(": im 1 ; immediate  : plain 2 ;");
+        for (name, sign) in [("im", 1), ("plain", -1), ("dup", -1), ("if", 1)] {
+            rt.kernel.string_save(name, 8000);
+            rt.kernel.push(8000);
+            rt.f_find_word();
+            assert_eq!(rt.kernel.pop(), sign, "{name}");
+            rt.kernel.pop();
+        }
+        rt.kernel.string_save("missing", 8000);
+        rt.kernel.push(8000);
+        rt.f_find_word();
+        assert_eq!(stack_values(&mut rt), vec![8000, 0]);
+    }
+
+    #[test]
+    fn test_redefinition_shares_name() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str(": twice 2 * ;");
+        let free = rt.kernel.get(rt.kernel.get_string_ptr());
+        rt.eval_str(": twice 2 * ;  5 twice");
+        assert_eq!(rt.kernel.pop(), 10);
+        assert_eq!(rt.kernel.get(rt.kernel.get_string_ptr()), free);
+        // forgetting the redefinition keeps the name the older word still uses
+        rt.eval_str(": thrice 3 * ; : twice 22 ;  forget twice  5 twice 5 thrice");
+        assert_eq!(stack_values(&mut rt), vec![10, 15]);
+        rt.eval_str("forget twice  : other 1 ;  5 twice other");
+        assert_eq!(stack_values(&mut rt), vec![10, 1]);
+    }
+
+    #[test]
+    fn test_allot_stops_at_stack() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        let room = rt.kernel.get_stack_ptr() as i64 - rt.here() as i64;
+        rt.eval_str(&format!("{room} allot"));
+        assert!(rt.get_abort_flag());
+        rt.eval_str(&format!("{} allot  -1 allot  1 , 2 ,", room - 9));
+        assert!(!rt.get_abort_flag());
+        let here = rt.here();
+        assert_eq!(rt.kernel.get(here - 1), 2);
+        rt.eval_str("8 allot");
+        assert!(rt.get_abort_flag());
+    }
+
+    #[test]
+    fn test_push_into_dictionary() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str(": fill 0 begin dup 1 + again ;");
+        let room = rt.kernel.get_stack_ptr() as i64 - rt.here() as i64;
+        rt.eval_str(&format!("{} allot", room - 4));
+        let here = rt.here();
+        rt.eval_str("1 2 3 4 5");
+        assert!(rt.get_abort_flag());
+        assert_eq!(rt.error, Some(ForthError::StackOverflow));
+        assert_eq!(rt.kernel.stack_len(), 0);
+        assert_eq!(rt.here(), here);
+        // a builtin that pushes onto the full stack is caught too
+        rt.eval_str("fill");
+        assert!(rt.get_abort_flag());
+        assert_eq!(rt.error, Some(ForthError::StackOverflow));
+        // a negative allot gives the space back, but not below the current word
+        rt.eval_str(&format!("{} allot", 4 - room));
+        assert!(!rt.get_abort_flag());
+        rt.eval_str("create buf 10 allot -10 allot -1 allot");
+        assert!(rt.get_abort_flag());
+    }
+
+    #[test]
+    fn test_protect_builtins() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        let here = rt.here();
+        rt.eval_str("protect-builtins  : + * ;  99");
+        assert!(rt.get_abort_flag());
+        assert_eq!(rt.here(), here);
+        assert!(!rt.get_compile_mode());
+        rt.eval_str("3 4 +");
+        assert_eq!(stack_values(&mut rt), vec![7]);
+        // words defined in Forth can still be redefined
+        rt.eval_str(": nip 5 ;  nip");
+        assert_eq!(stack_values(&mut rt), vec![5]);
+        // with the guard off, redefinition is allowed again, with a warning
+        rt.msg.set_level(DebugLevel::Warning);
+        let output =
+            rt.with_captured_output(|rt| rt.eval_str("unprotect-builtins  : + * ;  3 4 +"));
+        assert!(
+            output.contains("WARNING: unique?: Overwriting existing definition"),
+            "{output}"
+        );
+        assert!(!rt.get_abort_flag());
+        assert_eq!(stack_values(&mut rt), vec![12]);
+    }
+
+    #[test]
+    fn test_abort_quote() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str(": t abort\" boom\" 42 ;");
+        let output = rt.with_captured_output(|rt| rt.eval_str("7 0 t"));
+        assert_eq!(output, "");
+        assert!(!rt.get_abort_flag());
+        assert_eq!(stack_values(&mut rt), vec![7, 42]);
+        let output = rt.with_captured_output(|rt| rt.eval_str("7 -1 t"));
+        assert!(output.starts_with("boom\n"), "{output}");
+        assert!(rt.get_abort_flag());
+        assert_eq!(rt.kernel.stack_len(), 0);
+    }
+
+    #[test]
+    fn test_prompt() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        assert_eq!(rt.u_prompt(), "ok> ");
+        // quit prints the prompt after each line read from the terminal
+        rt.reader[0] = FileHandle::new_string("1 2 +\n3 *\n");
+        let output = rt.with_captured_output(|rt| {
+            rt.u_quit_line();
+        });
+        assert_eq!(output, "[ 3 ] ok> ");
+        let custom = rt.kernel.string_new("f3> ");
+        rt.eval_str(&format!("{custom} prompt !"));
+        assert_eq!(rt.u_prompt(), "f3> ");
+        let output = rt.with_captured_output(|rt| {
+            rt.u_quit_line();
+        });
+        assert_eq!(output, "[ 9 ] f3> ");
+    }
+
+    #[test]
+    fn test_load_core() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str(": sq dup * ;  7 sq");
+        assert_eq!(rt.kernel.pop(), 49);
+        assert_eq!(rt.kernel.stack_len(), 0);
+    }
 
     //     #[test]
     //     fn test_run_forth_word_dup_and_mul() {
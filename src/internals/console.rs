@@ -1,11 +1,40 @@
-use crate::internals::files::{FType, FileHandle, FileMode};
-use crate::internals::messages::Msg;
+use crate::internals::files::{FType, FileHandle, FileMode, OutputSink};
+use crate::internals::messages::ForthError;
 /// Input-output words
-use crate::kernel::BUF_SIZE;
-use crate::runtime::{ForthRuntime, FALSE, FILEMODE_RO, TRUE};
+use crate::kernel::{ADDRESS_MASK, ALLOC_START, BUF_SIZE, STRING_SIZE};
+use crate::runtime::{ForthRuntime, ENVBUF_START, FALSE, FILEMODE_RO, SRCBUF_START, TRUE};
 use std::cmp::min;
-use std::io::{self, BufRead, Write};
-use std::process::Command;
+use std::collections::HashSet;
+use std::env;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Identifies a file written by save-image, and the version of its layout
+const IMAGE_MAGIC: &[u8] = b"F3IMAGE2";
+
+/// u_utf8_char converts a Unicode scalar value to a char, substituting U+FFFD for invalid values
+///
+pub fn u_utf8_char(c: i64) -> char {
+    u32::try_from(c)
+        .ok()
+        .and_then(char::from_u32)
+        .unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+/// u_expand_home replaces a leading ~ in a file name with the home directory, if there is one
+///     Only ~ on its own or followed by / is expanded; ~user is left alone.
+///
+pub fn u_expand_home(name: &str, home: Option<&OsStr>) -> PathBuf {
+    match (name.strip_prefix('~'), home) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            PathBuf::from(home).join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(name),
+    }
+}
 
 /// file I/O and system call
 ///
@@ -19,47 +48,185 @@ use std::process::Command;
 ///
 /// Forth needs an i64 / usize as a file reference. This is achieved by creating a vector of file handles.
 /// Forth accesses files via an index into the vector.
-
 impl ForthRuntime {
     /// (system) ( s -- ) Execute a shell command from the string on the stack (Unix-like operating systems)
+    ///     The command is run by sh, so quoting and other shell syntax work as expected.
     ///
     pub fn f_system_p(&mut self) {
         if self.kernel.stack_check(1, "(system)") {
             let addr = self.kernel.pop() as usize;
             let cmd_string = self.kernel.string_get(addr);
-            let mut args = cmd_string.split_ascii_whitespace();
-            //println!("args: {:?}", args);
-            let mut cmd: Command;
-            let c = args.next();
-            match c {
-                Some(c) => cmd = Command::new(c),
-                None => return,
+            if let Some(output) = self.u_shell("(system)", &cmd_string) {
+                self.u_shell_output("(system)", &output.stdout, &output.stderr);
             }
-            for arg in args {
-                println!("Adding {}", arg);
-                cmd.arg(arg);
+        }
+    }
+
+    /// system ( s u -- n ) Execute a shell command, printing its output and pushing its exit status
+    ///     A command killed by a signal has no exit status, and returns -1.
+    ///
+    pub fn f_system(&mut self) {
+        if self.kernel.stack_check(2, "system") {
+            let len = self.kernel.pop();
+            let addr = self.kernel.pop();
+            if self.u_string_range("system", addr, len) {
+                let cmd_string =
+                    String::from_utf8_lossy(self.kernel.string_slice(addr as usize, len as usize))
+                        .to_string();
+                if let Some(output) = self.u_shell("system", &cmd_string) {
+                    if self.u_shell_output("system", &output.stdout, &output.stderr) {
+                        self.kernel.push(output.status.code().unwrap_or(-1) as i64);
+                    }
+                }
             }
-            let output = cmd.output().expect("(system) failed to execute command");
-            io::stdout().write_all(&output.stdout).unwrap();
-            io::stderr().write_all(&output.stderr).unwrap();
         }
     }
 
-    /// key ( -- c | 0 ) get a character and push on the stack, or zero if none available
-    pub fn f_key(&mut self) {
-        if let Some(reader) = self.reader.last_mut() {
-            let c = reader.read_char();
-            match c {
-                Some(c) => {
-                    self.kernel.push(c as u8 as i64);
+    /// $system ( s u dst -- n ) Execute a shell command, capturing its stdout as a counted string at dst
+    ///     The output is bounded by the end of string space, and n is the number of bytes captured.
+    ///     The count byte saturates at 255, so longer output should be read with dst 1+ n tell.
+    ///     Stderr still goes to the terminal.
+    ///
+    pub fn f_system_capture(&mut self) {
+        if self.kernel.stack_check(3, "$system") {
+            let dst = self.kernel.pop();
+            let len = self.kernel.pop();
+            let addr = self.kernel.pop();
+            if self.u_string_range("$system", addr, len) && self.u_string_range("$system", dst, 1) {
+                let cmd_string =
+                    String::from_utf8_lossy(self.kernel.string_slice(addr as usize, len as usize))
+                        .to_string();
+                if let Some(output) = self.u_shell("$system", &cmd_string) {
+                    if !self.u_shell_output("$system", &[], &output.stderr) {
+                        return;
+                    }
+                    let dst = dst as usize;
+                    let captured = min(output.stdout.len(), STRING_SIZE - dst - 1);
+                    self.kernel
+                        .byte_set(dst, min(captured, u8::MAX as usize) as u8);
+                    for (i, &b) in output.stdout[..captured].iter().enumerate() {
+                        self.kernel.byte_set(dst + 1 + i, b);
+                    }
+                    self.kernel.push(captured as i64);
                 }
-                None => {
-                    self.kernel.push(0);
+            }
+        }
+    }
+
+    /// u_shell runs a command with sh -c, collecting its output. Aborts if sh can't be started.
+    ///
+    pub fn u_shell(&mut self, word: &str, cmd_string: &str) -> Option<Output> {
+        match Command::new("sh").arg("-c").arg(cmd_string).output() {
+            Ok(output) => Some(output),
+            Err(e) => {
+                self.msg
+                    .error(word, "Failed to execute command", Some(e.to_string()));
+                self.set_error(ForthError::ShellError);
+                self.f_abort();
+                None
+            }
+        }
+    }
+
+    /// u_shell_output sends a command's stdout to the console output and its stderr to stderr
+    ///     Returns false, having aborted, if either can't be written (a closed pipe, for example).
+    ///
+    fn u_shell_output(&mut self, word: &str, stdout: &[u8], stderr: &[u8]) -> bool {
+        let result = self
+            .out
+            .write_all(stdout)
+            .and_then(|_| self.out.flush())
+            .and_then(|_| io::stderr().write_all(stderr));
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                self.msg
+                    .error(word, "Failed to write command output", Some(e.to_string()));
+                self.set_error(ForthError::ShellError);
+                self.f_abort();
+                false
+            }
+        }
+    }
+
+    /// getenv ( s u -- s2 u2 flag ) reads an environment variable into the environment buffer
+    ///     Pushes the value and TRUE, or an empty string and FALSE if the variable is not set.
+    ///     Long values are truncated to fit the buffer.
+    ///
+    pub fn f_getenv(&mut self) {
+        if self.kernel.stack_check(2, "getenv") {
+            let len = self.kernel.pop();
+            let addr = self.kernel.pop();
+            if self.u_string_range("getenv", addr, len) {
+                let name =
+                    String::from_utf8_lossy(self.kernel.string_slice(addr as usize, len as usize))
+                        .to_string();
+                let (value, found) = match std::env::var(name) {
+                    Ok(value) => (value, TRUE),
+                    Err(_) => (String::new(), FALSE),
+                };
+                let len = self.u_buffer_set(ENVBUF_START, value.as_bytes());
+                self.kernel.push(ENVBUF_START as i64 + 1);
+                self.kernel.push(len as i64);
+                self.kernel.push(found);
+            }
+        }
+    }
+
+    /// setenv ( s1 u1 s2 u2 -- ) sets environment variable s1 u1 to s2 u2 for this process and its children
+    ///
+    pub fn f_setenv(&mut self) {
+        if self.kernel.stack_check(4, "setenv") {
+            let val_len = self.kernel.pop();
+            let val_addr = self.kernel.pop();
+            let name_len = self.kernel.pop();
+            let name_addr = self.kernel.pop();
+            if self.u_string_range("setenv", name_addr, name_len)
+                && self.u_string_range("setenv", val_addr, val_len)
+            {
+                let name = String::from_utf8_lossy(
+                    self.kernel
+                        .string_slice(name_addr as usize, name_len as usize),
+                )
+                .to_string();
+                let value = String::from_utf8_lossy(
+                    self.kernel
+                        .string_slice(val_addr as usize, val_len as usize),
+                )
+                .to_string();
+                if name.is_empty() || name.contains(['=', '\0']) || value.contains('\0') {
+                    self.msg
+                        .error("setenv", "Invalid environment variable", Some(name));
+                    self.set_error(ForthError::BadValue);
+                    self.f_abort();
+                } else {
+                    std::env::set_var(name, value);
                 }
             }
         }
     }
 
+    /// key ( -- c | 0 ) get a character and push on the stack, or zero if none available
+    pub fn f_key(&mut self) {
+        let special = self.special_keys;
+        if let Some(reader) = self.reader.last_mut() {
+            let c = reader.read_key(special);
+            self.kernel.push(c.unwrap_or(0));
+        }
+    }
+
+    /// special-keys-on ( -- ) makes KEY return negative codes for arrows, function keys etc. in raw mode
+    ///
+    pub fn f_special_keys_on(&mut self) {
+        self.special_keys = true;
+    }
+
+    /// special-keys-off ( -- ) makes KEY ignore keys that aren't characters, as it does by default
+    ///
+    pub fn f_special_keys_off(&mut self) {
+        self.special_keys = false;
+    }
+
     /// key? ( -- b ) Checks to see if a character is ready to be read.
     ///     Only works properly in raw mode. In cooked mode it waits until EOL.
     ///
@@ -98,6 +265,11 @@ impl ForthRuntime {
                             if self.reader.len() > 1 {
                                 // Reader 0 is stdin
                                 self.reader.pop(); // file goes out of scope and should be closed automatically
+                                self.kernel.string_save("", dest); // nothing left over from the file's last line
+                                if dest == self.kernel.get(self.tib_ptr) as usize {
+                                    self.kernel.set(self.tib_size_ptr, 0);
+                                    self.kernel.set(self.tib_in_ptr, 1);
+                                }
                                 self.kernel.push(0);
                             } else {
                                 panic!("Reader error - EOF in stdin");
@@ -125,18 +297,54 @@ impl ForthRuntime {
         self.kernel.pop(); // we don't need the address
     }
 
+    /// source-line ( -- n ) pushes the number of the line being interpreted from the current source
+    ///     Lines are counted from 1 as the reader reads them.
+    ///
+    pub fn f_source_line(&mut self) {
+        let line = self.reader.last().map_or(0, |reader| reader.line_number);
+        self.kernel.push(line as i64);
+    }
+
+    /// source-name ( -- s u ) copies the current source's name into the source name buffer
+    ///     That's the file name, or stdin at the terminal. Long names are truncated.
+    ///
+    pub fn f_source_name(&mut self) {
+        let name = self
+            .reader
+            .last()
+            .map_or(String::new(), |reader| reader.name.clone());
+        let len = self.u_buffer_set(SRCBUF_START, name.as_bytes());
+        self.kernel.push(SRCBUF_START as i64 + 1);
+        self.kernel.push(len as i64);
+    }
+
     // output functions
 
-    /// (emit) ( c -- ) takes a character from the stack and prints it.
-    ///     (emit) will output any ASCII value (mod 128).
+    /// (emit) ( c -- ) takes a Unicode scalar value from the stack and prints its UTF-8 encoding.
+    ///     Values that are not valid characters are printed as the replacement character.
     ///
     pub fn f_emit_p(&mut self) {
         if self.kernel.stack_check(1, "(emit)") {
-            let c = self.kernel.pop() as u8 as char;
-            //print!("{}", c as u8 as char);
-            io::stdout()
-                .write_all(c.encode_utf8(&mut [0; 4]).as_bytes())
-                .unwrap();
+            let c = self.kernel.pop();
+            let _ = self
+                .out
+                .write_all(u_utf8_char(c).encode_utf8(&mut [0; 4]).as_bytes());
+        }
+    }
+
+    /// (abort") ( flag s -- ) Prints the counted string s and aborts if flag is true, otherwise continues
+    ///     Compiled by abort", with the message as a string literal.
+    ///
+    pub fn f_abort_quote_p(&mut self) {
+        if self.kernel.stack_check(2, "(abort\")") {
+            let addr = self.kernel.pop() as usize & ADDRESS_MASK;
+            let flag = self.kernel.pop();
+            if flag != FALSE {
+                let message = self.kernel.string_get(addr);
+                let _ = writeln!(self.out, "{message}");
+                self.set_error(ForthError::Aborted);
+                self.f_abort();
+            }
         }
     }
 
@@ -145,19 +353,93 @@ impl ForthRuntime {
     ///     This forces them out sooner
     ///
     pub fn f_flush(&mut self) {
-        io::stdout().flush().unwrap();
+        let _ = self.out.flush();
+    }
+
+    /// redirect> ( s u -- ) Send the output of emit, type, . and friends to a file, replacing its contents
+    ///     Output goes back to the terminal after redirect-off.
+    ///
+    pub fn f_redirect(&mut self) {
+        if self.kernel.stack_check(2, "redirect>") {
+            let len = self.kernel.pop();
+            let addr = self.kernel.pop();
+            if let Some(path) = self.u_file_name("redirect>", addr, len) {
+                match File::create(&path) {
+                    Ok(file) => {
+                        self.out.replace(OutputSink::File(BufWriter::new(file)));
+                    }
+                    Err(e) => {
+                        self.msg.error("redirect>", &e.to_string(), Some(path));
+                        self.set_error(ForthError::FileError);
+                    }
+                }
+            }
+        }
+    }
+
+    /// redirect-off ( -- ) Close the redirect file, if any, and send output to the terminal again
+    ///
+    pub fn f_redirect_off(&mut self) {
+        self.out.replace(OutputSink::Stdout);
+    }
+
+    /// with_captured_output runs f with console output going to a buffer, and returns what was printed
+    ///     The previous output sink, terminal or redirect file, is restored afterwards.
+    ///
+    #[cfg(test)]
+    pub fn with_captured_output<F: FnOnce(&mut Self)>(&mut self, f: F) -> String {
+        let saved = self.out.replace(OutputSink::Buffer(Vec::new()));
+        f(self);
+        match self.out.replace(saved) {
+            OutputSink::Buffer(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            _ => String::new(), // f redirected output elsewhere
+        }
     }
 
     /// .s ( -- ) prints a copy of the computation stack
     ///
     pub fn f_dot_s(&mut self) {
-        print!("[ ");
+        let mut text = String::from("[ ");
         let stack_len = self.kernel.stack_len();
         for i in (0..stack_len).rev() {
-            print!("{} ", self.kernel.peek(i));
+            text.push_str(&format!("{} ", self.kernel.peek(i)));
+        }
+        text.push_str("] ");
+        let _ = self.out.write_all(text.as_bytes());
+        let _ = self.out.flush();
+    }
+
+    /// evaluate ( s u -- ) Interprets the text at s, length u, as if it had been typed
+    ///     The text is read through a string reader, a line at a time, before evaluate returns.
+    ///     The input line being interpreted is saved and restored around it, so the rest of it runs next.
+    ///
+    pub fn f_evaluate(&mut self) {
+        if self.kernel.stack_check(2, "evaluate") {
+            let len = self.kernel.pop();
+            let addr = self.kernel.pop();
+            if !self.u_string_range("evaluate", addr, len) {
+                return;
+            }
+            let text =
+                String::from_utf8_lossy(self.kernel.string_slice(addr as usize, len as usize))
+                    .to_string();
+            let tib = self.kernel.get(self.tib_ptr) as usize;
+            let saved_tib = self.kernel.string_slice(tib, BUF_SIZE).to_vec();
+            let saved_size = self.kernel.get(self.tib_size_ptr);
+            let saved_in = self.kernel.get(self.tib_in_ptr);
+            let depth = self.reader.len();
+            self.reader.push(FileHandle::new_string(&text));
+            while self.reader.len() > depth && !self.get_abort_flag() {
+                self.f_query();
+                self.f_eval();
+            }
+            self.reader.truncate(depth); // after an abort, the rest of the text is dropped
+            for (i, byte) in saved_tib.into_iter().enumerate() {
+                self.kernel.byte_set(tib + i, byte);
+            }
+            self.kernel.set(self.tib_size_ptr, saved_size);
+            self.kernel.set(self.tib_in_ptr, saved_in);
         }
-        print!("] ");
-        io::stdout().flush().unwrap();
     }
 
     /// include-file (s -- T | F ) Pushes a new reader, pointing to the file named at s, calling ABORT if unsuccessful
@@ -170,7 +452,7 @@ impl ForthRuntime {
             let addr = self.kernel.pop() as usize;
             let file_name = self.kernel.string_get(addr);
             let mode = FILEMODE_RO; // Read-only mode for included files
-            let handle = self.u_open_file(&file_name, mode as i64);
+            let handle = self.u_open_file(&file_name, mode);
             match handle {
                 Some(handle) => {
                     self.reader.push(handle);
@@ -189,7 +471,6 @@ impl ForthRuntime {
             let mode = self.kernel.pop();
             let addr = self.kernel.pop() as usize;
             let name = self.kernel.string_get(addr);
-            println!("open-file: name={}, mode={}", name, mode);
             let handle = self.u_open_file(&name, mode);
             match handle {
                 Some(handle) => {
@@ -207,15 +488,21 @@ impl ForthRuntime {
     /// u_open-file  Open the named file with file access mode mode.
     ///    Returns a file handle and 0 if successful.
     pub fn u_open_file(&mut self, name: &str, mode: i64) -> Option<FileHandle> {
-        let full_path = std::fs::canonicalize(name);
         let mode = match mode {
             -1 => FileMode::RW,
             1 => FileMode::WO,
+            2 => FileMode::Append,
             _ => FileMode::RO,
         };
+        let name = u_expand_home(name, env::var_os("HOME").as_deref());
+        // Only a file being read has to exist already
+        let full_path = match mode {
+            FileMode::RO => self.u_resolve_path(&name),
+            _ => Ok(name),
+        };
         match full_path {
             Ok(full_path) => {
-                let file_handle = FileHandle::new_file(Some(&full_path), Msg::new(), mode);
+                let file_handle = FileHandle::new_file(Some(&full_path), self.msg.clone(), mode);
                 match file_handle {
                     Some(fh) => {
                         return Some(fh);
@@ -229,11 +516,32 @@ impl ForthRuntime {
             Err(error) => {
                 self.msg
                     .warning("open-file", error.to_string().as_str(), None::<bool>);
+                self.set_error(ForthError::FileError);
             }
         }
         None
     }
 
+    /// u_resolve_path finds a file to read: the name as given, or else the first directory in
+    ///     the search path that holds it. Absolute names aren't searched for.
+    ///
+    fn u_resolve_path(&self, name: &Path) -> io::Result<PathBuf> {
+        std::fs::canonicalize(name).or_else(|error| {
+            if name.is_absolute() {
+                return Err(error);
+            }
+            match self
+                .search_path
+                .iter()
+                .map(|dir| dir.join(name))
+                .find(|path| path.is_file())
+            {
+                Some(path) => std::fs::canonicalize(path),
+                None => Err(error),
+            }
+        })
+    }
+
     ///  close-file ( file-id -- ior ) Close a file, returning the I/O status code.
     ///     In rust, we just need it to go out of scope, so delete it from the vector
     pub fn f_close_file(&mut self) {
@@ -246,45 +554,313 @@ impl ForthRuntime {
         }
     }
 
-    /// read-line ( u file-id -- u flag ior ) Read up to u characters from a file.
+    /// save-image ( s u -- ior ) Save the dictionary and string space to the file named s u
+    ///     Returns 0 on success, or -1 after printing the error.
+    ///
+    pub fn f_save_image(&mut self) {
+        if self.kernel.stack_check(2, "save-image") {
+            let len = self.kernel.pop();
+            let addr = self.kernel.pop();
+            if self.u_string_range("save-image", addr, len) {
+                let path =
+                    String::from_utf8_lossy(self.kernel.string_slice(addr as usize, len as usize))
+                        .to_string();
+                let result = self.u_save_image(&path);
+                self.u_image_result("save-image", result);
+            }
+        }
+    }
+
+    /// load-image ( s u -- ior ) Replace the dictionary and string space with an image saved by save-image
+    ///     Returns 0 on success, or -1 after printing the error, leaving the dictionary unchanged.
+    ///
+    pub fn f_load_image(&mut self) {
+        if self.kernel.stack_check(2, "load-image") {
+            let len = self.kernel.pop();
+            let addr = self.kernel.pop();
+            if self.u_string_range("load-image", addr, len) {
+                let path =
+                    String::from_utf8_lossy(self.kernel.string_slice(addr as usize, len as usize))
+                        .to_string();
+                let result = self.u_load_image(&path);
+                self.u_image_result("load-image", result);
+            }
+        }
+    }
+
+    fn u_image_result(&mut self, word: &str, result: io::Result<()>) {
+        match result {
+            Ok(()) => self.kernel.push(0),
+            Err(e) => {
+                self.msg.error(word, &e.to_string(), None::<bool>);
+                self.set_error(ForthError::FileError);
+                self.kernel.push(-1);
+            }
+        }
+    }
+
+    /// u_save_image writes the heap up to HERE and string space up to S-HERE to a file
+    ///     Builtins are Rust functions, so they can't be saved. Compiled code refers to them by index,
+    ///     which is only meaningful with the same builtin table, so the image records their names too.
+    ///
+    ///     Layout (integers are little-endian u64 unless noted):
+    ///         IMAGE_MAGIC, builtin count, then each name as a length and UTF-8 bytes
+    ///         heap length, then that many i64 cells
+    ///         string space length, then that many bytes
+    ///         the number of BRANCH0s compiled by while, then their addresses
+    ///
+    pub fn u_save_image(&mut self, path: &str) -> io::Result<()> {
+        let mut image = IMAGE_MAGIC.to_vec();
+        let names: Vec<String> = (0..=self.kernel.max_builtin())
+            .map(|i| self.kernel.get_builtin(i).name.clone())
+            .collect();
+        image.extend((names.len() as u64).to_le_bytes());
+        for name in &names {
+            image.extend((name.len() as u64).to_le_bytes());
+            image.extend(name.as_bytes());
+        }
+        let heap_len = self.kernel.get(self.here_ptr) as usize;
+        image.extend((heap_len as u64).to_le_bytes());
+        for addr in 0..heap_len {
+            image.extend(self.kernel.get(addr).to_le_bytes());
+        }
+        let string_len = self.kernel.get(self.kernel.get_string_ptr()) as usize;
+        image.extend((string_len as u64).to_le_bytes());
+        image.extend(self.kernel.string_slice(0, string_len));
+        let mut branches: Vec<usize> = self.while_branches.iter().copied().collect();
+        branches.sort_unstable();
+        image.extend((branches.len() as u64).to_le_bytes());
+        for addr in branches {
+            image.extend((addr as u64).to_le_bytes());
+        }
+        std::fs::write(path, image)
+    }
+
+    /// u_load_image restores an image written by u_save_image
+    ///     The builtins must be registered first, by cold_start, and must match the ones in the image.
+    ///     Nothing changes unless the whole image is valid. State left from the previous session,
+    ///     such as locals and unfinished control structures, is cleared.
+    ///
+    pub fn u_load_image(&mut self, path: &str) -> io::Result<()> {
+        fn read_u64(r: &mut &[u8]) -> io::Result<usize> {
+            let mut buf = [0; 8];
+            r.read_exact(&mut buf)?;
+            Ok(u64::from_le_bytes(buf) as usize)
+        }
+        fn invalid(text: &str) -> io::Error {
+            io::Error::new(io::ErrorKind::InvalidData, text)
+        }
+        let image = std::fs::read(path)?;
+        let mut r = image
+            .strip_prefix(IMAGE_MAGIC)
+            .ok_or_else(|| invalid("Not an f3 image"))?;
+
+        let count = read_u64(&mut r)?;
+        if count != self.kernel.max_builtin() + 1 {
+            return Err(invalid("Image was saved with a different set of builtins"));
+        }
+        for i in 0..count {
+            let mut name = vec![0; read_u64(&mut r)?.min(r.len())];
+            r.read_exact(&mut name)?;
+            if name != self.kernel.get_builtin(i).name.as_bytes() {
+                return Err(invalid("Image was saved with a different set of builtins"));
+            }
+        }
+        let heap_len = read_u64(&mut r)?;
+        if heap_len > ALLOC_START {
+            return Err(invalid("Image dictionary is too large"));
+        }
+        let mut cells = vec![0; heap_len * 8];
+        r.read_exact(&mut cells)?;
+        let string_len = read_u64(&mut r)?;
+        if string_len > STRING_SIZE {
+            return Err(invalid("Image string space is too large"));
+        }
+        let mut strings = vec![0; string_len];
+        r.read_exact(&mut strings)?;
+        let branch_count = read_u64(&mut r)?;
+        if branch_count > heap_len {
+            return Err(invalid("Image has too many while branches"));
+        }
+        let mut branches = HashSet::new();
+        for _ in 0..branch_count {
+            branches.insert(read_u64(&mut r)?);
+        }
+
+        for (addr, cell) in cells.chunks_exact(8).enumerate() {
+            let mut buf = [0; 8];
+            buf.copy_from_slice(cell);
+            self.kernel.set(addr, i64::from_le_bytes(buf));
+        }
+        for (addr, &b) in strings.iter().enumerate() {
+            self.kernel.byte_set(addr, b);
+        }
+        self.while_branches = branches;
+        self.locals.clear();
+        self.local_frames.clear();
+        self.loop_frames.clear();
+        self.control_stack.clear();
+        self.definition_mark = None;
+        self.set_compile_mode(false);
+        self.u_rebuild_index();
+        Ok(())
+    }
+
+    /// read-line ( s u file-id -- u flag ior ) Read up to u characters from a file into s.
     ///     Returns the number of characters read, a flag indicating success or failure, and an i/o result code.
     ///     Starts from FILE_POSITION, and updates FILE_POSITION on completion
-    ///     Characters are read into TMP
+    ///     The line is stored without its count byte or line ending, so it can be any length; print it with tell.
+    ///
     pub fn f_read_line(&mut self) {
-        if self.kernel.stack_check(2, "read-line") {
+        if self.kernel.stack_check(3, "read-line") {
             let file_id = self.kernel.pop() as usize;
-            let _chars = self.kernel.pop() as usize;
+            let max_len = self.kernel.pop();
+            let dst = self.kernel.pop();
+            if !self.u_string_range("read-line", dst, max_len) {
+                return;
+            }
             if file_id < self.files.len() {
                 let mut result = String::new();
-                match self.files[file_id].source {
-                    FType::BReader(ref mut br) => {
-                        match br.read_line(&mut result) {
-                            Ok(r) => {
-                                if r == 0 {
-                                    // EOF
-                                    self.kernel.push(0);
-                                    self.kernel.push(FALSE);
-                                    self.kernel.push(-1);
-                                } else {
-                                    let addr = self.kernel.get(self.tmp_ptr) as usize;
-                                    self.kernel.string_save(&result, addr);
-                                    self.kernel.push(r as i64); // Chars read
-                                    self.kernel.push(TRUE);
-                                    self.kernel.push(0);
+                if let FType::BReader(ref mut br) = self.files[file_id].source {
+                    match br.read_line(&mut result) {
+                        Ok(r) => {
+                            if r == 0 {
+                                // EOF
+                                self.kernel.push(0);
+                                self.kernel.push(FALSE);
+                                self.kernel.push(-1);
+                            } else {
+                                let line = result.trim_end_matches(['\n', '\r']).as_bytes();
+                                let length = min(line.len(), max_len as usize);
+                                for (i, &b) in line[..length].iter().enumerate() {
+                                    self.kernel.byte_set(dst as usize + i, b);
                                 }
+                                self.kernel.push(length as i64); // Chars read
+                                self.kernel.push(TRUE);
+                                self.kernel.push(0);
                             }
-                            Err(e) => {
-                                self.msg
-                                    .error("read-line", e.to_string().as_str(), None::<bool>)
-                            }
+                        }
+                        Err(e) => {
+                            self.msg
+                                .error("read-line", e.to_string().as_str(), None::<bool>);
+                            self.set_error(ForthError::FileError);
                         }
                     }
-                    _ => {} //self.msg.error("read-line", "No source found", Some(&self.files[file_id].source)),
                 }
             }
         }
     }
 
+    /// read-file ( s u dst -- u ior ) Read the whole of the file named by s u into string space at dst
+    ///     Returns the number of bytes read and an i/o result code. The contents are not counted,
+    ///     so they can be printed with tell. Anything beyond the end of string space is dropped.
+    ///
+    pub fn f_read_file(&mut self) {
+        if self.kernel.stack_check(3, "read-file") {
+            let dst = self.kernel.pop();
+            let len = self.kernel.pop();
+            let addr = self.kernel.pop();
+            if !self.u_string_range("read-file", dst, 0) {
+                return;
+            }
+            if let Some(path) = self.u_file_name("read-file", addr, len) {
+                match std::fs::read(&path) {
+                    Ok(contents) => {
+                        let dst = dst as usize;
+                        let count = min(contents.len(), STRING_SIZE - dst);
+                        for (i, byte) in contents[..count].iter().enumerate() {
+                            self.kernel.byte_set(dst + i, *byte);
+                        }
+                        self.kernel.push(count as i64);
+                        self.kernel.push(0);
+                    }
+                    Err(e) => {
+                        self.msg.error("read-file", &e.to_string(), Some(path));
+                        self.set_error(ForthError::FileError);
+                        self.kernel.push(0);
+                        self.kernel.push(-1);
+                    }
+                }
+            }
+        }
+    }
+
+    /// write-file ( s u name u -- ior ) Write u bytes from string space at s to the named file
+    ///     The file is created if necessary, and replaced if it exists.
+    ///
+    pub fn f_write_file(&mut self) {
+        if self.kernel.stack_check(4, "write-file") {
+            let name_len = self.kernel.pop();
+            let name_addr = self.kernel.pop();
+            let len = self.kernel.pop();
+            let addr = self.kernel.pop();
+            if !self.u_string_range("write-file", addr, len) {
+                return;
+            }
+            if let Some(path) = self.u_file_name("write-file", name_addr, name_len) {
+                let contents = self
+                    .kernel
+                    .string_slice(addr as usize, len as usize)
+                    .to_vec();
+                match std::fs::write(&path, contents) {
+                    Ok(()) => self.kernel.push(0),
+                    Err(e) => {
+                        self.msg.error("write-file", &e.to_string(), Some(path));
+                        self.set_error(ForthError::FileError);
+                        self.kernel.push(-1);
+                    }
+                }
+            }
+        }
+    }
+
+    /// file-exists? ( s u -- flag ) Returns true if the named file or directory exists
+    ///
+    pub fn f_file_exists(&mut self) {
+        if self.kernel.stack_check(2, "file-exists?") {
+            let len = self.kernel.pop();
+            let addr = self.kernel.pop();
+            if let Some(path) = self.u_file_name("file-exists?", addr, len) {
+                let exists = Path::new(&path).exists();
+                self.kernel.push(if exists { TRUE } else { FALSE });
+            }
+        }
+    }
+
+    /// delete-file ( s u -- ior ) Delete the named file, returning an i/o result code
+    ///
+    pub fn f_delete_file(&mut self) {
+        if self.kernel.stack_check(2, "delete-file") {
+            let len = self.kernel.pop();
+            let addr = self.kernel.pop();
+            if let Some(path) = self.u_file_name("delete-file", addr, len) {
+                match std::fs::remove_file(&path) {
+                    Ok(()) => self.kernel.push(0),
+                    Err(e) => {
+                        self.msg.error("delete-file", &e.to_string(), Some(path));
+                        self.set_error(ForthError::FileError);
+                        self.kernel.push(-1);
+                    }
+                }
+            }
+        }
+    }
+
+    /// u_file_name returns the file name held in string space at addr, length len, with ~ expanded
+    ///     Aborts if the region is out of range.
+    ///
+    fn u_file_name(&mut self, word: &str, addr: i64, len: i64) -> Option<String> {
+        if self.u_string_range(word, addr, len) {
+            let name =
+                String::from_utf8_lossy(self.kernel.string_slice(addr as usize, len as usize))
+                    .to_string();
+            let path = u_expand_home(&name, env::var_os("HOME").as_deref());
+            Some(path.to_string_lossy().into_owned())
+        } else {
+            None
+        }
+    }
+
     ///  write-line ( s u file-id -- ior ) Write u characters from s to a file, returning an i/o result code.
     ///     Not intended to work with stdout
     pub fn f_write_line(&mut self) {
@@ -295,12 +871,36 @@ impl ForthRuntime {
             if file_id < self.files.len() {
                 let string = self.kernel.string_get(addr)[0..chars - 1].to_owned();
                 // write the string to the file
-                match self.files[file_id].source {
-                    FType::File(ref mut f) => {
-                        f.write_all(&string.as_bytes())
-                            .expect("Error writing to file");
-                    }
-                    _ => {}
+                if let FType::File(ref mut f) = self.files[file_id].source {
+                    f.write_all(string.as_bytes())
+                        .expect("Error writing to file");
+                }
+            }
+        }
+    }
+
+    /// flush-file ( file-id -- ior ) Push anything written to the file out to the operating system
+    ///     Returns 0 on success. Read-only files have nothing to flush.
+    ///
+    pub fn f_flush_file(&mut self) {
+        if self.kernel.stack_check(1, "flush-file") {
+            let file_id = self.kernel.pop() as usize;
+            if file_id >= self.files.len() {
+                self.msg.error("flush-file", "No such file", Some(file_id));
+                self.set_error(ForthError::FileError);
+                self.kernel.push(-1);
+                return;
+            }
+            let result = match self.files[file_id].source {
+                FType::File(ref mut f) => f.flush().and_then(|_| f.sync_data()),
+                _ => Ok(()),
+            };
+            match result {
+                Ok(()) => self.kernel.push(0),
+                Err(e) => {
+                    self.msg.error("flush-file", &e.to_string(), None::<bool>);
+                    self.set_error(ForthError::FileError);
+                    self.kernel.push(-1);
                 }
             }
         }
@@ -315,6 +915,7 @@ impl ForthRuntime {
             } else {
                 self.msg
                     .error("file-size", "No such file-id", Some(file_id));
+                self.set_error(ForthError::FileError);
             }
         }
     }
@@ -328,7 +929,545 @@ impl ForthRuntime {
             } else {
                 self.msg
                     .error("file-position", "No such file-id", Some(file_id));
+                self.set_error(ForthError::FileError);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{u_expand_home, u_utf8_char, IMAGE_MAGIC};
+    use crate::internals::files::{FType, FileHandle, FileMode, OutputSink, TempPath};
+    use crate::internals::messages::{DebugLevel, ForthError, Msg};
+    use crate::kernel::STRING_SIZE;
+    use crate::runtime::{ForthRuntime, FALSE, TRUE};
+    use std::ffi::OsStr;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_emit_utf8() {
+        let mut buf = [0; 4];
+        assert_eq!(u_utf8_char(65).encode_utf8(&mut buf).as_bytes(), b"A");
+        assert_eq!(
+            u_utf8_char(0x263A).encode_utf8(&mut buf).as_bytes(),
+            [0xE2, 0x98, 0xBA]
+        );
+        assert_eq!(u_utf8_char(-1), char::REPLACEMENT_CHARACTER);
+        assert_eq!(u_utf8_char(0xD800), char::REPLACEMENT_CHARACTER);
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.kernel.push(0x263A);
+        rt.f_emit_p();
+        assert_eq!(rt.kernel.stack_len(), 0);
+    }
+
+    #[test]
+    fn test_utf8_strings() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        let addr = rt.kernel.string_new("héllo ☺");
+        assert_eq!(rt.kernel.string_length(addr), "héllo ☺".len());
+        assert_eq!(rt.kernel.string_get(addr), "héllo ☺");
+    }
+
+    fn push_str(rt: &mut ForthRuntime, text: &str) {
+        let addr = rt.kernel.string_new(text) as i64 + 1;
+        rt.kernel.push(addr);
+        rt.kernel.push(text.len() as i64);
+    }
+
+    #[test]
+    fn test_system_exit_status() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        push_str(&mut rt, "false");
+        rt.f_system();
+        assert_ne!(rt.kernel.pop(), 0);
+        push_str(&mut rt, "true");
+        rt.f_system();
+        assert_eq!(rt.kernel.pop(), 0);
+        push_str(&mut rt, "test 'a b' = 'a b'");
+        rt.f_system();
+        assert_eq!(rt.kernel.pop(), 0);
+        assert_eq!(rt.kernel.stack_len(), 0);
+        // output goes to the console output, so it can be captured or redirected
+        let text = rt.with_captured_output(|rt| {
+            push_str(rt, "echo hello; exit 3");
+            rt.f_system();
+        });
+        assert_eq!(text, "hello\n");
+        assert_eq!(rt.kernel.pop(), 3);
+        let text = rt.with_captured_output(|rt| {
+            rt.kernel.string_save("printf bye", 8000);
+            rt.kernel.push(8000);
+            rt.f_system_p();
+        });
+        assert_eq!(text, "bye");
+    }
+
+    #[test]
+    fn test_system_capture() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        push_str(&mut rt, "echo hello");
+        rt.kernel.push(8000);
+        rt.f_system_capture();
+        assert_eq!(rt.kernel.pop(), 6);
+        assert_eq!(rt.kernel.string_get(8000), "hello\n");
+        rt.eval_str("8000 type");
+        assert_eq!(rt.kernel.stack_len(), 0);
+        // output is cut off at the end of string space
+        push_str(&mut rt, "echo hello");
+        rt.kernel.push(STRING_SIZE as i64 - 4);
+        rt.f_system_capture();
+        assert_eq!(rt.kernel.pop(), 3);
+    }
+
+    #[test]
+    fn test_redirect_to_file() {
+        let temp = TempPath::new("redirect.txt");
+        let path = temp.name();
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        push_str(&mut rt, path);
+        rt.f_redirect();
+        rt.kernel.push('A' as i64);
+        rt.f_emit_p();
+        rt.kernel.push(0x263A);
+        rt.f_emit_p();
+        rt.f_redirect_off();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "A\u{263A}");
+    }
+
+    #[test]
+    fn test_captured_output() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str("1 2 3");
+        assert_eq!(rt.with_captured_output(|rt| rt.f_dot_s()), "[ 1 2 3 ] ");
+        rt.f_clear();
+        assert_eq!(rt.with_captured_output(|rt| rt.f_dot_s()), "[ ] ");
+        assert_eq!(
+            rt.with_captured_output(|rt| rt.eval_str("42 . -7 .")),
+            "42 -7 "
+        );
+        assert_eq!(
+            rt.with_captured_output(|rt| rt.eval_str("65 emit 9786 emit")),
+            "A\u{263A}"
+        );
+        rt.eval_str("hex");
+        assert_eq!(
+            rt.with_captured_output(|rt| rt.eval_str("255 . decimal")),
+            "FF "
+        );
+        // messages and help text share the sink with the output words
+        rt.msg.set_level(DebugLevel::Warning);
+        let text = rt.with_captured_output(|rt| rt.eval_str("no-such-word"));
+        assert!(text.contains("WARNING"), "{text}");
+        assert!(text.contains("no-such-word"), "{text}");
+        rt.f_clear();
+        let text = rt.with_captured_output(|rt| rt.eval_str("1 2 .rs"));
+        assert!(text.starts_with("Return-stack pointer"), "{text}");
+        rt.f_clear();
+        assert!(matches!(
+            rt.out.replace(OutputSink::Stdout),
+            OutputSink::Stdout
+        ));
+    }
+
+    #[test]
+    fn test_zero_padded_numbers() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        assert_eq!(rt.with_captured_output(|rt| rt.eval_str("5 3 u.0r")), "005");
+        assert_eq!(
+            rt.with_captured_output(|rt| rt.eval_str("1234 3 u.0r")),
+            "1234"
+        );
+        assert_eq!(rt.with_captured_output(|rt| rt.eval_str("0 2 u.0r")), "00");
+        assert_eq!(
+            rt.with_captured_output(|rt| rt.eval_str("42 hex 4 u.0r decimal")),
+            "002A"
+        );
+        assert_eq!(
+            rt.with_captured_output(|rt| rt.eval_str("3 zeros 0 zeros")),
+            "000"
+        );
+        assert_eq!(rt.kernel.stack_len(), 0);
+    }
+
+    #[test]
+    fn test_output_to_buffer() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.out.replace(OutputSink::Buffer(Vec::new()));
+        rt.kernel.push(1);
+        rt.kernel.push(2);
+        rt.f_dot_s();
+        rt.f_clear();
+        rt.kernel.push('A' as i64);
+        rt.f_emit_p();
+        rt.kernel.push(0x263A);
+        rt.f_emit_p();
+        match rt.out.replace(OutputSink::Stdout) {
+            OutputSink::Buffer(bytes) => {
+                assert_eq!(String::from_utf8(bytes).unwrap(), "[ 1 2 ] A\u{263A}")
+            }
+            _ => panic!("output sink was replaced"),
+        }
+    }
+
+    #[test]
+    fn test_read_file() {
+        let temp = TempPath::new("read-file.txt");
+        let path = temp.name();
+        std::fs::write(path, "width 80\nheight 24\n").unwrap();
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        push_str(&mut rt, path);
+        rt.kernel.push(8000);
+        rt.f_read_file();
+        assert_eq!(rt.kernel.pop(), 0);
+        assert_eq!(rt.kernel.pop(), 19);
+        assert_eq!(rt.kernel.string_slice(8000, 8), b"width 80");
+        // the contents are cut off at the end of string space
+        push_str(&mut rt, path);
+        rt.kernel.push(STRING_SIZE as i64 - 5);
+        rt.f_read_file();
+        assert_eq!(rt.kernel.pop(), 0);
+        assert_eq!(rt.kernel.pop(), 5);
+        std::fs::remove_file(path).unwrap();
+        // a missing file returns an error code
+        push_str(&mut rt, path);
+        rt.kernel.push(8000);
+        rt.f_read_file();
+        assert_eq!(rt.kernel.pop(), -1);
+        assert_eq!(rt.kernel.pop(), 0);
+    }
+
+    #[test]
+    fn test_read_long_line() {
+        let temp = TempPath::new("long-line.txt");
+        let path = temp.name();
+        let line = "héllo wörld ".repeat(40); // 560 bytes, past any count byte
+        std::fs::write(path, format!("{line}\nnext\n")).unwrap();
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        let handle = rt.u_open_file(path, 0).unwrap();
+        rt.files.push(handle);
+        rt.eval_str("6000 1000 0 read-line");
+        assert_eq!(rt.kernel.pop(), 0);
+        assert_eq!(rt.kernel.pop(), TRUE);
+        let length = rt.kernel.pop();
+        assert_eq!(length, line.len() as i64);
+        assert_eq!(rt.kernel.string_slice(6000, line.len()), line.as_bytes());
+        let text = rt.with_captured_output(|rt| rt.eval_str(&format!("6000 {length} tell")));
+        assert_eq!(text, line);
+        // a short buffer takes only the first u bytes
+        rt.eval_str("6000 2 0 read-line");
+        assert_eq!(rt.kernel.pop(), 0);
+        assert_eq!(rt.kernel.pop(), TRUE);
+        assert_eq!(rt.kernel.pop(), 2);
+        assert_eq!(rt.kernel.string_slice(6000, 2), b"ne");
+        rt.eval_str("6000 10 0 read-line");
+        assert_eq!(rt.kernel.pop(), -1);
+        assert_eq!(rt.kernel.pop(), FALSE);
+        assert_eq!(rt.kernel.pop(), 0);
+        std::fs::remove_file(path).unwrap();
+        // accept stores the whole line too, although the count byte saturates
+        let line = line.trim_end(); // the reader drops trailing white space
+        rt.reader.push(FileHandle::new_string(line));
+        rt.eval_str("6000 1000 accept");
+        assert_eq!(rt.kernel.pop(), line.len() as i64);
+        assert_eq!(rt.kernel.pop(), 6000);
+        assert_eq!(rt.kernel.byte_get(6000), 255);
+        assert_eq!(rt.kernel.string_slice(6001, line.len()), line.as_bytes());
+    }
+
+    #[test]
+    fn test_write_file() {
+        let temp = TempPath::new("write-file.txt");
+        let path = temp.name();
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.kernel.string_save("first line\nsecond", 8000);
+        rt.kernel.push(8001);
+        rt.kernel.push(17);
+        push_str(&mut rt, path);
+        rt.f_write_file();
+        assert_eq!(rt.kernel.pop(), 0);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "first line\nsecond");
+        // writing again replaces the contents
+        rt.kernel.push(8001);
+        rt.kernel.push(5);
+        push_str(&mut rt, path);
+        rt.f_write_file();
+        assert_eq!(rt.kernel.pop(), 0);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "first");
+    }
+
+    #[test]
+    fn test_file_exists_and_delete() {
+        let temp = TempPath::new("delete.txt");
+        let path = temp.name();
+        std::fs::write(path, "temporary").unwrap();
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        push_str(&mut rt, path);
+        rt.f_file_exists();
+        assert_eq!(rt.kernel.pop(), TRUE);
+        push_str(&mut rt, path);
+        rt.f_delete_file();
+        assert_eq!(rt.kernel.pop(), 0);
+        push_str(&mut rt, path);
+        rt.f_file_exists();
+        assert_eq!(rt.kernel.pop(), FALSE);
+        // deleting a missing file is an error, not a panic
+        push_str(&mut rt, path);
+        rt.f_delete_file();
+        assert_ne!(rt.kernel.pop(), 0);
+        // a length that overflows the end of the region aborts instead of panicking
+        rt.eval_str("1 9223372036854775807 file-exists?");
+        assert!(rt.get_abort_flag());
+    }
+
+    #[test]
+    fn test_open_new_file_for_writing() {
+        let temp = TempPath::new("open-wo.txt");
+        let path = temp.name();
+        let _ = std::fs::remove_file(path);
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        assert!(rt.u_open_file(path, 0).is_none());
+        let mut handle = rt.u_open_file(path, 1).unwrap();
+        if let FType::File(ref mut f) = handle.source {
+            f.write_all(b"new file").unwrap();
+        }
+        drop(handle);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "new file");
+    }
+
+    #[test]
+    fn test_string_source_reader() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.reader.push(FileHandle::new_string(": sq dup * ;\n5 sq"));
+        while rt.reader.len() > 1 {
+            rt.f_query();
+            rt.f_eval();
+        }
+        assert_eq!(rt.kernel.pop(), 25);
+        // evaluate runs its text straight away, then the rest of the line
+        rt.eval_str("s\" 2 sq 3 +\" evaluate 10 *");
+        assert_eq!(rt.kernel.pop(), 70);
+        assert_eq!(rt.reader.len(), 1);
+    }
+
+    #[test]
+    fn test_flush_file() {
+        let temp = TempPath::new("flush.txt");
+        let path = temp.name();
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        let handle = rt.u_open_file(path, 1).unwrap();
+        rt.files.push(handle);
+        if let FType::File(ref mut f) = rt.files[0].source {
+            f.write_all(b"shared").unwrap();
+        }
+        rt.kernel.push(0);
+        rt.f_flush_file();
+        assert_eq!(rt.kernel.pop(), 0);
+        // another handle sees the data while the file is still open
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "shared");
+        rt.kernel.push(5);
+        rt.f_flush_file();
+        assert_eq!(rt.kernel.pop(), -1);
+    }
+
+    #[test]
+    fn test_end_of_included_file() {
+        let temp = TempPath::new("eof.fs");
+        let path = temp.name();
+        std::fs::write(path, ": three 3 ;\n1 2 +").unwrap(); // no newline at the end
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.reader[0] = FileHandle::new_string("three 10 *\n"); // standing in for the terminal
+        let tmp = rt.kernel.get(rt.tmp_ptr) as usize;
+        rt.kernel.string_set(tmp, path);
+        rt.kernel.push(tmp as i64);
+        rt.f_include_file();
+        assert_eq!(rt.kernel.pop(), TRUE);
+        let prompts: Vec<bool> = (0..3).map(|_| rt.u_quit_line()).collect();
+        assert_eq!(prompts, vec![false, false, true]);
+        let tib = rt.kernel.get(rt.tib_ptr) as usize;
+        assert_eq!(rt.kernel.string_get(tib), "");
+        assert!(rt.u_quit_line());
+        assert_eq!(rt.kernel.pop(), 30);
+        assert_eq!(rt.kernel.pop(), 3);
+    }
+
+    #[test]
+    fn test_expand_home() {
+        let home = Some(OsStr::new("/home/forth"));
+        assert_eq!(
+            u_expand_home("~/.f2/corelib.fs", home),
+            PathBuf::from("/home/forth/.f2/corelib.fs")
+        );
+        assert_eq!(u_expand_home("~", home), PathBuf::from("/home/forth"));
+        assert_eq!(
+            u_expand_home("~other/x.fs", home),
+            PathBuf::from("~other/x.fs")
+        );
+        assert_eq!(u_expand_home("src/~x.fs", home), PathBuf::from("src/~x.fs"));
+        // without a home directory the name is used as it is
+        assert_eq!(u_expand_home("~/x.fs", None), PathBuf::from("~/x.fs"));
+    }
+
+    #[test]
+    fn test_include_from_search_path() {
+        let temp = TempPath::new("path");
+        let dir = temp.0.clone();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("f3-path-lib.fs"), ": from-path 42 ;\n").unwrap();
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str("s\" f3-path-lib.fs\" drop 1- include-file");
+        assert_eq!(rt.kernel.pop(), FALSE); // not in the current directory
+        rt.search_path = vec![std::env::temp_dir().join("no-such-f3-dir"), dir.clone()];
+        rt.eval_str("s\" f3-path-lib.fs\" drop 1- include-file");
+        assert_eq!(rt.kernel.pop(), TRUE);
+        rt.reader[0] = FileHandle::new_string("from-path\n");
+        while rt.reader.len() > 1 {
+            rt.u_quit_line();
+        }
+        rt.u_quit_line();
+        assert_eq!(rt.kernel.pop(), 42);
+    }
+
+    #[test]
+    fn test_error_source_location() {
+        let temp = TempPath::new("bad-token.fs");
+        let path = &temp.0;
+        std::fs::write(path, ": a 1 ;\n: b 2 ;\n3 no-such-token\n: c 3 ;\n").unwrap();
+        let name = path.to_str().unwrap();
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str(&format!("include {name}"));
+        while rt.reader.len() > 1 {
+            rt.set_abort_flag(false);
+            rt.f_query();
+            rt.f_eval();
+        }
+        assert_eq!(
+            rt.error,
+            Some(ForthError::NotFound {
+                name: "no-such-token".to_string()
+            })
+        );
+        let full_path = std::fs::canonicalize(path).unwrap();
+        assert_eq!(rt.error_source, Some(format!("{}:3", full_path.display())));
+        // typed lines have no location
+        rt.eval_str("no-such-token");
+        assert_eq!(rt.error_source, None);
+    }
+
+    #[test]
+    fn test_source_line_and_name() {
+        let temp = TempPath::new("source-line.fs");
+        let path = &temp.0;
+        std::fs::write(path, "1\n2\n3\n").unwrap();
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str("source-line source-name s\" stdin\" compare");
+        assert_eq!(rt.kernel.pop(), 0);
+        assert_eq!(rt.kernel.pop(), 0);
+        let reader = FileHandle::new_file(Some(path), Msg::new(), FileMode::RO).unwrap();
+        rt.reader.push(reader);
+        rt.f_query();
+        rt.f_query();
+        rt.f_source_line();
+        assert_eq!(rt.kernel.pop(), 2);
+        rt.f_source_name();
+        let len = rt.kernel.pop() as usize;
+        let addr = rt.kernel.pop() as usize;
+        assert_eq!(
+            rt.kernel.string_slice(addr, len),
+            path.to_str().unwrap().as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_image_round_trip() {
+        let temp = TempPath::new("image.img");
+        let path = temp.name();
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str(": sq dup * ;  variable v  5 v !");
+        rt.eval_str(": w begin dup while 1- repeat ;  : i 1 if 2 then ;");
+        rt.u_save_image(path).unwrap();
+
+        let mut fresh = ForthRuntime::new();
+        fresh.cold_start();
+        // leftovers from the session before the load don't survive it
+        fresh.while_branches.extend(0..crate::kernel::ALLOC_START);
+        fresh.local_frames.push((0, 0));
+        fresh.definition_mark = Some((0, 0));
+        fresh.u_load_image(path).unwrap();
+        assert!(fresh.local_frames.is_empty());
+        assert!(fresh.definition_mark.is_none());
+        fresh.eval_str("7 sq v @ : cube dup sq * ; 3 cube");
+        assert_eq!(fresh.kernel.pop(), 27);
+        assert_eq!(fresh.kernel.pop(), 5);
+        assert_eq!(fresh.kernel.pop(), 49);
+        fresh.eval_str("' w ' i");
+        let i = fresh.kernel.pop();
+        let w = fresh.kernel.pop();
+        assert!(fresh.u_see_text(w).unwrap().contains(" while "));
+        assert!(!fresh.u_see_text(i).unwrap().contains("while"));
+
+        // an image that doesn't match the builtins is refused
+        let mut image = std::fs::read(path).unwrap();
+        image[IMAGE_MAGIC.len()] += 1;
+        std::fs::write(path, image).unwrap();
+        assert!(fresh.u_load_image(path).is_err());
+    }
+
+    #[test]
+    fn test_getenv_setenv() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        let name = rt.kernel.string_new("F3_TEST_VAR") as i64 + 1;
+        let value = rt.kernel.string_new("forth") as i64 + 1;
+        for v in [name, 11, value, 5] {
+            rt.kernel.push(v);
+        }
+        rt.f_setenv();
+        rt.kernel.push(name);
+        rt.kernel.push(11);
+        rt.f_getenv();
+        assert_eq!(rt.kernel.pop(), TRUE);
+        let len = rt.kernel.pop() as usize;
+        let addr = rt.kernel.pop() as usize;
+        assert_eq!(rt.kernel.string_slice(addr, len), b"forth");
+        let name = rt.kernel.string_new("F3_NO_SUCH_VAR") as i64 + 1;
+        rt.kernel.push(name);
+        rt.kernel.push(14);
+        rt.f_getenv();
+        assert_eq!(rt.kernel.pop(), FALSE);
+        assert_eq!(rt.kernel.pop(), 0);
+        rt.kernel.pop();
+        assert_eq!(rt.kernel.stack_len(), 0);
+    }
+}
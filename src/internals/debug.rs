@@ -1,10 +1,12 @@
 // Debugging help
 
 use crate::internals::messages::DebugLevel;
+use crate::kernel::DATA_SIZE;
 use crate::runtime::{
     ForthRuntime, ABORT, ADDRESS_MASK, BRANCH, BRANCH0, BREAK, BUILTIN_FLAG, CONSTANT, DEFINITION,
-    EXEC, EXIT, LITERAL, STRLIT, VARIABLE,
+    EXEC, EXIT, FALSE, LITERAL, STRLIT, VARIABLE,
 };
+use std::io::Write;
 
 impl ForthRuntime {
     /// show-stack ( -- ) turns on stack printing at the time the prompt is issued
@@ -44,6 +46,181 @@ impl ForthRuntime {
         // println!("DebugLevel is {:?}", self.msg.get_level());
     }
 
+    /// break-at <name> ( -- ) sets a breakpoint on a word
+    ///     When the word is about to be executed, the stepper is switched to single step mode,
+    ///     whatever its current state.
+    ///     Compiled references to builtins hold the builtin's index rather than its cfa,
+    ///     so that is what gets recorded for them.
+    ///
+    pub fn f_break_at(&mut self) {
+        self.f_tick_p();
+        let cfa = self.kernel.pop();
+        if cfa == FALSE {
+            self.msg.warning("break-at", "Word not found", None::<bool>);
+        } else {
+            let xt = self.kernel.get(cfa as usize) as usize;
+            let target = if xt & BUILTIN_FLAG != 0 {
+                xt
+            } else {
+                cfa as usize
+            };
+            self.breakpoints.insert(target);
+        }
+    }
+
+    /// clear-breaks ( -- ) removes all breakpoints
+    ///
+    pub fn f_clear_breaks(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// u_check_breakpoint switches on single stepping if the cell at pc refers to a breakpoint word
+    ///     The stepper depth is extended if necessary so the step is visible.
+    ///
+    pub fn u_check_breakpoint(&mut self, pc: usize, call_depth: usize) {
+        if self.breakpoints.is_empty() || pc >= DATA_SIZE {
+            return;
+        }
+        let target = self.kernel.get(pc) as usize;
+        if self.breakpoints.contains(&target) {
+            self.kernel.set(self.stepper_ptr, -1);
+            if (self.kernel.get(self.step_depth_ptr) as usize) < call_depth {
+                self.kernel.set(self.step_depth_ptr, call_depth as i64);
+            }
+        }
+    }
+
+    /// watch <name> ( -- ) registers a variable whose value is reported by the stepper when it changes
+    ///     The value cell is the one following the cfa, as for VARIABLE.
+    ///
+    pub fn f_watch(&mut self) {
+        self.f_tick_p();
+        let cfa = self.kernel.pop();
+        if cfa == FALSE {
+            self.msg.warning("watch", "Word not found", None::<bool>);
+        } else {
+            let addr = cfa as usize + 1;
+            let val = self.kernel.get(addr);
+            self.watches.push((addr, val));
+        }
+    }
+
+    /// u_check_watches compares each watched cell with its last-seen value
+    ///     Returns a `name: old -> new` line for each change, and records the new values.
+    ///
+    pub fn u_check_watches(&mut self) -> Vec<String> {
+        let mut changes = Vec::new();
+        for i in 0..self.watches.len() {
+            let (addr, old) = self.watches[i];
+            let new = self.kernel.get(addr);
+            if new != old {
+                let nfa = self.kernel.get(addr - 2) as usize & ADDRESS_MASK;
+                let name = self.kernel.string_get(nfa);
+                changes.push(format!("{name}: {old} -> {new}"));
+                self.watches[i].1 = new;
+            }
+        }
+        changes
+    }
+
+    /// profile-on ( -- ) clears the counts and starts profiling
+    ///     While profiling, the inner interpreter counts each builtin and word it dispatches.
+    ///
+    pub fn f_profile_on(&mut self) {
+        self.profile.clear();
+        self.profiling = true;
+    }
+
+    /// profile-off ( -- ) stops profiling, keeping the counts for profile-dump
+    ///
+    pub fn f_profile_off(&mut self) {
+        self.profiling = false;
+    }
+
+    /// profile-dump ( -- ) prints the profile, most frequently called words first
+    ///
+    pub fn f_profile_dump(&mut self) {
+        for (name, count) in self.u_profile_report() {
+            let _ = writeln!(self.out, "{count:>10} {name}");
+        }
+    }
+
+    /// u_profile_report returns (name, count) pairs sorted by descending count
+    ///
+    pub fn u_profile_report(&mut self) -> Vec<(String, u64)> {
+        let mut report: Vec<(String, u64)> = Vec::new();
+        let entries: Vec<(usize, u64)> = self.profile.iter().map(|(k, v)| (*k, *v)).collect();
+        for (code, count) in entries {
+            let name = if code & BUILTIN_FLAG != 0 {
+                self.kernel.get_builtin(code & ADDRESS_MASK).name.clone()
+            } else {
+                let nfa = self.kernel.get(code - 1) as usize & ADDRESS_MASK;
+                self.kernel.string_get(nfa)
+            };
+            report.push((name, count));
+        }
+        report.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        report
+    }
+
+    /// t{ ( -- ) starts a test, noting the stack depth so the results can be found
+    ///     Usage: t{ 2 3 + -> 5 }t
+    ///
+    pub fn f_test_start(&mut self) {
+        self.test_depth = self.kernel.stack_len();
+        self.test_results.clear();
+    }
+
+    /// -> ( .. -- ) captures everything pushed since t{ as the test results
+    ///
+    pub fn f_test_arrow(&mut self) {
+        self.test_results = self.u_test_take("->");
+    }
+
+    /// }t ( .. -- ) compares the values pushed since -> with the captured results
+    ///
+    pub fn f_test_end(&mut self) {
+        let expected = self.u_test_take("}t");
+        if expected == self.test_results {
+            self.test_passes += 1;
+            let _ = writeln!(self.out, "PASS");
+        } else {
+            self.test_failures += 1;
+            let _ = writeln!(
+                self.out,
+                "FAIL: expected {:?}, got {:?}",
+                expected, self.test_results
+            );
+        }
+    }
+
+    /// u_test_take pops the cells above the t{ depth, returning them bottom first
+    ///
+    fn u_test_take(&mut self, word: &str) -> Vec<i64> {
+        let depth = self.kernel.stack_len();
+        if depth < self.test_depth {
+            self.msg
+                .error(word, "Stack underflow during test", Some(depth));
+            return Vec::new();
+        }
+        let mut cells: Vec<i64> = (0..depth - self.test_depth)
+            .map(|_| self.kernel.pop())
+            .collect();
+        cells.reverse();
+        cells
+    }
+
+    /// u_return_stack_string formats the return stack for the stepper, oldest entry first
+    ///
+    pub fn u_return_stack_string(&self) -> String {
+        let mut text = String::from("R{ ");
+        for val in self.kernel.return_stack() {
+            text.push_str(&format!("{val} "));
+        }
+        text.push_str("} ");
+        text
+    }
+
     /// u_step provides the step / trace functionality
     ///     called from inside the definition interpreter
     ///     it is driven by the STEPPER and STEPPER-DEPTH variables:
@@ -56,81 +233,203 @@ impl ForthRuntime {
     ///     pc is the program counter, which represents the address of the cell being executed.
     ///
     pub fn debug_step(&mut self, pc: usize, call_depth: usize) {
+        self.u_check_breakpoint(pc, call_depth);
         let stepper_mode = self.kernel.get(self.stepper_ptr);
         let stepper_depth = self.kernel.get(self.step_depth_ptr) as usize;
         if stepper_mode == 0 || call_depth > stepper_depth {
             return;
         };
+        for change in self.u_check_watches() {
+            let _ = writeln!(self.out, "       {change}");
+        }
         let mut contents = self.kernel.get(pc) as usize;
-        let is_builtin = if contents & BUILTIN_FLAG != 0 {
-            true
-        } else {
-            false
-        };
+        let is_builtin = contents & BUILTIN_FLAG != 0;
         contents &= ADDRESS_MASK;
         let mut c = 's';
 
         // Print the program counter address
-        print!("{:>5}: ", pc);
+        let _ = write!(self.out, "{:>5}: ", pc);
         // Indent based on call depth and print the stack
         for _i in 1..call_depth {
-            print!(" ");
+            let _ = write!(self.out, " ");
         }
         self.f_dot_s();
+        if self.kernel.get(self.step_rstack_ptr) != 0 {
+            let rstack = self.u_return_stack_string();
+            let _ = write!(self.out, "{rstack}");
+        }
 
-        match contents as i64 {
+        let text = match contents as i64 {
             VARIABLE | CONSTANT | DEFINITION => {
                 let val = self.kernel.get(pc - 1) as usize;
-                println!(" {} ", self.kernel.string_get(val))
+                format!(" {} ", self.kernel.string_get(val))
             }
-            LITERAL => println!(" {} ", self.kernel.get(pc + 1)),
+            LITERAL => format!(" {} ", self.kernel.get(pc + 1)),
             STRLIT => {
                 let val = self.kernel.get(pc + 1) as usize;
-                println!(" {} ", self.kernel.string_get(val))
+                format!(" {} ", self.kernel.string_get(val))
             }
-            BRANCH => {
-                let val = self.kernel.get(pc + 1);
-                println!(" BRANCH:{}", val)
-            }
-            BRANCH0 => println!(" BRANCH0:{}", self.kernel.get(pc + 1)),
-            ABORT => println!(" ABORT "),
-            EXIT => println!(" EXIT "),
-            BREAK => println!(" BREAK "),
-            EXEC => println!(" -> EXEC"),
+            BRANCH => format!(" BRANCH:{}", self.kernel.get(pc + 1)),
+            BRANCH0 => format!(" BRANCH0:{}", self.kernel.get(pc + 1)),
+            ABORT => " ABORT ".to_string(),
+            EXIT => " EXIT ".to_string(),
+            BREAK => " BREAK ".to_string(),
+            EXEC => " -> EXEC".to_string(),
             _ => {
                 if is_builtin {
-                    println!(" {} ", &self.kernel.get_builtin(contents).name);
+                    format!(" {} ", &self.kernel.get_builtin(contents).name)
                 } else {
                     // it's a word address: step-in about to occur
                     let val = self.kernel.get(contents - 1);
-                    println!(" ->{}", self.kernel.string_get(val as usize));
+                    format!(" ->{}", self.kernel.string_get(val as usize))
                 }
             }
-        }
-        match stepper_mode {
-            -1 => {
-                // step mode: get a character
-                print!("Step> ");
-                self.f_flush();
-                loop {
-                    self.f_key();
-                    c = self.kernel.pop() as u8 as char;
-                    if c != '\n' {
-                        break;
-                    }
+        };
+        let _ = writeln!(self.out, "{text}");
+        if stepper_mode == -1 {
+            // step mode: get a character
+            let _ = write!(self.out, "Step> ");
+            self.f_flush();
+            loop {
+                self.f_key();
+                c = self.kernel.pop() as u8 as char;
+                if c != '\n' {
+                    break;
                 }
             }
-            _ => {}
         }
         match c {
             't' => self.kernel.set(self.stepper_ptr, 1),
             'i' => self.kernel.incr(self.step_depth_ptr),
             'o' => self.kernel.decr(self.step_depth_ptr),
             'c' => self.kernel.set(self.stepper_ptr, 0),
-            'h' | '?' => println!(
-                "Stepper: 's' for show, 't' for trace, 'c' for continue, 'o' for step-out."
-            ),
+            'h' | '?' => {
+                let _ = writeln!(
+                    self.out,
+                    "Stepper: 's' for show, 't' for trace, 'c' for continue, 'o' for step-out."
+                );
+            }
             _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::ForthRuntime;
+
+    #[test]
+    fn test_breakpoint_flips_stepper() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.eval_str(": inner 1 + ;");
+        rt.eval_str(": outer inner ;");
+        rt.eval_str("(') outer");
+        let outer = rt.kernel.pop() as usize;
+        assert_eq!(rt.kernel.get(rt.stepper_ptr), 0);
+
+        rt.eval_str("break-at inner");
+        rt.u_check_breakpoint(outer, 1); // the DEFINITION opcode: no match
+        assert_eq!(rt.kernel.get(rt.stepper_ptr), 0);
+        rt.u_check_breakpoint(outer + 1, 3); // the call to inner
+        assert_eq!(rt.kernel.get(rt.stepper_ptr), -1);
+        assert_eq!(rt.kernel.get(rt.step_depth_ptr), 3);
+
+        rt.kernel.set(rt.stepper_ptr, 0);
+        rt.eval_str("clear-breaks");
+        rt.u_check_breakpoint(outer + 1, 1);
+        assert_eq!(rt.kernel.get(rt.stepper_ptr), 0);
+    }
+
+    #[test]
+    fn test_watch_reports_change() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.eval_str("watch base");
+        assert!(rt.u_check_watches().is_empty());
+        rt.kernel.set(rt.base_ptr, 16);
+        assert_eq!(rt.u_check_watches(), vec!["base: 10 -> 16".to_string()]);
+        assert!(rt.u_check_watches().is_empty()); // reported once only
+    }
+
+    #[test]
+    fn test_watch_during_trace() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.eval_str(": to-hex 16 base ! ;");
+        rt.eval_str(": to-bin 2 base ! ;");
+        rt.eval_str("watch base");
+        rt.kernel.set(rt.stepper_ptr, 1); // trace
+        rt.kernel.set(rt.step_depth_ptr, 1);
+        rt.eval_str("to-hex");
+        assert_eq!(rt.watches[0].1, 16); // the tracer saw the change
+        rt.kernel.set(rt.stepper_ptr, 0);
+        rt.eval_str("to-bin");
+        assert_eq!(rt.watches[0].1, 16); // not tracing, so not checked
+        assert_eq!(rt.u_check_watches(), vec!["base: 16 -> 2".to_string()]);
+    }
+
+    #[test]
+    fn test_profile_counts() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str(": add1 1 + ;");
+        rt.eval_str(": run 0 5 for add1 next ;");
+        rt.eval_str("run"); // not profiled
+        rt.kernel.pop();
+        assert!(rt.profile.is_empty());
+
+        rt.eval_str("profile-on");
+        rt.eval_str("run");
+        rt.eval_str("profile-off");
+        let iterations = rt.kernel.pop() as u64;
+        assert!(iterations > 0);
+        rt.eval_str("run"); // not counted either
+        rt.kernel.pop();
+
+        let report = rt.u_profile_report();
+        let count = |name: &str| report.iter().find(|(n, _)| n == name).map(|(_, c)| *c);
+        assert_eq!(count("add1"), Some(iterations));
+        assert_eq!(count("+"), Some(iterations));
+        assert_eq!(count("run"), None); // called from the interpreter, not dispatched
+        assert!(report.windows(2).all(|w| w[0].1 >= w[1].1));
+    }
+
+    #[test]
+    fn test_t_braces() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.kernel.push(77); // not part of the test
+        let run =
+            |rt: &mut ForthRuntime, line: &str| rt.with_captured_output(|rt| rt.eval_str(line));
+        assert_eq!(run(&mut rt, "t{ 2 3 + -> 5 }t"), "PASS\n");
+        assert_eq!((rt.test_passes, rt.test_failures), (1, 0));
+        assert_eq!(run(&mut rt, "t{ 1 2 swap -> 2 1 }t"), "PASS\n");
+        assert_eq!((rt.test_passes, rt.test_failures), (2, 0));
+        assert_eq!(
+            run(&mut rt, "t{ 2 3 * -> 5 }t"),
+            "FAIL: expected [5], got [6]\n"
+        );
+        assert_eq!((rt.test_passes, rt.test_failures), (2, 1));
+        assert_eq!(
+            run(&mut rt, "t{ 1 2 -> 1 }t"),
+            "FAIL: expected [1], got [1, 2]\n"
+        );
+        assert_eq!((rt.test_passes, rt.test_failures), (2, 2));
+        assert_eq!(rt.kernel.stack_len(), 1);
+        assert_eq!(rt.kernel.pop(), 77);
+    }
+
+    #[test]
+    fn test_breakpoint_on_builtin() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.eval_str(": add1 1 + ;");
+        rt.eval_str("(') add1");
+        let add1 = rt.kernel.pop() as usize;
+        rt.eval_str("break-at +");
+        rt.u_check_breakpoint(add1 + 3, 1); // DEFINITION LITERAL 1 +
+        assert_eq!(rt.kernel.get(rt.stepper_ptr), -1);
+    }
+}
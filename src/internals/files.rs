@@ -1,33 +1,180 @@
 ////////////////////////////
-use crossterm::event::{poll, read, Event, KeyCode, KeyEvent};
-/// File: src/files.rs
+use crossterm::event::{poll, read, Event, KeyCode, KeyEvent, KeyModifiers};
+/// File: src/internals/files.rs
 ///
 /// This module provides functionality for reading and writing files,
 ///      Read tokens from a file or stdin, one line at a time.
 ///      Return one space-delimited token at a time.
 ///      Cache the remainder of the line.
-use std::fs::File;
-use std::io::{self, BufRead, BufReader, Read};
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::rc::Rc;
 use std::time::Duration;
 
-use crate::internals::messages::{DebugLevel, Msg};
+use crate::internals::messages::Msg;
 
 // Check if a key is available. Only works properly in raw mode
 pub fn key_available() -> bool {
     poll(Duration::from_millis(0)).unwrap_or(false)
 }
 
+// Codes returned by KEY for keys that aren't characters, when special keys are turned on
+pub const KEY_UP: i64 = -1;
+pub const KEY_DOWN: i64 = -2;
+pub const KEY_LEFT: i64 = -3;
+pub const KEY_RIGHT: i64 = -4;
+pub const KEY_HOME: i64 = -5;
+pub const KEY_END: i64 = -6;
+pub const KEY_PAGE_UP: i64 = -7;
+pub const KEY_PAGE_DOWN: i64 = -8;
+pub const KEY_INSERT: i64 = -9;
+pub const KEY_F0: i64 = -20; // function key Fn gives KEY_F0 - n
+
+/// decode_key turns a raw mode key event into the code KEY returns, or None if the key is ignored
+///     Tab, Esc and the special keys are only decoded when special is true.
+///
+pub fn decode_key(code: KeyCode, modifiers: KeyModifiers, special: bool) -> Option<i64> {
+    let key = match code {
+        // Control keys (including Ctrl-C, ^C = 3) arrive as ASCII control codes
+        KeyCode::Char(c)
+            if modifiers.contains(KeyModifiers::CONTROL) && c.is_ascii_alphabetic() =>
+        {
+            (c.to_ascii_lowercase() as u8 - b'a' + 1) as i64
+        }
+        KeyCode::Char(c) => c as i64,
+        KeyCode::Enter => '\n' as i64,
+        KeyCode::Backspace => 8,      // ASCII backspace
+        KeyCode::Delete => 127,       // ASCII delete
+        _ if !special => return None, // Ignore other keys
+        KeyCode::Tab => 9,
+        KeyCode::Esc => 27,
+        KeyCode::Up => KEY_UP,
+        KeyCode::Down => KEY_DOWN,
+        KeyCode::Left => KEY_LEFT,
+        KeyCode::Right => KEY_RIGHT,
+        KeyCode::Home => KEY_HOME,
+        KeyCode::End => KEY_END,
+        KeyCode::PageUp => KEY_PAGE_UP,
+        KeyCode::PageDown => KEY_PAGE_DOWN,
+        KeyCode::Insert => KEY_INSERT,
+        KeyCode::F(n) => KEY_F0 - n as i64,
+        _ => return None,
+    };
+    Some(key)
+}
+
 #[derive(Debug, PartialEq)]
 pub enum FileMode {
-    RW, // -1 => Read-write
-    RO, //  0 => Read-only
-    WO, //  1 => Write-only
+    RW,     // -1 => Read-write
+    RO,     //  0 => Read-only
+    WO,     //  1 => Write-only
+    Append, //  2 => Write-only, adding to the end of the file
 }
 
 pub enum FType {
     Stdin, // Standard input
     File(File),
     BReader(BufReader<File>), // Buffered reader for file input
+    StringSource { text: String, pos: usize }, // Text held in memory, e.g. for EVALUATE
+}
+
+/// Where console output goes: the terminal, a file opened by redirect>, or a buffer for capturing it
+pub enum OutputSink {
+    Stdout,
+    File(BufWriter<File>),
+    #[cfg(test)]
+    Buffer(Vec<u8>),
+}
+
+impl Write for OutputSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputSink::Stdout => io::stdout().write(buf),
+            OutputSink::File(writer) => writer.write(buf),
+            #[cfg(test)]
+            OutputSink::Buffer(bytes) => bytes.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputSink::Stdout => io::stdout().flush(),
+            OutputSink::File(writer) => writer.flush(),
+            #[cfg(test)]
+            OutputSink::Buffer(_) => Ok(()),
+        }
+    }
+}
+
+/// Output is a handle on the console output sink, shared by the runtime and its messages,
+///     so redirecting or capturing output catches everything that is printed
+#[derive(Clone)]
+pub struct Output(Rc<RefCell<OutputSink>>);
+
+impl Output {
+    pub fn new() -> Output {
+        Output(Rc::new(RefCell::new(OutputSink::Stdout)))
+    }
+
+    /// replace makes sink the destination for output, returning the previous one
+    ///
+    pub fn replace(&self, sink: OutputSink) -> OutputSink {
+        let _ = self.0.borrow_mut().flush();
+        self.0.replace(sink)
+    }
+
+    /// write_line prints text and a newline. Used where only a shared reference is available
+    ///
+    pub fn write_line(&self, text: &str) {
+        let _ = writeln!(self.0.borrow_mut(), "{text}");
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Output")
+    }
+}
+
+/// TempPath names a file or directory in the system temp directory for a test, unique to
+///     this process. Whatever is there is removed when it goes out of scope, even if the test fails.
+#[cfg(test)]
+pub struct TempPath(pub std::path::PathBuf);
+
+#[cfg(test)]
+impl TempPath {
+    pub fn new(name: &str) -> TempPath {
+        TempPath(std::env::temp_dir().join(format!("f3-{}-{name}", std::process::id())))
+    }
+
+    /// name returns the path as a string, for passing to Forth words
+    ///
+    pub fn name(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        if self.0.is_dir() {
+            let _ = std::fs::remove_dir_all(&self.0);
+        } else {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
 }
 
 pub struct FileHandle {
@@ -35,6 +182,9 @@ pub struct FileHandle {
     pub file_mode: FileMode,
     pub file_size: usize,
     pub file_position: usize,
+    pub name: String,       // the file name, or stdin, for locating errors
+    pub line_number: usize, // lines read so far, so the current line while it's interpreted
+    pub msg: Msg,           // reports read errors
 }
 
 impl FileHandle {
@@ -43,12 +193,25 @@ impl FileHandle {
         msg_handler: Msg,
         mode: FileMode,
     ) -> Option<FileHandle> {
-        // Initialize a tokenizer.
-        let mut message_handler = Msg::new();
-        message_handler.set_level(DebugLevel::Warning);
         match file_path {
             Some(file_path) => {
-                let file = File::open(file_path);
+                let file = match mode {
+                    FileMode::RO => File::open(file_path),
+                    FileMode::WO => OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .truncate(true)
+                        .open(file_path),
+                    FileMode::RW => OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .create(true)
+                        .truncate(false)
+                        .open(file_path),
+                    FileMode::Append => {
+                        OpenOptions::new().append(true).create(true).open(file_path)
+                    }
+                };
                 match file {
                     Ok(file) => match mode {
                         FileMode::RO => Some(FileHandle {
@@ -56,17 +219,23 @@ impl FileHandle {
                             file_mode: FileMode::RO,
                             file_size: 0,
                             file_position: 0,
+                            name: file_path.display().to_string(),
+                            line_number: 0,
+                            msg: msg_handler,
                         }),
-                        FileMode::RW | FileMode::WO => Some(FileHandle {
+                        FileMode::RW | FileMode::WO | FileMode::Append => Some(FileHandle {
                             source: FType::File(file),
                             file_mode: mode,
                             file_size: 0,
                             file_position: 0,
+                            name: file_path.display().to_string(),
+                            line_number: 0,
+                            msg: msg_handler,
                         }),
                     },
                     Err(_) => {
                         msg_handler.error("Reader::new", "Unable to open file", Some(file_path));
-                        return None;
+                        None
                     }
                 }
             }
@@ -75,47 +244,93 @@ impl FileHandle {
                 file_mode: FileMode::RO,
                 file_size: 0,
                 file_position: 0,
+                name: "stdin".to_string(),
+                line_number: 0,
+                msg: msg_handler,
             }),
         }
     }
 
+    /// new_string makes a read-only handle that serves text from memory, one line at a time
+    ///
+    pub fn new_string(text: &str) -> FileHandle {
+        FileHandle {
+            source: FType::StringSource {
+                text: text.to_string(),
+                pos: 0,
+            },
+            file_mode: FileMode::RO,
+            file_size: text.len(),
+            file_position: 0,
+            name: "string".to_string(),
+            line_number: 0,
+            msg: Msg::new(),
+        }
+    }
+
+    /// get_line reads the next line, counting it
+    ///
     pub fn get_line(&mut self) -> Option<String> {
+        let line = self.u_read_line();
+        if line.is_some() {
+            self.line_number += 1;
+        }
+        line
+    }
+
+    fn u_read_line(&mut self) -> Option<String> {
         match &mut self.source {
             FType::Stdin => {
                 let mut new_line = String::new();
-                if std::io::stdin().read_line(&mut new_line).is_ok() {
-                    Some(new_line.trim_end().to_string())
-                } else {
-                    None
+                match std::io::stdin().read_line(&mut new_line) {
+                    Ok(_) => Some(new_line.trim_end().to_string()),
+                    Err(e) => {
+                        self.msg.error("get_line", &e.to_string(), None::<bool>);
+                        None
+                    }
                 }
             }
             FType::BReader(ref mut br) => {
                 let mut new_line = String::new();
                 match br.read_line(&mut new_line) {
                     Ok(n) if n > 0 => Some(new_line.trim_end().to_string()),
-                    _ => None,
+                    Ok(_) => None, // end of file
+                    Err(e) => {
+                        self.msg.error("get_line", &e.to_string(), None::<bool>);
+                        None
+                    }
                 }
             }
             FType::File(_) => None, // Files don't support line reading
+            FType::StringSource { text, pos } => {
+                if *pos >= text.len() {
+                    return None;
+                }
+                let rest = &text[*pos..];
+                let line = match rest.find('\n') {
+                    Some(end) => &rest[..end],
+                    None => rest,
+                };
+                *pos += (line.len() + 1).min(rest.len());
+                self.file_position = *pos;
+                Some(line.trim_end().to_string())
+            }
         }
     }
 
-    pub fn read_char(&mut self) -> Option<char> {
+    /// read_key reads a key or character, returning its code
+    ///     In raw mode, special keys give the negative KEY_ codes if special is true.
+    ///
+    pub fn read_key(&mut self, special: bool) -> Option<i64> {
         match &mut self.source {
             FType::Stdin => {
                 // Check if we're in raw mode by trying to poll for events
                 if poll(Duration::from_millis(0)).unwrap_or(false) {
                     // Raw mode - use crossterm event system
                     match read() {
-                        Ok(Event::Key(KeyEvent { code, .. })) => {
-                            match code {
-                                KeyCode::Char(c) => Some(c),
-                                KeyCode::Enter => Some('\n'),
-                                KeyCode::Backspace => Some(8 as char), // ASCII backspace
-                                KeyCode::Delete => Some(127 as char),  // ASCII delete
-                                _ => None,                             // Ignore other keys
-                            }
-                        }
+                        Ok(Event::Key(KeyEvent {
+                            code, modifiers, ..
+                        })) => decode_key(code, modifiers, special),
                         _ => None, // Ignore non-key events
                     }
                 } else {
@@ -124,7 +339,7 @@ impl FileHandle {
                     let mut handle = io::stdin().lock();
                     let bytes_read = handle.read(&mut buf);
                     match bytes_read {
-                        Ok(_size) => Some(buf[0] as char),
+                        Ok(_size) => Some(buf[0] as i64),
                         Err(_) => None,
                     }
                 }
@@ -139,11 +354,17 @@ impl FileHandle {
                 match br.read(&mut buf) {
                     Ok(1) => {
                         self.file_position += 1;
-                        Some(buf[0] as char)
+                        Some(buf[0] as i64)
                     }
                     _ => None,
                 }
             }
+            FType::StringSource { text, pos } => {
+                let c = text[*pos..].chars().next()?;
+                *pos += c.len_utf8();
+                self.file_position = *pos;
+                Some(c as i64)
+            }
         }
     }
 
@@ -197,16 +418,100 @@ mod tests {
         println!("Read line: {:?}", line.unwrap());
     }
 
+    #[test]
+    fn test_get_line_read_error() {
+        // a directory can be opened, but reading it fails, which is reported, unlike end of file
+        let out = Output::new();
+        out.replace(OutputSink::Buffer(Vec::new()));
+        let buf = &PathBuf::from("src");
+        let msg = Msg::with_output(out.clone());
+        let mut handle = FileHandle::new_file(Some(buf), msg, FileMode::RO).unwrap();
+        assert!(handle.get_line().is_none());
+        assert_eq!(handle.line_number, 0);
+        let temp = TempPath::new("empty.txt");
+        std::fs::write(&temp.0, "").unwrap();
+        let msg = Msg::with_output(out.clone());
+        let mut empty = FileHandle::new_file(Some(&temp.0), msg, FileMode::RO).unwrap();
+        assert!(empty.get_line().is_none());
+        let OutputSink::Buffer(bytes) = out.replace(OutputSink::Stdout) else {
+            panic!("output was not captured");
+        };
+        let text = String::from_utf8_lossy(&bytes);
+        assert!(text.starts_with("ERROR: get_line: "), "{text}");
+        assert_eq!(text.lines().count(), 1);
+    }
+
+    #[test]
+    fn test_string_source() {
+        let mut handle = FileHandle::new_string("one\ntwo  \né");
+        assert_eq!(handle.get_line(), Some("one".to_string()));
+        assert_eq!(handle.read_key(false), Some('t' as i64));
+        assert_eq!(handle.get_line(), Some("wo".to_string()));
+        assert_eq!(handle.read_key(false), Some('é' as i64));
+        assert_eq!(handle.get_line(), None);
+        assert_eq!(handle.read_key(false), None);
+    }
+
     #[test]
     fn test_read_char() {
         // This test requires interactive input
         let mut handle = FileHandle::new_file(None, Msg::new(), FileMode::RO).unwrap();
         println!("Please enter a character:");
-        let ch = handle.read_char();
+        let ch = handle.read_key(false);
         assert!(ch.is_some());
         println!("Read character: {:?}", ch.unwrap());
     }
 
+    #[test]
+    fn test_decode_special_keys() {
+        let none = KeyModifiers::NONE;
+        assert_eq!(decode_key(KeyCode::Up, none, true), Some(KEY_UP));
+        assert_eq!(decode_key(KeyCode::F(3), none, true), Some(KEY_F0 - 3));
+        assert_eq!(decode_key(KeyCode::Up, none, false), None);
+        assert_eq!(
+            decode_key(KeyCode::Char('c'), KeyModifiers::CONTROL, false),
+            Some(3)
+        );
+        assert_eq!(decode_key(KeyCode::Char('é'), none, true), Some('é' as i64));
+    }
+
+    #[test]
+    fn test_append_mode() {
+        let temp = TempPath::new("append.log");
+        let path = &temp.0;
+        for line in ["first\n", "second\n"] {
+            let mut handle =
+                FileHandle::new_file(Some(path), Msg::new(), FileMode::Append).unwrap();
+            assert_eq!(handle.file_mode(), &FileMode::Append);
+            if let FType::File(ref mut f) = handle.source {
+                f.write_all(line.as_bytes()).unwrap();
+            }
+        }
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "first\nsecond\n");
+    }
+
+    #[test]
+    fn test_write_modes() {
+        let temp = TempPath::new("write.txt");
+        let path = &temp.0;
+        let _ = std::fs::remove_file(path);
+        let mut handle = FileHandle::new_file(Some(path), Msg::new(), FileMode::WO).unwrap();
+        assert_eq!(handle.file_mode(), &FileMode::WO);
+        if let FType::File(ref mut f) = handle.source {
+            f.write_all(b"written").unwrap();
+        }
+        drop(handle);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "written");
+        // read-write keeps the existing contents
+        let mut handle = FileHandle::new_file(Some(path), Msg::new(), FileMode::RW).unwrap();
+        assert_eq!(handle.file_mode(), &FileMode::RW);
+        if let FType::File(ref mut f) = handle.source {
+            f.write_all(b"W").unwrap();
+        }
+        drop(handle);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "Written");
+    }
+
     #[test]
     fn test_file_position() {
         let handle = FileHandle::new_file(None, Msg::new(), FileMode::RO).unwrap();
@@ -1,9 +1,24 @@
 // General-purpose builtin words
 
-use crate::kernel::DATA_SIZE;
-use crate::runtime::ForthRuntime;
+use crate::internals::messages::ForthError;
+use crate::kernel::{ALLOC_START, BUF_SIZE, DATA_SIZE, RET_START, STACK_START, STRING_SIZE};
+use crate::runtime::{ForthRuntime, ARGBUF_START, FALSE, MAX_LOCALS, TIMERS, TRUE};
+use std::cmp::Ordering;
+use std::io::Write;
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// u_digit_value returns the value of character c as a digit in the given base, if it is one
+///     Letters of either case stand for the digits from 10 upwards.
+///
+pub fn u_digit_value(c: u8, base: i64) -> Option<i64> {
+    let value = (c as char).to_digit(36)? as i64;
+    if value < base {
+        Some(value)
+    } else {
+        None
+    }
+}
 
 /// u_is_integer determines whether a string parses correctly as an integer
 ///
@@ -11,6 +26,155 @@ pub fn u_is_integer(s: &str) -> bool {
     s.parse::<i64>().is_ok()
 }
 
+/// u_seed_state turns a seed into a generator state, mixing it so that nearby seeds give unrelated
+///     sequences. xorshift gets stuck at zero, so a zero state is replaced.
+///
+pub fn u_seed_state(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^= z >> 31;
+    if z == 0 {
+        0x9E3779B97F4A7C15
+    } else {
+        z
+    }
+}
+
+/// u_civil_from_days converts a count of days since 1970-01-01 to a (year, month, day) date
+///     This is the proleptic Gregorian calendar, using the era-based method from Howard Hinnant's
+///     date algorithms, so it is exact for dates before the epoch too.
+///
+pub fn u_civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097); // day of era, 0..146096
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365; // year of era, 0..399
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100); // day of year, starting in March
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// CRC32_TABLE holds the CRC of each byte value, for the reflected polynomial 0xEDB88320
+///
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+/// u_crc32 returns the CRC-32 of bytes, as used by zip, PNG and Ethernet
+///
+pub fn u_crc32(bytes: impl IntoIterator<Item = u8>) -> u32 {
+    let crc = bytes.into_iter().fold(0xFFFFFFFF, |crc, b| {
+        CRC32_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8)
+    });
+    crc ^ 0xFFFFFFFF
+}
+
+/// u_hex_encode returns the lowercase hex digits for bytes, two per byte
+///
+pub fn u_hex_encode(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    Ok(bytes
+        .iter()
+        .flat_map(|b| format!("{b:02x}").into_bytes())
+        .collect())
+}
+
+/// u_hex_decode turns pairs of hex digits, in either case, back into bytes
+///
+pub fn u_hex_decode(text: &[u8]) -> Result<Vec<u8>, String> {
+    if !text.len().is_multiple_of(2) {
+        return Err("Hex text must have an even length".to_string());
+    }
+    text.chunks(2)
+        .map(
+            |pair| match (u_digit_value(pair[0], 16), u_digit_value(pair[1], 16)) {
+                (Some(high), Some(low)) => Ok((high * 16 + low) as u8),
+                _ => Err(format!(
+                    "Not a hex digit pair: {}",
+                    String::from_utf8_lossy(pair)
+                )),
+            },
+        )
+        .collect()
+}
+
+/// The standard Base64 alphabet
+const BASE64_DIGITS: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// u_base64_encode returns the standard Base64 encoding of bytes, padded with = to a multiple of 4
+///
+pub fn u_base64_encode(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut text = Vec::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, b)| acc | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64_DIGITS[(group >> (18 - 6 * i) & 0x3F) as usize]);
+            } else {
+                text.push(b'=');
+            }
+        }
+    }
+    Ok(text)
+}
+
+/// u_base64_decode turns Base64 text back into bytes
+///     Whitespace is skipped, so encoded text can be split across lines. The text without it
+///     must be a multiple of 4 characters long, with = only as padding at the end.
+///
+pub fn u_base64_decode(text: &[u8]) -> Result<Vec<u8>, String> {
+    let text: Vec<u8> = text
+        .iter()
+        .copied()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect();
+    if !text.len().is_multiple_of(4) {
+        return Err("Base64 text must be a multiple of 4 characters".to_string());
+    }
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    for (n, chunk) in text.chunks(4).enumerate() {
+        let last = n == text.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return Err("Misplaced Base64 padding".to_string());
+        }
+        let mut group = 0u32;
+        for &c in &chunk[..4 - padding] {
+            let value = BASE64_DIGITS
+                .iter()
+                .position(|&d| d == c)
+                .ok_or_else(|| format!("Not a Base64 character: {}", c as char))?;
+            group = group << 6 | value as u32;
+        }
+        group <<= 6 * padding as u32;
+        bytes.extend_from_slice(&group.to_be_bytes()[1..4 - padding]);
+    }
+    Ok(bytes)
+}
+
 impl ForthRuntime {
     /// Basic Forth operations on the stack.
     ///
@@ -31,7 +195,9 @@ impl ForthRuntime {
     }
 
     pub fn f_divide(&mut self) {
-        self.kernel.pop2_push1("/", |a, b| a / b);
+        if self.u_nonzero_divisor("/") {
+            self.kernel.pop2_push1("/", |a, b| a.wrapping_div(b));
+        }
     }
 
     pub fn f_lshift(&mut self) {
@@ -43,7 +209,22 @@ impl ForthRuntime {
     }
 
     pub fn f_mod(&mut self) {
-        self.kernel.pop2_push1("mod", |a, b| a % b);
+        if self.u_nonzero_divisor("mod") {
+            self.kernel.pop2_push1("mod", |a, b| a.wrapping_rem(b));
+        }
+    }
+
+    /// u_nonzero_divisor checks the divisor on top of the stack, aborting if it is zero
+    ///     An underflow is left for the stack check in pop2_push1 to report.
+    ///
+    fn u_nonzero_divisor(&mut self, word: &str) -> bool {
+        if self.kernel.stack_len() >= 2 && self.kernel.top() == 0 {
+            self.msg.error(word, "Division by zero", None::<bool>);
+            self.set_error(ForthError::DivByZero);
+            self.f_abort();
+            return false;
+        }
+        true
     }
 
     pub fn f_less(&mut self) {
@@ -51,11 +232,44 @@ impl ForthRuntime {
             .pop2_push1("<", |a, b| if a < b { -1 } else { 0 });
     }
 
+    pub fn f_greater(&mut self) {
+        self.kernel
+            .pop2_push1(">", |a, b| if a > b { -1 } else { 0 });
+    }
+
+    pub fn f_less_equal(&mut self) {
+        self.kernel
+            .pop2_push1("<=", |a, b| if a <= b { -1 } else { 0 });
+    }
+
+    pub fn f_greater_equal(&mut self) {
+        self.kernel
+            .pop2_push1(">=", |a, b| if a >= b { -1 } else { 0 });
+    }
+
     pub fn f_equal(&mut self) {
         self.kernel
             .pop2_push1("=", |a, b| if a == b { -1 } else { 0 });
     }
 
+    pub fn f_not_equal(&mut self) {
+        self.kernel
+            .pop2_push1("<>", |a, b| if a != b { -1 } else { 0 });
+    }
+
+    /// within ( n lo hi -- flag ) true if lo <= n < hi, comparing n - lo and hi - lo as unsigned
+    ///     As in the standard, this also works when the range wraps around, i.e. hi < lo.
+    ///
+    pub fn f_within(&mut self) {
+        if self.kernel.stack_check(3, "within") {
+            let hi = self.kernel.pop();
+            let lo = self.kernel.pop();
+            let n = self.kernel.pop();
+            let inside = (n.wrapping_sub(lo) as u64) < (hi.wrapping_sub(lo) as u64);
+            self.kernel.push(if inside { TRUE } else { FALSE });
+        }
+    }
+
     pub fn f_0equal(&mut self) {
         self.kernel
             .pop1_push1("0=", |a| if a == 0 { -1 } else { 0 });
@@ -65,12 +279,43 @@ impl ForthRuntime {
         self.kernel.pop1_push1("0<", |a| if a < 0 { -1 } else { 0 });
     }
 
+    /// 0<> ( n -- flag ) true if n is not zero
+    ///
+    pub fn f_0not_equal(&mut self) {
+        self.kernel
+            .pop1_push1("0<>", |a| if a != 0 { TRUE } else { FALSE });
+    }
+
+    /// not ( flag -- flag' ) logical negation: zero becomes TRUE, anything else FALSE
+    ///     Unlike invert, not 1 is FALSE, because 1 is a true flag.
+    ///
+    pub fn f_not(&mut self) {
+        self.kernel
+            .pop1_push1("not", |a| if a == 0 { TRUE } else { FALSE });
+    }
+
+    /// invert ( n -- ~n ) flips every bit of n
+    ///
+    pub fn f_invert(&mut self) {
+        self.kernel.pop1_push1("invert", |a| !a);
+    }
+
     pub fn f_dup(&mut self) {
         if self.kernel.stack_check(1, "dup") {
             let top = self.kernel.top();
             self.kernel.push(top);
         }
     }
+    /// ?dup ( n -- n n | 0 ) duplicates the top of stack only if it's nonzero
+    ///
+    pub fn f_q_dup(&mut self) {
+        if self.kernel.stack_check(1, "?dup") {
+            let top = self.kernel.top();
+            if top != 0 {
+                self.kernel.push(top);
+            }
+        }
+    }
     pub fn f_drop(&mut self) {
         if self.kernel.stack_check(1, "drop") {
             self.kernel.pop();
@@ -105,20 +350,22 @@ impl ForthRuntime {
     }
     pub fn f_pick(&mut self) {
         if self.kernel.stack_check(1, "pick") {
-            let n = self.kernel.pop() as usize;
-            if self.kernel.stack_check(n + 1, "pick") {
+            if let Some(n) = self.u_stack_index("pick") {
                 let val = self.kernel.peek(n);
                 self.kernel.push(val);
             }
         }
     }
+
+    /// roll ( x_n .. x_0 n -- x_n-1 .. x_0 x_n ) moves the nth item from the top up to the top
+    ///     1 roll is swap and 2 roll is rot. 0 roll does nothing.
+    ///
     pub fn f_roll(&mut self) {
         if self.kernel.stack_check(1, "roll") {
-            let n = self.kernel.pop() as usize;
-            if n == 0 {
-                return;
-            } // 0 roll is a no-op
-            if self.kernel.stack_check(n + 1, "roll") {
+            if let Some(n) = self.u_stack_index("roll") {
+                if n == 0 {
+                    return; // 0 roll is a no-op
+                }
                 // Save the nth value from the top
                 let val = self.kernel.peek(n);
                 // Shift all items above it down by one
@@ -132,6 +379,50 @@ impl ForthRuntime {
             }
         }
     }
+
+    /// nrot ( x_n .. x_1 n -- x_1 x_n .. x_2 ) moves the top item down under the n - 1 items below it
+    ///     2 nrot is swap and 3 nrot is -rot. 0 nrot and 1 nrot do nothing.
+    ///
+    pub fn f_nrot(&mut self) {
+        if self.kernel.stack_check(1, "nrot") {
+            let n = self.kernel.pop();
+            if n < 0 {
+                self.msg
+                    .error("nrot", "Count must not be negative", Some(n));
+                self.set_error(ForthError::BadIndex);
+                self.f_abort();
+                return;
+            }
+            if self.kernel.stack_check(n as usize, "nrot") {
+                // items[0] is x_1, the top of the stack
+                let items: Vec<i64> = (0..n).map(|_| self.kernel.pop()).collect();
+                if let Some((&top, rest)) = items.split_first() {
+                    self.kernel.push(top);
+                    for &item in rest.iter().rev() {
+                        self.kernel.push(item);
+                    }
+                }
+            }
+        }
+    }
+
+    /// u_stack_index pops the index for pick or roll, checking that it names an item on the stack
+    ///     A negative index aborts; one beyond the depth is a stack underflow.
+    ///
+    fn u_stack_index(&mut self, word: &str) -> Option<usize> {
+        let n = self.kernel.pop();
+        if n < 0 {
+            self.msg.error(word, "Index must not be negative", Some(n));
+            self.set_error(ForthError::BadIndex);
+            self.f_abort();
+            return None;
+        }
+        if self.kernel.stack_check(n as usize + 1, word) {
+            Some(n as usize)
+        } else {
+            None
+        }
+    }
     pub fn f_and(&mut self) {
         if self.kernel.stack_check(2, "and") {
             let a = self.kernel.pop();
@@ -149,15 +440,20 @@ impl ForthRuntime {
     }
 
     /// @ (get) ( a -- n ) loads the value at address a onto the stack
+    ///     Reading unused space warns, since it's usually a string address used by mistake.
     pub fn f_get(&mut self) {
         if self.kernel.stack_check(1, "@") {
-            let addr = self.kernel.pop() as usize;
-            if self.kernel.addr_check(addr) {
+            let addr = self.kernel.pop();
+            if let Some(addr) = self.u_data_address("@", addr) {
+                if self.u_unused_data(addr) {
+                    self.msg.warning(
+                        "@",
+                        "Address is in unused data space. Is it a string address? Use c@ for string space",
+                        Some(addr),
+                    );
+                }
                 let val = self.kernel.get(addr);
                 self.kernel.push(val);
-            } else {
-                self.msg.error("@", "Address out of range", Some(addr));
-                self.f_abort();
             }
         }
     }
@@ -166,35 +462,179 @@ impl ForthRuntime {
     ///
     pub fn f_store(&mut self) {
         if self.kernel.stack_check(2, "!") {
-            let addr = self.kernel.pop() as usize;
+            let addr = self.kernel.pop();
+            let value = self.kernel.pop();
+            if let Some(addr) = self.u_data_address("!", addr) {
+                if self.u_writable("!", addr) {
+                    self.kernel.set(addr, value);
+                }
+            }
+        }
+    }
+
+    /// b@ ( buf i -- n ) fetches cell i of a buffer made by buffer:, checking the index
+    ///
+    pub fn f_b_get(&mut self) {
+        if self.kernel.stack_check(2, "b@") {
+            let i = self.kernel.pop();
+            let buf = self.kernel.pop();
+            if let Some(addr) = self.u_buffer_element("b@", buf, i) {
+                let val = self.kernel.get(addr);
+                self.kernel.push(val);
+            }
+        }
+    }
+
+    /// b! ( n buf i -- ) stores n in cell i of a buffer made by buffer:, checking the index
+    ///
+    pub fn f_b_store(&mut self) {
+        if self.kernel.stack_check(3, "b!") {
+            let i = self.kernel.pop();
+            let buf = self.kernel.pop();
             let value = self.kernel.pop();
-            if addr < DATA_SIZE {
+            if let Some(addr) = self.u_buffer_element("b!", buf, i) {
                 self.kernel.set(addr, value);
-            } else {
-                self.msg.error("@", "Address out of range", Some(addr));
-                self.f_abort();
             }
         }
     }
 
+    /// u_buffer_element returns the address of cell i of the buffer at buf, which has the same
+    ///     layout as an array, so the index is checked by u_array_element.
+    ///
+    fn u_buffer_element(&mut self, word: &str, buf: i64, i: i64) -> Option<usize> {
+        let buf = self.u_data_address(word, buf)?;
+        let length = self.kernel.get(buf);
+        if length < 0 || !self.kernel.addr_check(buf + length as usize) {
+            self.msg.error(word, "Not a buffer", Some(buf));
+            self.set_error(ForthError::BadAddress);
+            self.f_abort();
+            return None;
+        }
+        self.kernel.push(i);
+        self.u_array_element(word, buf)
+    }
+
+    /// watermark ( -- a ) pushes the address below which ! and , refuse to write
+    ///
+    pub fn f_watermark(&mut self) {
+        self.kernel.push(self.watermark as i64);
+    }
+
+    /// set-watermark ( a -- ) protects data space below a. 0 turns protection off
+    ///
+    pub fn f_set_watermark(&mut self) {
+        if self.kernel.stack_check(1, "set-watermark") {
+            let addr = self.kernel.pop();
+            if let Some(addr) = self.u_data_address("set-watermark", addr) {
+                self.watermark = addr;
+            }
+        }
+    }
+
+    /// u_writable checks that addr is above the watermark, or is the value of one of the system
+    ///     variables a program may set. Otherwise it reports an error and aborts.
+    ///     The dictionary pointers (s-here, here, context, last) are only moved by the compiler
+    ///     and (set-dictionary).
+    ///
+    pub fn u_writable(&mut self, word: &str, addr: usize) -> bool {
+        if addr >= self.watermark || self.u_settable_variables().contains(&addr) {
+            return true;
+        }
+        self.msg
+            .error(word, "Address is in the protected system area", Some(addr));
+        self.set_error(ForthError::Protected);
+        self.f_abort();
+        false
+    }
+
+    /// u_settable_variables lists the value cells of the system variables that ! may write
+    ///
+    fn u_settable_variables(&self) -> [usize; 14] {
+        [
+            self.base_ptr,
+            self.state_ptr,
+            self.abort_ptr,
+            self.hld_ptr,
+            self.tib_size_ptr,
+            self.tib_in_ptr,
+            self.stepper_ptr,
+            self.step_depth_ptr,
+            self.step_rstack_ptr,
+            self.prompt_ptr,
+            self.emit_vector_ptr,
+            self.key_vector_ptr,
+            self.optimize_ptr,
+            self.tail_calls_ptr,
+        ]
+    }
+
+    /// u_data_address checks that an address for @ or ! is in data space, aborting if not
+    ///
+    fn u_data_address(&mut self, word: &str, addr: i64) -> Option<usize> {
+        if addr < 0 || !self.kernel.addr_check(addr as usize) {
+            self.msg.error(
+                word,
+                "Address out of range for data space. Use c@ and c! for string space",
+                Some(addr),
+            );
+            self.set_error(ForthError::BadAddress);
+            self.f_abort();
+            return None;
+        }
+        Some(addr as usize)
+    }
+
+    /// u_unused_data is true for data space that holds nothing: above HERE and below the stack,
+    ///     or below the return stack
+    ///
+    fn u_unused_data(&mut self, addr: usize) -> bool {
+        (addr >= self.here() && addr < self.kernel.get_stack_ptr())
+            || (addr >= ALLOC_START && addr < self.kernel.get_return_ptr())
+    }
+
     /// >r ( n -- ) Pops the stack, placing the value on the return stack
     ///
     pub fn f_to_r(&mut self) {
         if self.kernel.stack_check(1, ">r") {
             let val = self.kernel.pop();
-            self.kernel.push_r(val);
+            self.u_push_r(">r", val);
         }
     }
 
     /// r> ( -- n ) Pops the return stack, pushing the value to the calculation stack
     ///
     pub fn f_r_from(&mut self) {
-        if self.kernel.stack_check_r(1, "r>") {
-            let val = self.kernel.pop_r();
+        if let Some(val) = self.u_pop_r("r>") {
             self.kernel.push(val);
         }
     }
 
+    /// u_push_r pushes val onto the return stack, aborting if it would run into the data area
+    ///     Returns false if the push failed.
+    ///
+    pub fn u_push_r(&mut self, word: &str, val: i64) -> bool {
+        if self.kernel.get_return_ptr() <= ALLOC_START {
+            self.msg.error(word, "Return stack overflow", None::<bool>);
+            self.set_error(ForthError::ReturnStack);
+            self.f_abort();
+            return false;
+        }
+        self.kernel.push_r(val);
+        true
+    }
+
+    /// u_pop_r pops the return stack, aborting if it is empty
+    ///
+    pub fn u_pop_r(&mut self, word: &str) -> Option<i64> {
+        if self.kernel.get_return_ptr() >= RET_START {
+            self.msg.error(word, "Return stack underflow", None::<bool>);
+            self.set_error(ForthError::ReturnStack);
+            self.f_abort();
+            return None;
+        }
+        Some(self.kernel.pop_r())
+    }
+
     /// r@ ( -- n ) Gets the top value from the return stack, pushing the value to the calculation stack
     ///
     pub fn f_r_get(&mut self) {
@@ -202,31 +642,227 @@ impl ForthRuntime {
         self.kernel.push(val);
     }
 
+    /// 2>r ( a b -- ) ( R: -- a b ) Moves a pair to the return stack, leaving b on top
+    ///
+    pub fn f_2to_r(&mut self) {
+        if self.kernel.stack_check(2, "2>r") {
+            let b = self.kernel.pop();
+            let a = self.kernel.pop();
+            if self.u_push_r("2>r", a) {
+                self.u_push_r("2>r", b);
+            }
+        }
+    }
+
+    /// 2r> ( -- a b ) ( R: a b -- ) Moves a pair from the return stack, undoing 2>r
+    ///
+    pub fn f_2r_from(&mut self) {
+        if let Some(b) = self.u_pop_r("2r>") {
+            if let Some(a) = self.u_pop_r("2r>") {
+                self.kernel.push(a);
+                self.kernel.push(b);
+            }
+        }
+    }
+
+    /// 2r@ ( -- a b ) ( R: a b -- a b ) Copies the pair on top of the return stack
+    ///
+    pub fn f_2r_get(&mut self) {
+        let rp = self.kernel.get_return_ptr();
+        if rp + 2 > RET_START {
+            self.msg
+                .error("2r@", "Return stack underflow", None::<bool>);
+            self.set_error(ForthError::ReturnStack);
+            self.f_abort();
+            return;
+        }
+        let b = self.kernel.get(rp);
+        let a = self.kernel.get(rp + 1);
+        self.kernel.push(a);
+        self.kernel.push(b);
+    }
+
     pub fn f_dot_rs(&mut self) {
-        self.kernel.print_return_stack();
+        let text = self.kernel.return_stack_text();
+        let _ = write!(self.out, "{text}");
     }
 
     /// i ( -- n ) Pushes the current loop index to the calculation stack
     ///
     pub fn f_i(&mut self) {
-        let val = self.kernel.get(self.kernel.get_return_ptr());
-        self.kernel.push(val);
+        self.u_loop_index("i", 0);
     }
 
     /// j ( -- n ) Pushes the second level (outer) loop index to the calculation stack
     ///
     pub fn f_j(&mut self) {
-        let val = self.kernel.get(self.kernel.get_return_ptr() + 1);
-        self.kernel.push(val);
+        self.u_loop_index("j", 1);
+    }
+
+    /// u_loop_index pushes the index of the loop depth levels out from the innermost one
+    ///     Loops record where their index lives on the return stack, so the index is found
+    ///     even from a word called inside the loop, with its return address in the way.
+    ///
+    fn u_loop_index(&mut self, word: &str, depth: usize) {
+        self.u_release_loops();
+        match self.loop_frames.iter().rev().nth(depth) {
+            Some(&addr) => {
+                let val = self.kernel.get(addr);
+                self.kernel.push(val);
+            }
+            None => {
+                self.msg.error(word, "Not inside a loop", None::<bool>);
+                self.set_error(ForthError::CompileError);
+                self.f_abort();
+            }
+        }
+    }
+
+    /// u_release_loops forgets loops whose index has been popped off the return stack
+    ///     e.g. by r> drop in a FOR loop that is left early
+    ///
+    fn u_release_loops(&mut self) {
+        let rp = self.kernel.get_return_ptr();
+        while self.loop_frames.last().is_some_and(|&addr| addr < rp) {
+            self.loop_frames.pop();
+        }
+    }
+
+    /// (for) ( n -- ) ( R: -- n ) Starts a FOR loop, which counts n down to 1
+    ///
+    pub fn f_for_p(&mut self) {
+        if self.kernel.stack_check(1, "(for)") {
+            let count = self.kernel.pop();
+            self.u_release_loops();
+            if self.u_push_r("(for)", count) {
+                self.loop_frames.push(self.kernel.get_return_ptr());
+            }
+        }
+    }
+
+    /// (next) ( -- flag ) Decrements the FOR count, ending the loop and returning TRUE when it reaches zero
+    ///
+    pub fn f_next_p(&mut self) {
+        if self.kernel.stack_check_r(1, "(next)") {
+            let rp = self.kernel.get_return_ptr();
+            let count = self.kernel.get(rp).wrapping_sub(1);
+            if count == 0 {
+                self.kernel.pop_r();
+                self.loop_frames.pop();
+                self.kernel.push(TRUE);
+            } else {
+                self.kernel.set(rp, count);
+                self.kernel.push(FALSE);
+            }
+        }
+    }
+
+    /// (do) ( limit start -- ) ( R: -- limit index ) Starts a DO loop
+    ///     The limit and the index are kept on the return stack, with the index on top.
+    ///
+    pub fn f_do_p(&mut self) {
+        if self.kernel.stack_check(2, "(do)") {
+            let start = self.kernel.pop();
+            let limit = self.kernel.pop();
+            self.u_release_loops();
+            if self.u_push_r("(do)", limit) && self.u_push_r("(do)", start) {
+                self.loop_frames.push(self.kernel.get_return_ptr());
+            }
+        }
+    }
+
+    /// (?do) ( limit start -- flag ) Starts a DO loop, unless the limit and start are equal
+    ///     Returns FALSE, leaving nothing on the return stack, when the loop should be skipped
+    ///
+    pub fn f_q_do_p(&mut self) {
+        if self.kernel.stack_check(2, "(?do)") {
+            if self.kernel.peek(0) == self.kernel.peek(1) {
+                self.kernel.pop();
+                self.kernel.pop();
+                self.kernel.push(FALSE);
+            } else {
+                self.f_do_p();
+                self.kernel.push(TRUE);
+            }
+        }
+    }
+
+    /// (loop) ( -- flag ) Increments the loop index, returning TRUE when it reaches the limit
+    ///
+    pub fn f_loop_p(&mut self) {
+        self.kernel.push(1);
+        self.f_plus_loop_p();
+    }
+
+    /// (+loop) ( n -- flag ) Adds n to the loop index, returning TRUE when the index
+    ///     crosses the boundary between limit-1 and limit, in either direction
+    ///
+    pub fn f_plus_loop_p(&mut self) {
+        if self.kernel.stack_check(1, "(+loop)") && self.kernel.stack_check_r(2, "(+loop)") {
+            let step = self.kernel.pop();
+            let rp = self.kernel.get_return_ptr();
+            let index = self.kernel.get(rp);
+            let limit = self.kernel.get(rp + 1);
+            let before = index as i128 - limit as i128;
+            let after = before + step as i128;
+            self.kernel.set(rp, index.wrapping_add(step));
+            let done = step != 0 && ((before < 0) != (after < 0));
+            self.kernel.push(if done { TRUE } else { FALSE });
+        }
+    }
+
+    /// unloop ( -- ) ( R: limit index -- ) Discards the parameters of the current DO loop
+    ///
+    pub fn f_unloop(&mut self) {
+        if self.kernel.stack_check_r(2, "unloop") {
+            self.kernel.pop_r();
+            self.kernel.pop_r();
+            self.loop_frames.pop();
+        }
     }
 
     /// c@ - ( s -- c ) read a character from a string address and place on the stack
     ///
     pub fn f_c_get(&mut self) {
         if self.kernel.stack_check(1, "c@") {
-            let s_address = self.kernel.pop() as usize;
-            let c = self.kernel.byte_get(s_address);
-            self.kernel.push(c as i64);
+            let s_address = self.kernel.pop();
+            if self.u_string_range("c@", s_address, 1) {
+                let c = self.kernel.byte_get(s_address as usize);
+                self.kernel.push(c as i64);
+            }
+        }
+    }
+
+    /// utf8@ ( s u -- c n ) decodes the first UTF-8 character of the u bytes at s
+    ///     Leaves the character and the number of bytes it takes. A byte that doesn't start
+    ///     a complete character gives U+FFFD and a length of 1, so a loop always moves on.
+    ///
+    pub fn f_utf8_get(&mut self) {
+        if self.kernel.stack_check(2, "utf8@") {
+            let len = self.kernel.pop();
+            let addr = self.kernel.pop();
+            if self.u_string_range("utf8@", addr, len.min(4)) {
+                let bytes = self
+                    .kernel
+                    .string_slice(addr as usize, len.clamp(0, 4) as usize);
+                let width = match bytes.first() {
+                    Some(b) if *b < 0x80 => 1,
+                    Some(b) if *b & 0xE0 == 0xC0 => 2,
+                    Some(b) if *b & 0xF0 == 0xE0 => 3,
+                    Some(b) if *b & 0xF8 == 0xF0 => 4,
+                    _ => 0,
+                };
+                let decoded = bytes
+                    .get(..width)
+                    .and_then(|b| std::str::from_utf8(b).ok())
+                    .and_then(|s| s.chars().next());
+                let (c, n) = match decoded {
+                    Some(c) => (c, width),
+                    None => (char::REPLACEMENT_CHARACTER, 1),
+                };
+                self.kernel.push(c as i64);
+                self.kernel.push(n as i64);
+            }
         }
     }
 
@@ -234,9 +870,11 @@ impl ForthRuntime {
     ///
     pub fn f_c_store(&mut self) {
         if self.kernel.stack_check(2, "c!") {
-            let s_address = self.kernel.pop() as usize;
+            let s_address = self.kernel.pop();
             let c = self.kernel.pop() as u8;
-            self.kernel.byte_set(s_address, c);
+            if self.u_string_range("c!", s_address, 1) {
+                self.kernel.byte_set(s_address as usize, c);
+            }
         }
     }
 
@@ -280,36 +918,727 @@ impl ForthRuntime {
         self.kernel.push(dest as i64);
     }
 
-    /// f_now ( -- ) Start a timer
-    pub fn f_now(&mut self) {
-        self.timer = Instant::now();
+    /// s+ ( s1 u1 s2 u2 dest -- dest u ) concatenates two string regions into dest.
+    ///     The result is a raw region with no count byte, for use with tell
+    ///
+    pub fn f_s_plus(&mut self) {
+        if self.kernel.stack_check(5, "s+") {
+            let dest = self.kernel.pop();
+            let len2 = self.kernel.pop();
+            let addr2 = self.kernel.pop();
+            let len1 = self.kernel.pop();
+            let addr1 = self.kernel.pop();
+            if self.u_string_range("s+", addr1, len1)
+                && self.u_string_range("s+", addr2, len2)
+                && self.u_string_range("s+", dest, len1 + len2)
+            {
+                let mut text = self
+                    .kernel
+                    .string_slice(addr1 as usize, len1 as usize)
+                    .to_vec();
+                text.extend_from_slice(self.kernel.string_slice(addr2 as usize, len2 as usize));
+                for (i, c) in text.iter().enumerate() {
+                    self.kernel.byte_set(dest as usize + i, *c);
+                }
+                self.kernel.push(dest);
+                self.kernel.push(text.len() as i64);
+            }
+        }
     }
 
-    /// micros ( -- n ) returns the number of microseconds since NOW was called
-    pub fn f_micros(&mut self) {
-        let duration = self.timer.elapsed();
-        self.kernel.push(duration.as_micros() as i64);
+    /// cmove ( src dst len -- ) copies len bytes of string space from src to dst, lowest address first
+    ///     When dst overlaps the end of src, the bytes already copied are copied again.
+    ///
+    pub fn f_cmove(&mut self) {
+        if let Some((src, dst, len)) = self.u_cmove_args("cmove") {
+            for i in 0..len {
+                let c = self.kernel.byte_get(src + i);
+                self.kernel.byte_set(dst + i, c);
+            }
+        }
     }
 
-    /// millis ( -- n ) returns the number of milliseconds since NOW was called
-    pub fn f_millis(&mut self) {
-        let duration = self.timer.elapsed();
-        self.kernel.push(duration.as_millis() as i64);
+    /// cmove> ( src dst len -- ) copies len bytes of string space from src to dst, highest address first
+    ///     Use it to shift a region up over itself.
+    ///
+    pub fn f_cmove_up(&mut self) {
+        if let Some((src, dst, len)) = self.u_cmove_args("cmove>") {
+            for i in (0..len).rev() {
+                let c = self.kernel.byte_get(src + i);
+                self.kernel.byte_set(dst + i, c);
+            }
+        }
     }
 
-    /// ms ( ms -- ) Sleep for ms milliseconds
-    pub fn f_ms(&mut self) {
-        if self.kernel.stack_check(1, "sleep") {
-            let delay = self.kernel.pop() as u64;
-            let millis = Duration::from_millis(delay);
-            thread::sleep(millis);
+    /// u_cmove_args pops and range checks the arguments of cmove and cmove>
+    ///
+    fn u_cmove_args(&mut self, word: &str) -> Option<(usize, usize, usize)> {
+        if self.kernel.stack_check(3, word) {
+            let len = self.kernel.pop();
+            let dst = self.kernel.pop();
+            let src = self.kernel.pop();
+            if self.u_string_range(word, src, len) && self.u_string_range(word, dst, len) {
+                return Some((src as usize, dst as usize, len as usize));
+            }
         }
+        None
     }
-    /// DEPTH - print the number of items on the stack
+
+    /// search ( s u s2 u2 -- s3 u3 flag ) looks for the region s2 u2 within s u.
+    ///     If found, returns the remainder of s u starting at the match, and TRUE.
+    ///     Otherwise returns the original region and FALSE.
     ///
-    pub fn f_stack_depth(&mut self) {
-        let depth = self.kernel.stack_len();
-        self.kernel.push(depth as i64);
+    pub fn f_search(&mut self) {
+        if self.kernel.stack_check(4, "search") {
+            let sub_len = self.kernel.pop();
+            let sub_addr = self.kernel.pop();
+            let len = self.kernel.pop();
+            let addr = self.kernel.pop();
+            if self.u_string_range("search", addr, len)
+                && self.u_string_range("search", sub_addr, sub_len)
+            {
+                let text = self.kernel.string_slice(addr as usize, len as usize);
+                let sub = self
+                    .kernel
+                    .string_slice(sub_addr as usize, sub_len as usize);
+                let found = if sub.is_empty() {
+                    Some(0)
+                } else {
+                    text.windows(sub.len()).position(|w| w == sub)
+                };
+                match found {
+                    Some(offset) => {
+                        self.kernel.push(addr + offset as i64);
+                        self.kernel.push(len - offset as i64);
+                        self.kernel.push(TRUE);
+                    }
+                    None => {
+                        self.kernel.push(addr);
+                        self.kernel.push(len);
+                        self.kernel.push(FALSE);
+                    }
+                }
+            }
+        }
+    }
+
+    /// -trailing ( s u -- s u2 ) shortens a string region to exclude trailing spaces
+    ///
+    pub fn f_dash_trailing(&mut self) {
+        if self.kernel.stack_check(2, "-trailing") {
+            let len = self.kernel.pop();
+            let addr = self.kernel.peek(0);
+            if self.u_string_range("-trailing", addr, len) {
+                let text = self.kernel.string_slice(addr as usize, len as usize);
+                let trimmed = text.iter().rposition(|&c| c != b' ').map_or(0, |i| i + 1);
+                self.kernel.push(trimmed as i64);
+            }
+        }
+    }
+
+    /// /string ( s u n -- s+n u-n ) removes n characters from the start of a string region.
+    ///     n is limited to the length of the string, so the result is never negative.
+    ///
+    pub fn f_slash_string(&mut self) {
+        if self.kernel.stack_check(3, "/string") {
+            let n = self.kernel.pop();
+            let len = self.kernel.pop();
+            let addr = self.kernel.pop();
+            let n = n.min(len.max(0));
+            self.kernel.push(addr + n);
+            self.kernel.push(len - n);
+        }
+    }
+
+    /// compare ( s1 u1 s2 u2 -- n ) compares two string regions byte by byte.
+    ///     Returns -1, 0 or 1. If one string is a prefix of the other, the shorter one is less.
+    ///
+    pub fn f_compare(&mut self) {
+        if self.kernel.stack_check(4, "compare") {
+            let len2 = self.kernel.pop();
+            let addr2 = self.kernel.pop();
+            let len1 = self.kernel.pop();
+            let addr1 = self.kernel.pop();
+            if self.u_string_range("compare", addr1, len1)
+                && self.u_string_range("compare", addr2, len2)
+            {
+                let s1 = self.kernel.string_slice(addr1 as usize, len1 as usize);
+                let s2 = self.kernel.string_slice(addr2 as usize, len2 as usize);
+                let result = match s1.cmp(s2) {
+                    Ordering::Less => -1,
+                    Ordering::Equal => 0,
+                    Ordering::Greater => 1,
+                };
+                self.kernel.push(result);
+            }
+        }
+    }
+
+    /// \>number ( ud1 s u -- ud2 s2 u2 ) accumulates the digits of a string into a double number.
+    ///     Each digit in the current BASE is added to ud1 * BASE. Conversion stops at the first
+    ///     character that is not a digit, returning the accumulated value and the unconverted remainder.
+    ///
+    pub fn f_to_number(&mut self) {
+        if self.kernel.stack_check(4, ">number") {
+            let mut len = self.kernel.pop();
+            let mut addr = self.kernel.pop();
+            let hi = self.kernel.pop() as u64;
+            let lo = self.kernel.pop() as u64;
+            if self.u_string_range(">number", addr, len) {
+                let base = self.kernel.get(self.base_ptr);
+                let mut ud = ((hi as u128) << 64) | lo as u128;
+                while len > 0 {
+                    match u_digit_value(self.kernel.byte_get(addr as usize), base) {
+                        Some(digit) => {
+                            ud = ud.wrapping_mul(base as u128).wrapping_add(digit as u128);
+                            addr += 1;
+                            len -= 1;
+                        }
+                        None => break,
+                    }
+                }
+                self.kernel.push(ud as u64 as i64);
+                self.kernel.push((ud >> 64) as u64 as i64);
+                self.kernel.push(addr);
+                self.kernel.push(len);
+            }
+        }
+    }
+
+    /// digit? ( c -- n true | c false ) converts character c to its value as a digit in the current base
+    ///
+    pub fn f_digit_q(&mut self) {
+        if self.kernel.stack_check(1, "digit?") {
+            let c = self.kernel.pop();
+            let base = self.kernel.get(self.base_ptr);
+            match u8::try_from(c).ok().and_then(|b| u_digit_value(b, base)) {
+                Some(value) => {
+                    self.kernel.push(value);
+                    self.kernel.push(TRUE);
+                }
+                None => {
+                    self.kernel.push(c);
+                    self.kernel.push(FALSE);
+                }
+            }
+        }
+    }
+
+    /// alpha? ( c -- flag ) returns true if c is a letter, including letters beyond ASCII
+    ///
+    pub fn f_alpha_q(&mut self) {
+        self.kernel.pop1_push1("alpha?", |c| {
+            let alpha = u32::try_from(c)
+                .ok()
+                .and_then(char::from_u32)
+                .is_some_and(char::is_alphabetic);
+            if alpha {
+                TRUE
+            } else {
+                FALSE
+            }
+        });
+    }
+
+    /// space? ( c -- flag ) returns true if c is white space, such as a blank, tab or newline
+    ///
+    pub fn f_space_q(&mut self) {
+        self.kernel.pop1_push1("space?", |c| {
+            let space = u32::try_from(c)
+                .ok()
+                .and_then(char::from_u32)
+                .is_some_and(char::is_whitespace);
+            if space {
+                TRUE
+            } else {
+                FALSE
+            }
+        });
+    }
+
+    /// environment? ( s u -- false | n true ) answers a query about the system's limits.
+    ///     Known queries are MAX-N, MAX-U, ADDRESS-UNIT-BITS, /COUNTED-STRING and DATA-SPACE-SIZE.
+    ///     Anything else returns FALSE.
+    ///
+    pub fn f_environment_q(&mut self) {
+        if self.kernel.stack_check(2, "environment?") {
+            let len = self.kernel.pop();
+            let addr = self.kernel.pop();
+            if self.u_string_range("environment?", addr, len) {
+                let query =
+                    String::from_utf8_lossy(self.kernel.string_slice(addr as usize, len as usize))
+                        .to_uppercase();
+                let value = match query.as_str() {
+                    "MAX-N" => Some(i64::MAX),
+                    "MAX-U" => Some(-1),
+                    "ADDRESS-UNIT-BITS" => Some(i64::BITS as i64),
+                    "/COUNTED-STRING" => Some(u8::MAX as i64),
+                    "DATA-SPACE-SIZE" => Some(DATA_SIZE as i64),
+                    _ => None,
+                };
+                match value {
+                    Some(n) => {
+                        self.kernel.push(n);
+                        self.kernel.push(TRUE);
+                    }
+                    None => self.kernel.push(FALSE),
+                }
+            }
+        }
+    }
+
+    /// argc ( -- n ) pushes the number of command line arguments left after the recognized flags
+    ///
+    pub fn f_argc(&mut self) {
+        self.kernel.push(self.args.len() as i64);
+    }
+
+    /// arg ( i -- s u ) copies command line argument i into the argument buffer
+    ///     Arguments longer than the buffer are truncated. The buffer is reused by each call.
+    ///
+    pub fn f_arg(&mut self) {
+        if self.kernel.stack_check(1, "arg") {
+            let i = self.kernel.pop();
+            if i < 0 || i as usize >= self.args.len() {
+                self.msg
+                    .error("arg", "Argument index out of range", Some(i.to_string()));
+                self.set_error(ForthError::BadIndex);
+                self.f_abort();
+                return;
+            }
+            let arg = self.args[i as usize].clone();
+            let len = self.u_buffer_set(ARGBUF_START, arg.as_bytes());
+            self.kernel.push(ARGBUF_START as i64 + 1);
+            self.kernel.push(len as i64);
+        }
+    }
+
+    /// (locals) ( x1 .. xn n m -- ) starts a frame of m locals for the running definition
+    ///     The first n locals are taken from the stack, x1 first. The rest are set to zero.
+    ///     The frame is released when the definition exits.
+    ///
+    pub fn f_locals_p(&mut self) {
+        if self.kernel.stack_check(2, "(locals)") {
+            let m = self.kernel.pop();
+            let n = self.kernel.pop();
+            if n < 0 || n > m || m > MAX_LOCALS as i64 {
+                self.msg.error(
+                    "(locals)",
+                    "Bad locals frame: need 0 <= n <= m <= MAX_LOCALS",
+                    Some(format!("{n} {m}")),
+                );
+                self.set_error(ForthError::BadIndex);
+                self.f_abort();
+                return;
+            }
+            let (n, m) = (n as usize, m as usize);
+            if self.kernel.stack_check(n, "(locals)") {
+                let base = self.locals.len();
+                self.locals.resize(base + m, 0);
+                for k in (0..n).rev() {
+                    self.locals[base + k] = self.kernel.pop();
+                }
+                self.local_frames.push((base, self.kernel.get_return_ptr()));
+            }
+        }
+    }
+
+    /// (local@) ( k -- n ) fetches local k of the current frame
+    ///
+    pub fn f_local_fetch(&mut self) {
+        if self.kernel.stack_check(1, "(local@)") {
+            let k = self.kernel.pop();
+            if let Some(addr) = self.u_local_addr("(local@)", k) {
+                self.kernel.push(self.locals[addr]);
+            }
+        }
+    }
+
+    /// (local!) ( n k -- ) stores n in local k of the current frame
+    ///
+    pub fn f_local_store(&mut self) {
+        if self.kernel.stack_check(2, "(local!)") {
+            let k = self.kernel.pop();
+            let n = self.kernel.pop();
+            if let Some(addr) = self.u_local_addr("(local!)", k) {
+                self.locals[addr] = n;
+            }
+        }
+    }
+
+    /// u_local_addr finds local k of the current frame, aborting if there isn't one
+    ///
+    fn u_local_addr(&mut self, word: &str, k: i64) -> Option<usize> {
+        if let Some(&(base, _)) = self.local_frames.last() {
+            if k >= 0 && base + (k as usize) < self.locals.len() {
+                return Some(base + k as usize);
+            }
+        }
+        self.msg.error(word, "No such local", Some(k));
+        self.set_error(ForthError::BadIndex);
+        self.f_abort();
+        None
+    }
+
+    /// u_release_locals drops the locals frame of a definition that is exiting
+    ///     A frame belongs to the definition whose return address was on top when it was made.
+    ///
+    pub fn u_release_locals(&mut self) {
+        if let Some(&(base, ret)) = self.local_frames.last() {
+            if ret == self.kernel.get_return_ptr() {
+                self.locals.truncate(base);
+                self.local_frames.pop();
+            }
+        }
+    }
+
+    /// u_buffer_set copies bytes into the counted buffer at buf, truncating to fit, and returns the length
+    ///
+    pub fn u_buffer_set(&mut self, buf: usize, bytes: &[u8]) -> usize {
+        let len = bytes.len().min(BUF_SIZE - 1);
+        self.kernel.byte_set(buf, len as u8);
+        for (offset, &b) in bytes[..len].iter().enumerate() {
+            self.kernel.byte_set(buf + 1 + offset, b);
+        }
+        len
+    }
+
+    /// u_string_range checks that a string region lies within string space, aborting if not
+    ///
+    pub fn u_string_range(&mut self, word: &str, addr: i64, len: i64) -> bool {
+        if addr < 0
+            || len < 0
+            || addr
+                .checked_add(len)
+                .is_none_or(|end| end as usize > STRING_SIZE)
+        {
+            self.msg.error(
+                word,
+                "String region out of range",
+                Some(format!("{addr} {len}")),
+            );
+            self.set_error(ForthError::BadAddress);
+            self.f_abort();
+            false
+        } else {
+            true
+        }
+    }
+
+    /// u_cell_range checks that a region of cells lies within data space, aborting if not
+    ///
+    pub fn u_cell_range(&mut self, word: &str, addr: i64, count: i64) -> bool {
+        if addr < 0
+            || count < 0
+            || addr
+                .checked_add(count)
+                .is_none_or(|end| end as usize > DATA_SIZE)
+        {
+            self.msg.error(
+                word,
+                "Cell region out of range",
+                Some(format!("{addr} {count}")),
+            );
+            self.set_error(ForthError::BadAddress);
+            self.f_abort();
+            false
+        } else {
+            true
+        }
+    }
+
+    /// crc32 ( s u -- crc ) returns the CRC-32 checksum of u bytes of string space starting at s
+    ///
+    pub fn f_crc32(&mut self) {
+        if self.kernel.stack_check(2, "crc32") {
+            let len = self.kernel.pop();
+            let addr = self.kernel.pop();
+            if self.u_string_range("crc32", addr, len) {
+                let bytes = (addr as usize..(addr + len) as usize).map(|a| self.kernel.byte_get(a));
+                let crc = u_crc32(bytes);
+                self.kernel.push(crc as i64);
+            }
+        }
+    }
+
+    /// >hex ( src u dst -- dst u2 ) writes the hex encoding of u bytes at src to dst
+    ///
+    pub fn f_to_hex(&mut self) {
+        self.u_transcode(">hex", u_hex_encode);
+    }
+
+    /// hex> ( src u dst -- dst u2 ) decodes the hex text at src into bytes at dst
+    ///     Odd lengths and characters that aren't hex digits are reported, and abort.
+    ///
+    pub fn f_hex_from(&mut self) {
+        self.u_transcode("hex>", u_hex_decode);
+    }
+
+    /// >base64 ( src u dst -- dst u2 ) writes the Base64 encoding of u bytes at src to dst
+    ///
+    pub fn f_to_base64(&mut self) {
+        self.u_transcode(">base64", u_base64_encode);
+    }
+
+    /// base64> ( src u dst -- dst u2 ) decodes the Base64 text at src into bytes at dst
+    ///     Whitespace in the text is ignored. Anything else that isn't valid Base64 aborts.
+    ///
+    pub fn f_base64_from(&mut self) {
+        self.u_transcode("base64>", u_base64_decode);
+    }
+
+    /// upcase ( s u -- ) changes the ASCII letters in a string region to upper case, in place
+    ///
+    pub fn f_upcase(&mut self) {
+        self.u_change_case("upcase", |b| b.to_ascii_uppercase());
+    }
+
+    /// downcase ( s u -- ) changes the ASCII letters in a string region to lower case, in place
+    ///
+    pub fn f_downcase(&mut self) {
+        self.u_change_case("downcase", |b| b.to_ascii_lowercase());
+    }
+
+    /// u_change_case applies change to each byte of the string region on the stack
+    ///
+    fn u_change_case(&mut self, word: &str, change: fn(u8) -> u8) {
+        if self.kernel.stack_check(2, word) {
+            let len = self.kernel.pop();
+            let addr = self.kernel.pop();
+            if self.u_string_range(word, addr, len) {
+                for a in addr as usize..(addr + len) as usize {
+                    let byte = self.kernel.byte_get(a);
+                    self.kernel.byte_set(a, change(byte));
+                }
+            }
+        }
+    }
+
+    /// u_transcode does the work of ( src u dst -- dst u2 ) words that turn one string region into another
+    ///     The result is written to dst, uncounted. Errors from convert, or a result that
+    ///     won't fit in string space, abort.
+    ///
+    fn u_transcode(&mut self, word: &str, convert: fn(&[u8]) -> Result<Vec<u8>, String>) {
+        if self.kernel.stack_check(3, word) {
+            let dst = self.kernel.pop();
+            let len = self.kernel.pop();
+            let src = self.kernel.pop();
+            if !self.u_string_range(word, src, len) {
+                return;
+            }
+            let input = self
+                .kernel
+                .string_slice(src as usize, len as usize)
+                .to_vec();
+            match convert(&input) {
+                Ok(output) => {
+                    if self.u_string_range(word, dst, output.len() as i64) {
+                        for (i, byte) in output.iter().enumerate() {
+                            self.kernel.byte_set(dst as usize + i, *byte);
+                        }
+                        self.kernel.push(dst);
+                        self.kernel.push(output.len() as i64);
+                    }
+                }
+                Err(problem) => {
+                    self.msg.error(word, &problem, None::<bool>);
+                    self.set_error(ForthError::BadValue);
+                    self.f_abort();
+                }
+            }
+        }
+    }
+
+    /// sort ( addr count -- ) sorts count cells starting at addr into ascending order, in place
+    ///
+    pub fn f_sort(&mut self) {
+        if self.kernel.stack_check(2, "sort") {
+            let count = self.kernel.pop();
+            let addr = self.kernel.pop();
+            if self.u_cell_range("sort", addr, count) {
+                let cells = addr as usize..(addr + count) as usize;
+                let mut values: Vec<i64> = cells.clone().map(|a| self.kernel.get(a)).collect();
+                values.sort_unstable();
+                for (a, value) in cells.zip(values) {
+                    self.kernel.set(a, value);
+                }
+            }
+        }
+    }
+
+    /// bsearch ( key addr count -- index flag ) looks for key in count sorted cells starting at addr
+    ///     Returns the index of a matching cell and TRUE, or the index where key would be
+    ///     inserted to keep the cells sorted and FALSE.
+    ///
+    pub fn f_bsearch(&mut self) {
+        if self.kernel.stack_check(3, "bsearch") {
+            let count = self.kernel.pop();
+            let addr = self.kernel.pop();
+            let key = self.kernel.pop();
+            if self.u_cell_range("bsearch", addr, count) {
+                let (mut low, mut high) = (0, count as usize);
+                while low < high {
+                    let mid = low + (high - low) / 2;
+                    let val = self.kernel.get(addr as usize + mid);
+                    if val == key {
+                        self.kernel.push(mid as i64);
+                        self.kernel.push(TRUE);
+                        return;
+                    } else if val < key {
+                        low = mid + 1;
+                    } else {
+                        high = mid;
+                    }
+                }
+                self.kernel.push(low as i64);
+                self.kernel.push(FALSE);
+            }
+        }
+    }
+
+    /// f_now ( -- ) Start a timer
+    pub fn f_now(&mut self) {
+        self.timers[0] = Instant::now();
+    }
+
+    /// micros ( -- n ) returns the number of microseconds since NOW was called
+    pub fn f_micros(&mut self) {
+        let duration = self.timers[0].elapsed();
+        self.kernel.push(duration.as_micros() as i64);
+    }
+
+    /// millis ( -- n ) returns the number of milliseconds since NOW was called
+    pub fn f_millis(&mut self) {
+        let duration = self.timers[0].elapsed();
+        self.kernel.push(duration.as_millis() as i64);
+    }
+
+    /// start-timer ( n -- ) starts timer n
+    pub fn f_start_timer(&mut self) {
+        if let Some(n) = self.u_timer("start-timer") {
+            self.timers[n] = Instant::now();
+        }
+    }
+
+    /// timer-micros ( n -- t ) returns the number of microseconds since timer n was started
+    pub fn f_timer_micros(&mut self) {
+        if let Some(n) = self.u_timer("timer-micros") {
+            let duration = self.timers[n].elapsed();
+            self.kernel.push(duration.as_micros() as i64);
+        }
+    }
+
+    /// timer-millis ( n -- t ) returns the number of milliseconds since timer n was started
+    pub fn f_timer_millis(&mut self) {
+        if let Some(n) = self.u_timer("timer-millis") {
+            let duration = self.timers[n].elapsed();
+            self.kernel.push(duration.as_millis() as i64);
+        }
+    }
+
+    /// u_timer pops a timer number, aborting if it is out of range
+    ///
+    fn u_timer(&mut self, word: &str) -> Option<usize> {
+        if self.kernel.stack_check(1, word) {
+            let n = self.kernel.pop();
+            if (0..TIMERS as i64).contains(&n) {
+                return Some(n as usize);
+            }
+            self.msg
+                .error(word, "Timer number out of range", Some(n.to_string()));
+            self.set_error(ForthError::BadIndex);
+            self.f_abort();
+        }
+        None
+    }
+
+    /// random ( n -- r ) pushes a pseudo-random number in the range 0 to n - 1
+    ///     Uses xorshift64*, which is fast and good enough for simulations, but not for cryptography.
+    ///     A range of zero or less returns 0.
+    ///
+    pub fn f_random(&mut self) {
+        if self.kernel.stack_check(1, "random") {
+            let n = self.kernel.pop();
+            let mut x = self.rng_state;
+            x ^= x >> 12;
+            x ^= x << 25;
+            x ^= x >> 27;
+            self.rng_state = x;
+            let r = x.wrapping_mul(0x2545F4914F6CDD1D);
+            self.kernel
+                .push(if n > 0 { (r % n as u64) as i64 } else { 0 });
+        }
+    }
+
+    /// seed ( n -- ) seeds the random number generator, so the same seed repeats the same sequence
+    ///
+    pub fn f_seed(&mut self) {
+        if self.kernel.stack_check(1, "seed") {
+            let seed = self.kernel.pop();
+            self.rng_state = u_seed_state(seed as u64);
+        }
+    }
+
+    /// time&date ( -- sec min hour day month year ) pushes the current date and time in UTC
+    ///
+    pub fn f_time_and_date(&mut self) {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        let (year, month, day) = u_civil_from_days(secs.div_euclid(86400));
+        let time = secs.rem_euclid(86400);
+        self.kernel.push(time % 60);
+        self.kernel.push(time / 60 % 60);
+        self.kernel.push(time / 3600);
+        self.kernel.push(day);
+        self.kernel.push(month);
+        self.kernel.push(year);
+    }
+
+    /// ms ( ms -- ) Sleep for ms milliseconds
+    pub fn f_ms(&mut self) {
+        if self.kernel.stack_check(1, "sleep") {
+            let delay = self.kernel.pop() as u64;
+            let millis = Duration::from_millis(delay);
+            thread::sleep(millis);
+        }
+    }
+    /// DEPTH - print the number of items on the stack
+    ///
+    pub fn f_stack_depth(&mut self) {
+        let depth = self.kernel.stack_len();
+        self.kernel.push(depth as i64);
+    }
+
+    /// ?stack ( -- ) Checks that both stacks are within their bounds, aborting with an error if not
+    ///
+    pub fn f_q_stack(&mut self) {
+        let sp = self.kernel.get_stack_ptr();
+        let rp = self.kernel.get_return_ptr();
+        let problem = if sp > STACK_START {
+            Some(("Data stack underflow", sp, ForthError::StackUnderflow))
+        } else if sp < self.here() {
+            Some((
+                "Data stack has overwritten the dictionary",
+                sp,
+                ForthError::StackOverflow,
+            ))
+        } else if rp > RET_START {
+            Some(("Return stack underflow", rp, ForthError::ReturnStack))
+        } else if rp < ALLOC_START {
+            Some((
+                "Return stack has overwritten the data area",
+                rp,
+                ForthError::ReturnStack,
+            ))
+        } else {
+            None
+        };
+        if let Some((text, ptr, error)) = problem {
+            self.msg.error("?stack", text, Some(ptr));
+            self.set_error(error);
+            self.f_abort();
+        }
     }
 
     pub fn f_builtin_name(&mut self) {
@@ -326,7 +1655,15 @@ impl ForthRuntime {
 
 #[cfg(test)]
 mod tests {
-    use crate::runtime::ForthRuntime;
+    use super::{
+        u_base64_decode, u_base64_encode, u_civil_from_days, u_crc32, u_hex_decode, u_hex_encode,
+    };
+    use crate::internals::messages::{DebugLevel, ForthError};
+    use crate::kernel::{DATA_SIZE, STRING_SIZE};
+    use crate::runtime::TIMERS;
+    use crate::runtime::{ForthRuntime, FALSE, TRUE};
+    use std::thread;
+    use std::time::Duration;
 
     fn setup_stack(rt: &mut ForthRuntime, vals: &[i64]) {
         for &v in vals.iter() {
@@ -334,6 +1671,614 @@ mod tests {
         }
     }
 
+    fn stack(rt: &mut ForthRuntime) -> Vec<i64> {
+        (0..rt.kernel.stack_len())
+            .rev()
+            .map(|i| rt.kernel.peek(i))
+            .collect()
+    }
+
+    #[test]
+    fn test_s_plus() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        let foo = rt.kernel.string_new("foo") as i64 + 1;
+        let bar = rt.kernel.string_new("bar") as i64 + 1;
+        setup_stack(&mut rt, &[foo, 3, bar, 3, 8000]);
+        rt.f_s_plus();
+        assert_eq!(stack(&mut rt), vec![8000, 6]);
+        assert_eq!(rt.kernel.string_slice(8000, 6), b"foobar");
+        rt.eval_str("tell");
+        assert_eq!(rt.kernel.stack_len(), 0);
+    }
+
+    #[test]
+    fn test_cmove_directions() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.kernel.string_set(7999, "abcdef");
+        setup_stack(&mut rt, &[8000, 8001, 5]);
+        rt.f_cmove();
+        assert_eq!(rt.kernel.string_slice(8000, 6), b"aaaaaa");
+        rt.kernel.string_set(7999, "abcdef");
+        setup_stack(&mut rt, &[8000, 8001, 5]);
+        rt.f_cmove_up();
+        assert_eq!(rt.kernel.string_slice(8000, 6), b"aabcde");
+        assert_eq!(rt.kernel.stack_len(), 0);
+        setup_stack(&mut rt, &[8000, STRING_SIZE as i64 - 2, 5]);
+        rt.f_cmove_up();
+        assert!(rt.get_abort_flag());
+    }
+
+    #[test]
+    fn test_sort_cells() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        let values = [5, -2, 9, 0, 5, -7];
+        for (i, v) in values.iter().enumerate() {
+            rt.kernel.set(3000 + i, *v);
+        }
+        rt.kernel.set(3006, -100); // just past the region, so not sorted
+        setup_stack(&mut rt, &[3000, 6]);
+        rt.f_sort();
+        let sorted: Vec<i64> = (3000..3007).map(|a| rt.kernel.get(a)).collect();
+        assert_eq!(sorted, vec![-7, -2, 0, 5, 5, 9, -100]);
+        setup_stack(&mut rt, &[3006, 0]);
+        rt.f_sort();
+        assert_eq!(rt.kernel.get(3006), -100);
+        assert_eq!(rt.kernel.stack_len(), 0);
+        setup_stack(&mut rt, &[DATA_SIZE as i64 - 2, 3]);
+        rt.f_sort();
+        assert!(rt.get_abort_flag());
+        // a length that overflows the end of the region aborts instead of panicking
+        rt.set_abort_flag(false);
+        setup_stack(&mut rt, &[i64::MAX, i64::MAX]);
+        rt.f_sort();
+        assert!(rt.get_abort_flag());
+    }
+
+    #[test]
+    fn test_bsearch() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        for (i, v) in [-4, 0, 3, 8, 15].iter().enumerate() {
+            rt.kernel.set(3000 + i, *v);
+        }
+        setup_stack(&mut rt, &[8, 3000, 5]);
+        rt.f_bsearch();
+        assert_eq!(stack(&mut rt), vec![3, TRUE]);
+        rt.f_clear();
+        setup_stack(&mut rt, &[4, 3000, 5]);
+        rt.f_bsearch();
+        assert_eq!(stack(&mut rt), vec![3, FALSE]);
+        rt.f_clear();
+        setup_stack(&mut rt, &[99, 3000, 5]);
+        rt.f_bsearch();
+        assert_eq!(stack(&mut rt), vec![5, FALSE]);
+        rt.f_clear();
+        setup_stack(&mut rt, &[1, 3000, 0]);
+        rt.f_bsearch();
+        assert_eq!(stack(&mut rt), vec![0, FALSE]);
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(u_crc32(*b"123456789"), 0xCBF43926);
+        assert_eq!(u_crc32([]), 0);
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.kernel.string_set(7999, "123456789");
+        setup_stack(&mut rt, &[8000, 9]);
+        rt.f_crc32();
+        assert_eq!(stack(&mut rt), vec![0xCBF43926]);
+    }
+
+    #[test]
+    fn test_hex_round_trip() {
+        let bytes: Vec<u8> = vec![0x00, 0x01, 0x7f, 0x80, 0xab, 0xff];
+        assert_eq!(u_hex_encode(&bytes).unwrap(), b"00017f80abff");
+        assert_eq!(u_hex_decode(b"00017F80abff").unwrap(), bytes);
+        assert!(u_hex_decode(b"abc").is_err());
+        assert!(u_hex_decode(b"0g").is_err());
+
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        for (i, b) in bytes.iter().enumerate() {
+            rt.kernel.byte_set(8000 + i, *b);
+        }
+        setup_stack(&mut rt, &[8000, 6, 8100]);
+        rt.f_to_hex();
+        assert_eq!(stack(&mut rt), vec![8100, 12]);
+        assert_eq!(rt.kernel.string_slice(8100, 12), b"00017f80abff");
+        rt.f_clear();
+        setup_stack(&mut rt, &[8100, 12, 8200]);
+        rt.f_hex_from();
+        assert_eq!(stack(&mut rt), vec![8200, 6]);
+        assert_eq!(rt.kernel.string_slice(8200, 6), &bytes[..]);
+        // bad input is an error that returns to the prompt
+        rt.f_clear();
+        setup_stack(&mut rt, &[8100, 11, 8200]);
+        rt.f_hex_from();
+        assert!(rt.get_abort_flag());
+        assert_eq!(rt.kernel.stack_len(), 0);
+    }
+
+    #[test]
+    fn test_base64_round_trip() {
+        assert_eq!(u_base64_encode(b"Man").unwrap(), b"TWFu");
+        assert_eq!(u_base64_encode(b"Ma").unwrap(), b"TWE=");
+        assert_eq!(u_base64_encode(b"M").unwrap(), b"TQ==");
+        assert_eq!(u_base64_encode(b"").unwrap(), b"");
+        assert_eq!(u_base64_decode(b"TQ==").unwrap(), b"M");
+        assert_eq!(u_base64_decode(b"TW\nFu TWE=").unwrap(), b"ManMa");
+        assert!(u_base64_decode(b"TWF").is_err());
+        assert!(u_base64_decode(b"TQ==TWFu").is_err());
+        assert!(u_base64_decode(b"TW*u").is_err());
+
+        let bytes: Vec<u8> = (0..=255).collect();
+        let text = u_base64_encode(&bytes).unwrap();
+        assert_eq!(u_base64_decode(&text).unwrap(), bytes);
+
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.kernel.string_set(7999, "any carnal pleasure.");
+        setup_stack(&mut rt, &[8000, 20, 8100]);
+        rt.f_to_base64();
+        assert_eq!(stack(&mut rt), vec![8100, 28]);
+        assert_eq!(
+            rt.kernel.string_slice(8100, 28),
+            b"YW55IGNhcm5hbCBwbGVhc3VyZS4="
+        );
+        rt.f_clear();
+        setup_stack(&mut rt, &[8100, 28, 8200]);
+        rt.f_base64_from();
+        assert_eq!(stack(&mut rt), vec![8200, 20]);
+        assert_eq!(rt.kernel.string_slice(8200, 20), b"any carnal pleasure.");
+    }
+
+    #[test]
+    fn test_change_case() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.kernel.string_set(7999, "Hello, World 42! é");
+        setup_stack(&mut rt, &[8000, 19]);
+        rt.f_upcase();
+        assert_eq!(rt.kernel.string_get(7999), "HELLO, WORLD 42! é");
+        setup_stack(&mut rt, &[8000, 5]);
+        rt.f_downcase();
+        assert_eq!(rt.kernel.string_get(7999), "hello, WORLD 42! é");
+        assert_eq!(rt.kernel.stack_len(), 0);
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        let compare = |rt: &mut ForthRuntime, a: i64, b: i64| {
+            let words: [fn(&mut ForthRuntime); 4] = [
+                ForthRuntime::f_greater,
+                ForthRuntime::f_less_equal,
+                ForthRuntime::f_greater_equal,
+                ForthRuntime::f_not_equal,
+            ];
+            for word in words {
+                rt.kernel.push(a);
+                rt.kernel.push(b);
+                word(rt);
+            }
+            let flags = stack(rt);
+            rt.f_clear();
+            flags
+        };
+        // >, <=, >=, <>
+        assert_eq!(compare(&mut rt, 2, 3), vec![FALSE, TRUE, FALSE, TRUE]);
+        assert_eq!(compare(&mut rt, 3, 3), vec![FALSE, TRUE, TRUE, FALSE]);
+        assert_eq!(compare(&mut rt, 4, 3), vec![TRUE, FALSE, TRUE, TRUE]);
+        assert_eq!(
+            compare(&mut rt, i64::MAX, i64::MAX),
+            vec![FALSE, TRUE, TRUE, FALSE]
+        );
+        assert_eq!(compare(&mut rt, -1, 0), vec![FALSE, TRUE, FALSE, TRUE]);
+    }
+
+    #[test]
+    fn test_within() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        for (n, lo, hi, flag) in [
+            (5, 1, 10, TRUE),
+            (1, 1, 10, TRUE),
+            (10, 1, 10, FALSE),
+            (0, 1, 10, FALSE),
+            (-3, -5, 5, TRUE),
+            // a wrapped range: everything from 10 upwards, and below -10
+            (i64::MAX, 10, -10, TRUE),
+            (-20, 10, -10, TRUE),
+            (0, 10, -10, FALSE),
+            (5, 5, 5, FALSE),
+        ] {
+            setup_stack(&mut rt, &[n, lo, hi]);
+            rt.f_within();
+            assert_eq!(stack(&mut rt), vec![flag], "{n} {lo} {hi} within");
+            rt.f_clear();
+        }
+    }
+
+    #[test]
+    fn test_not_and_invert() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        for (n, not, invert, nonzero) in [
+            (1, FALSE, -2, TRUE),
+            (0, TRUE, -1, FALSE),
+            (-1, FALSE, 0, TRUE),
+        ] {
+            rt.kernel.push(n);
+            rt.f_not();
+            rt.kernel.push(n);
+            rt.f_invert();
+            rt.kernel.push(n);
+            rt.f_0not_equal();
+            assert_eq!(stack(&mut rt), vec![not, invert, nonzero]);
+            rt.f_clear();
+        }
+    }
+
+    #[test]
+    fn test_q_dup() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        setup_stack(&mut rt, &[1, 5]);
+        rt.f_q_dup();
+        assert_eq!(stack(&mut rt), vec![1, 5, 5]);
+        rt.f_clear();
+        setup_stack(&mut rt, &[1, 0]);
+        rt.f_q_dup();
+        assert_eq!(stack(&mut rt), vec![1, 0]);
+        rt.f_clear();
+        rt.f_q_dup();
+        assert!(rt.kernel.take_underflow().is_some());
+    }
+
+    #[test]
+    fn test_character_classes() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        setup_stack(&mut rt, &['7' as i64]);
+        rt.f_digit_q();
+        assert_eq!(stack(&mut rt), vec![7, TRUE]);
+        rt.f_clear();
+        setup_stack(&mut rt, &['F' as i64]);
+        rt.f_digit_q();
+        assert_eq!(stack(&mut rt), vec!['F' as i64, FALSE]);
+        rt.f_clear();
+        rt.kernel.set(rt.base_ptr, 16);
+        for c in ['F', 'f'] {
+            setup_stack(&mut rt, &[c as i64]);
+            rt.f_digit_q();
+            assert_eq!(stack(&mut rt), vec![15, TRUE]);
+            rt.f_clear();
+        }
+        rt.kernel.set(rt.base_ptr, 2);
+        setup_stack(&mut rt, &['2' as i64, '1' as i64]);
+        rt.f_digit_q();
+        assert_eq!(stack(&mut rt), vec!['2' as i64, 1, TRUE]);
+        rt.f_clear();
+
+        let classes = |rt: &mut ForthRuntime, c: char| {
+            rt.kernel.push(c as i64);
+            rt.f_alpha_q();
+            rt.kernel.push(c as i64);
+            rt.f_space_q();
+            let space = rt.kernel.pop();
+            (rt.kernel.pop(), space)
+        };
+        assert_eq!(classes(&mut rt, 'a'), (TRUE, FALSE));
+        assert_eq!(classes(&mut rt, 'é'), (TRUE, FALSE));
+        assert_eq!(classes(&mut rt, '5'), (FALSE, FALSE));
+        assert_eq!(classes(&mut rt, ' '), (FALSE, TRUE));
+        assert_eq!(classes(&mut rt, '\t'), (FALSE, TRUE));
+    }
+
+    #[test]
+    fn test_s_plus_out_of_range() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        setup_stack(&mut rt, &[100, 3, 200, 3, STRING_SIZE as i64 - 4]);
+        rt.f_s_plus();
+        assert!(rt.get_abort_flag());
+        assert_eq!(rt.kernel.stack_len(), 0);
+    }
+
+    fn search(text: &str, sub: &str) -> Vec<i64> {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        let addr = rt.kernel.string_new(text) as i64 + 1;
+        let sub_addr = rt.kernel.string_new(sub) as i64 + 1;
+        setup_stack(
+            &mut rt,
+            &[addr, text.len() as i64, sub_addr, sub.len() as i64],
+        );
+        rt.f_search();
+        let mut result = stack(&mut rt);
+        result[0] -= addr; // report the offset rather than the address
+        result
+    }
+
+    #[test]
+    fn test_search() {
+        assert_eq!(search("hello world", "lo w"), vec![3, 8, TRUE]);
+        assert_eq!(search("hello world", "hell"), vec![0, 11, TRUE]);
+        assert_eq!(search("hello world", "worlds"), vec![0, 11, FALSE]);
+        assert_eq!(search("hello world", "xyz"), vec![0, 11, FALSE]);
+        assert_eq!(search("hello", ""), vec![0, 5, TRUE]);
+    }
+
+    #[test]
+    fn test_dash_trailing() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        let addr = rt.kernel.string_new("abc   ") as i64 + 1;
+        setup_stack(&mut rt, &[addr, 6]);
+        rt.f_dash_trailing();
+        assert_eq!(stack(&mut rt), vec![addr, 3]);
+        rt.kernel.pop();
+        rt.kernel.pop();
+        let spaces = rt.kernel.string_new("    ") as i64 + 1;
+        setup_stack(&mut rt, &[spaces, 4]);
+        rt.f_dash_trailing();
+        assert_eq!(stack(&mut rt), vec![spaces, 0]);
+    }
+
+    #[test]
+    fn test_slash_string() {
+        let mut rt = ForthRuntime::new();
+        setup_stack(&mut rt, &[100, 10, 3]);
+        rt.f_slash_string();
+        assert_eq!(stack(&mut rt), vec![103, 7]);
+        rt.kernel.push(20);
+        rt.f_slash_string();
+        assert_eq!(stack(&mut rt), vec![110, 0]);
+    }
+
+    fn compare(a: &str, b: &str) -> i64 {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        let addr_a = rt.kernel.string_new(a) as i64 + 1;
+        let addr_b = rt.kernel.string_new(b) as i64 + 1;
+        setup_stack(&mut rt, &[addr_a, a.len() as i64, addr_b, b.len() as i64]);
+        rt.f_compare();
+        rt.kernel.pop()
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(compare("apple", "apple"), 0);
+        assert_eq!(compare("", ""), 0);
+        assert_eq!(compare("app", "apple"), -1);
+        assert_eq!(compare("apple", "app"), 1);
+        assert_eq!(compare("b", "apple"), 1);
+        assert_eq!(compare("apple", "b"), -1);
+    }
+
+    fn to_number(text: &str, base: i64) -> (Vec<i64>, i64) {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.kernel.set(rt.base_ptr, base);
+        let addr = rt.kernel.string_new(text) as i64 + 1;
+        setup_stack(&mut rt, &[0, 0, addr, text.len() as i64]);
+        rt.f_to_number();
+        (stack(&mut rt), addr)
+    }
+
+    #[test]
+    fn test_to_number() {
+        let (result, addr) = to_number("123abc", 10);
+        assert_eq!(result, vec![123, 0, addr + 3, 3]);
+        let (result, addr) = to_number("ff", 16);
+        assert_eq!(result, vec![255, 0, addr + 2, 0]);
+        let (result, addr) = to_number("FFg", 16);
+        assert_eq!(result, vec![255, 0, addr + 2, 1]);
+        let (result, addr) = to_number("19", 8);
+        assert_eq!(result, vec![1, 0, addr + 1, 1]);
+        // a value too large for one cell carries into the high cell
+        let (result, _) = to_number("18446744073709551616", 10);
+        assert_eq!(&result[..2], &[0, 1]);
+    }
+
+    #[test]
+    fn test_environment_q() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        let addr = rt.kernel.string_new("max-n") as i64 + 1;
+        setup_stack(&mut rt, &[addr, 5]);
+        rt.f_environment_q();
+        assert_eq!(stack(&mut rt), vec![i64::MAX, TRUE]);
+        rt.kernel.reset();
+        let addr = rt.kernel.string_new("DATA-SPACE-SIZE") as i64 + 1;
+        setup_stack(&mut rt, &[addr, 15]);
+        rt.f_environment_q();
+        assert_eq!(stack(&mut rt), vec![DATA_SIZE as i64, TRUE]);
+        rt.kernel.reset();
+        let addr = rt.kernel.string_new("NO-SUCH-QUERY") as i64 + 1;
+        setup_stack(&mut rt, &[addr, 13]);
+        rt.f_environment_q();
+        assert_eq!(stack(&mut rt), vec![FALSE]);
+    }
+
+    #[test]
+    fn test_args() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.args = vec!["foo".to_string(), "bar".to_string()];
+        rt.f_argc();
+        assert_eq!(stack(&mut rt), vec![2]);
+        rt.kernel.reset();
+        setup_stack(&mut rt, &[0]);
+        rt.f_arg();
+        let len = rt.kernel.pop();
+        let addr = rt.kernel.pop();
+        assert_eq!(rt.kernel.string_slice(addr as usize, len as usize), b"foo");
+        setup_stack(&mut rt, &[2]);
+        rt.f_arg();
+        assert!(rt.get_abort_flag());
+        assert_eq!(rt.kernel.stack_len(), 0);
+    }
+
+    #[test]
+    fn test_random() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        let run = |rt: &mut ForthRuntime| -> Vec<i64> {
+            setup_stack(rt, &[42]);
+            rt.f_seed();
+            (0..5)
+                .map(|_| {
+                    rt.kernel.push(1000);
+                    rt.f_random();
+                    rt.kernel.pop()
+                })
+                .collect()
+        };
+        let first = run(&mut rt);
+        assert_eq!(first, run(&mut rt));
+        assert!(first.iter().any(|&r| r != first[0]));
+        for _ in 0..1000 {
+            rt.kernel.push(6);
+            rt.f_random();
+            assert!((0..6).contains(&rt.kernel.pop()));
+        }
+        for n in [0, -5] {
+            rt.kernel.push(n);
+            rt.f_random();
+            assert_eq!(rt.kernel.pop(), 0);
+        }
+    }
+
+    #[test]
+    fn test_time_and_date() {
+        assert_eq!(u_civil_from_days(0), (1970, 1, 1));
+        assert_eq!(u_civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(u_civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(u_civil_from_days(19723), (2024, 1, 1));
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.f_time_and_date();
+        let t = stack(&mut rt);
+        assert_eq!(t.len(), 6);
+        assert!(t[5] >= 2024);
+        assert!((1..=12).contains(&t[4]) && (1..=31).contains(&t[3]));
+        assert!((0..24).contains(&t[2]) && (0..60).contains(&t[1]) && (0..60).contains(&t[0]));
+    }
+
+    #[test]
+    fn test_timers() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        setup_stack(&mut rt, &[1]);
+        rt.f_start_timer();
+        thread::sleep(Duration::from_millis(20));
+        setup_stack(&mut rt, &[2]);
+        rt.f_start_timer();
+        setup_stack(&mut rt, &[1]);
+        rt.f_timer_millis();
+        setup_stack(&mut rt, &[2]);
+        rt.f_timer_micros();
+        let t = stack(&mut rt);
+        assert!(t[0] >= 20);
+        assert!(t[1] < t[0] * 1000);
+        rt.kernel.reset();
+        setup_stack(&mut rt, &[TIMERS as i64]);
+        rt.f_timer_millis();
+        assert!(rt.get_abort_flag());
+        assert_eq!(rt.kernel.stack_len(), 0);
+    }
+
+    #[test]
+    fn test_do_loop() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str(": t do i loop ; 5 0 t");
+        assert_eq!(stack(&mut rt), vec![0, 1, 2, 3, 4]);
+        rt.kernel.reset();
+        rt.eval_str(": t2 ?do 99 loop ; 5 5 t2");
+        assert_eq!(rt.kernel.stack_len(), 0);
+        rt.eval_str("5 0 t2");
+        assert_eq!(stack(&mut rt), vec![99; 5]);
+        rt.kernel.reset();
+        rt.eval_str(": t3 do i 2 +loop ; 7 0 t3");
+        assert_eq!(stack(&mut rt), vec![0, 2, 4, 6]);
+        rt.kernel.reset();
+        rt.eval_str(": t4 do i -1 +loop ; 0 3 t4");
+        assert_eq!(stack(&mut rt), vec![3, 2, 1, 0]);
+        rt.kernel.reset();
+        rt.eval_str(": t5 3 0 do 2 0 ?do 1 loop loop ; t5");
+        assert_eq!(stack(&mut rt), vec![1; 6]);
+        assert_eq!(rt.kernel.get_return_ptr(), crate::kernel::RET_START);
+    }
+
+    #[test]
+    fn test_nested_loop_indexes() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str(": grid 2 0 do 3 0 do j i loop loop ;  grid");
+        assert_eq!(stack(&mut rt), vec![0, 0, 0, 1, 0, 2, 1, 0, 1, 1, 1, 2]);
+        rt.f_clear();
+        // j reads the outer index from a word called inside the outer loop
+        rt.eval_str(": inner 2 0 do j 10 * i + loop ;");
+        rt.eval_str(": outer 3 0 do inner loop ;  outer");
+        assert_eq!(stack(&mut rt), vec![0, 1, 10, 11, 20, 21]);
+        rt.f_clear();
+        rt.eval_str(": mix 2 0 do 2 for j i next loop ;  mix");
+        assert_eq!(stack(&mut rt), vec![0, 2, 0, 1, 1, 2, 1, 1]);
+        rt.f_clear();
+        assert!(rt.loop_frames.is_empty());
+        rt.eval_str("i");
+        assert!(rt.get_abort_flag());
+    }
+
+    #[test]
+    fn test_double_return_stack() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str(": pair 1 2 2>r 3 2r@ 2r> ;");
+        rt.eval_str("pair");
+        assert_eq!(stack(&mut rt), vec![3, 1, 2, 1, 2]);
+        assert_eq!(rt.kernel.get_return_ptr(), crate::kernel::RET_START);
+        rt.f_clear();
+        // the pair keeps its order on the return stack, so r> sees b first
+        rt.eval_str(": split 1 2 2>r r> r> ;  split");
+        assert_eq!(stack(&mut rt), vec![2, 1]);
+        rt.f_clear();
+        rt.f_2r_get();
+        assert!(rt.get_abort_flag());
+        rt.set_abort_flag(false);
+        rt.f_2r_from();
+        assert!(rt.get_abort_flag());
+    }
+
+    #[test]
+    fn test_return_stack_limits() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        // unbounded recursion aborts instead of running into the data area
+        rt.eval_str(": forever recurse ;  forever");
+        assert!(rt.get_abort_flag());
+        assert_eq!(rt.kernel.get_return_ptr(), crate::kernel::RET_START);
+        assert_eq!(rt.kernel.stack_len(), 0);
+        rt.set_abort_flag(false);
+        rt.eval_str("r>");
+        assert!(rt.get_abort_flag());
+        rt.set_abort_flag(false);
+        rt.eval_str("2 3 +");
+        assert_eq!(stack(&mut rt), vec![5]);
+    }
+
     #[test]
     fn test_roll_basic() {
         let mut rt = ForthRuntime::new();
@@ -379,11 +2324,189 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
     fn test_roll_underflow() {
         let mut rt = ForthRuntime::new();
         setup_stack(&mut rt, &[1]);
         rt.kernel.push(2); // n = 2, not enough items
         rt.f_roll();
+        assert!(rt.kernel.take_underflow().is_some());
+        assert_eq!(stack(&mut rt), vec![1]);
+    }
+
+    #[test]
+    fn test_get_store_address_checks() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        // the top of string space is well past HERE, in the unused part of data space
+        rt.eval_str("s-here @");
+        let string_addr = rt.kernel.pop() as usize;
+        assert!(rt.u_unused_data(string_addr));
+        rt.eval_str("here @");
+        let here = rt.kernel.pop() as usize;
+        assert!(!rt.u_unused_data(here - 1));
+        assert!(rt.u_unused_data(here));
+        assert!(!rt.u_unused_data(rt.base_ptr));
+        // @ only warns about unused space, but an address outside data space aborts
+        rt.msg.set_level(DebugLevel::Warning);
+        rt.kernel.push(string_addr as i64);
+        let output = rt.with_captured_output(|rt| rt.f_get());
+        assert!(output.contains("WARNING: @: Address is in unused data space"));
+        assert!(!rt.get_abort_flag());
+        assert_eq!(rt.kernel.stack_len(), 1);
+        rt.eval_str("20000 @");
+        assert!(rt.get_abort_flag());
+        rt.eval_str("1 -5 !");
+        assert!(rt.get_abort_flag());
+        assert_eq!(rt.kernel.stack_len(), 0);
+        // c@ and c! check against string space
+        rt.eval_str("-1 c@");
+        assert_eq!(rt.error, Some(ForthError::BadAddress));
+        rt.eval_str("65 10000 c!");
+        assert_eq!(rt.error, Some(ForthError::BadAddress));
+        assert_eq!(rt.kernel.stack_len(), 0);
+        rt.eval_str("65 s-here @ c! s-here @ c@");
+        assert_eq!(stack(&mut rt), vec![65]);
+    }
+
+    #[test]
+    fn test_pick_roll_index_checks() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        setup_stack(&mut rt, &[1, 2, 5]);
+        rt.f_pick();
+        assert!(rt.kernel.take_underflow().is_some());
+        assert_eq!(stack(&mut rt), vec![1, 2]);
+        rt.kernel.push(-1);
+        rt.f_roll();
+        assert!(rt.get_abort_flag());
+        rt.set_abort_flag(false);
+        setup_stack(&mut rt, &[1, 2, -1]);
+        rt.f_pick();
+        assert!(rt.get_abort_flag());
+        rt.set_abort_flag(false);
+        setup_stack(&mut rt, &[1, 2, 1]);
+        rt.f_pick();
+        assert_eq!(stack(&mut rt), vec![1, 2, 1]);
+    }
+
+    #[test]
+    fn test_nrot() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        setup_stack(&mut rt, &[1, 2, 3, 4, 3]);
+        rt.f_nrot();
+        assert_eq!(stack(&mut rt), vec![1, 4, 2, 3]);
+        rt.f_clear();
+        setup_stack(&mut rt, &[1, 2, 3, 4, 4]);
+        rt.f_nrot();
+        assert_eq!(stack(&mut rt), vec![4, 1, 2, 3]);
+        rt.kernel.push(1);
+        rt.f_nrot();
+        rt.kernel.push(0);
+        rt.f_nrot();
+        assert_eq!(stack(&mut rt), vec![4, 1, 2, 3]);
+        rt.f_clear();
+        // too few items leaves the stack alone and reports an underflow
+        setup_stack(&mut rt, &[1, 2, 3]);
+        rt.f_nrot();
+        assert!(rt.kernel.take_underflow().is_some());
+        assert_eq!(stack(&mut rt), vec![1, 2]);
+        rt.kernel.push(-2);
+        rt.f_nrot();
+        assert!(rt.get_abort_flag());
+    }
+
+    #[test]
+    fn test_buffer_bounds() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str("3 buffer: buf  : next-word 42 ;");
+        rt.eval_str("7 buf 0 b!  9 buf 2 b!  buf 0 b@ buf 2 b@ buf @");
+        assert_eq!(stack(&mut rt), vec![7, 9, 3]);
+        rt.f_clear();
+        // writing past the end aborts instead of overwriting next-word's header
+        rt.eval_str("99 buf 3 b!");
+        assert!(rt.get_abort_flag());
+        rt.set_abort_flag(false);
+        rt.eval_str("99 buf -1 b!");
+        assert!(rt.get_abort_flag());
+        rt.set_abort_flag(false);
+        rt.eval_str("buf 3 b@");
+        assert!(rt.get_abort_flag());
+        rt.set_abort_flag(false);
+        rt.eval_str("next-word buf 1 b@");
+        assert_eq!(stack(&mut rt), vec![42, 0]);
+        rt.f_clear();
+        // a negative size would give back dictionary space, so it aborts before creating anything
+        rt.eval_str("here @");
+        let here = rt.kernel.pop();
+        rt.eval_str("-2 buffer: neg");
+        assert!(rt.get_abort_flag());
+        rt.set_abort_flag(false);
+        rt.eval_str("here @ ' neg");
+        assert_eq!(stack(&mut rt), vec![here, 0]);
+    }
+
+    #[test]
+    fn test_protected_system_area() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        let watermark = rt.watermark as i64;
+        assert!(watermark > 14);
+        // the name field of pad, a system variable
+        rt.eval_str("0 14 !");
+        assert!(rt.get_abort_flag());
+        rt.set_abort_flag(false);
+        assert_ne!(rt.kernel.get(14), 0);
+        // the dictionary pointers: HERE, CONTEXT, LAST and S-HERE
+        let here = rt.here();
+        for store in [
+            "0 8 !",
+            "0 12 !",
+            "0 last !",
+            "0 s-here !",
+            "0 here !",
+            "0 pad !",
+        ] {
+            rt.eval_str(store);
+            assert!(rt.get_abort_flag(), "{store}");
+            rt.set_abort_flag(false);
+        }
+        assert_eq!(rt.here(), here);
+        assert_eq!(rt.kernel.get(rt.context_ptr), rt.kernel.get(rt.last_ptr));
+        // the dictionary still grows and shrinks through the compiler and forget
+        rt.eval_str(": t1 1 ;  forget-last  : t2 2 ;  t2  forget t2");
+        assert_eq!(stack(&mut rt), vec![2]);
+        assert_eq!(rt.here(), here);
+        rt.f_clear();
+        // system variables can still be set, and user space is writable
+        rt.eval_str("16 base ! 10 base !  variable v  5 v !  v @ base @");
+        assert_eq!(stack(&mut rt), vec![5, 10]);
+        rt.f_clear();
+        rt.eval_str("0 set-watermark  watermark");
+        assert_eq!(stack(&mut rt), vec![0]);
+        rt.f_clear();
+        rt.kernel.push(watermark);
+        rt.f_set_watermark();
+        rt.eval_str("watermark");
+        assert_eq!(stack(&mut rt), vec![watermark]);
+    }
+
+    #[test]
+    fn test_underflow_returns_to_prompt() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        rt.eval_str("+");
+        assert!(rt.get_abort_flag());
+        rt.eval_str(": add + ;  1 add");
+        assert!(rt.get_abort_flag());
+        assert_eq!(rt.kernel.get_return_ptr(), crate::kernel::RET_START);
+        rt.eval_str("1 2 + ?stack");
+        assert!(!rt.get_abort_flag());
+        assert_eq!(stack(&mut rt), vec![3]);
     }
 }
@@ -1,3 +1,4 @@
+use crate::internals::messages::ForthError;
 use crate::kernel::{DATA_SIZE, RET_START};
 /// Inner Interpreters
 ///
@@ -5,7 +6,7 @@ use crate::kernel::{DATA_SIZE, RET_START};
 ///
 use crate::runtime::{
     ForthRuntime, ABORT, ADDRESS_MASK, ARRAY, BRANCH, BRANCH0, BREAK, BUILTIN, BUILTIN_FLAG,
-    CONSTANT, DEFINITION, EXEC, EXIT, LITERAL, STRLIT, VARIABLE,
+    CONSTANT, DEFER, DEFINITION, DOES, EXEC, EXIT, FALSE, LITERAL, STRLIT, VARIABLE,
 };
 
 impl ForthRuntime {
@@ -13,9 +14,39 @@ impl ForthRuntime {
     ///
     ///    [ index of i_builtin ] [ index of builtin ] in a compiled word
     ///
+    ///    A stack underflow found by the builtin is reported here, and aborts.
+    ///
     pub fn builtin(&mut self, code: usize) {
         let func = &self.kernel.get_builtin(code);
         (func.code)(self); // call the function pointer directly
+        if let Some((word, problem, return_stack)) = self.kernel.take_underflow() {
+            self.msg.error(&word, &problem, None::<bool>);
+            self.set_error(if return_stack {
+                ForthError::ReturnStack
+            } else {
+                ForthError::StackUnderflow
+            });
+            self.f_abort();
+        }
+        self.u_stack_overflow();
+    }
+
+    /// u_stack_overflow reports a push the kernel refused because the stack reached HERE, and aborts
+    ///     Returns true if there was one.
+    ///
+    pub fn u_stack_overflow(&mut self) -> bool {
+        if !self.kernel.take_overflow() {
+            return false;
+        }
+        let here = self.here();
+        self.msg.error(
+            "push",
+            "Stack overflow: the data stack would overwrite the dictionary",
+            Some(here),
+        );
+        self.set_error(ForthError::StackOverflow);
+        self.f_abort();
+        true
     }
 
     /// Places the address of the adjacent variable on the stack
@@ -37,8 +68,74 @@ impl ForthRuntime {
         self.kernel.push(val);
     }
 
+    /// Places the address of element i of the adjacent array on the stack
+    ///
+    ///    [ index of i_array ] [ length ] [ element 0 ] ... [ element length - 1 ]
+    ///
     pub fn i_array(&mut self) {
-        self.i_variable();
+        let addr = self.kernel.pop() as usize;
+        if let Some(element) = self.u_array_element("array", addr) {
+            self.kernel.push(element as i64);
+        }
+    }
+
+    /// u_array_element pops an index and returns the address of that element of the array whose
+    ///     length is at addr. An index out of range aborts, naming word in the error.
+    ///
+    pub fn u_array_element(&mut self, word: &str, addr: usize) -> Option<usize> {
+        if self.kernel.stack_check(1, word) {
+            let i = self.kernel.pop();
+            let length = self.kernel.get(addr);
+            if (0..length).contains(&i) {
+                return Some(addr + 1 + i as usize);
+            }
+            self.msg.error(
+                word,
+                "Index out of range",
+                Some(format!("{i} (length {length})")),
+            );
+            self.set_error(ForthError::BadIndex);
+            self.f_abort();
+        }
+        None
+    }
+
+    /// Runs the execution token stored in a deferred word
+    ///
+    ///    [ index of i_defer ] [ xt ] in a compiled word
+    ///
+    pub fn i_defer(&mut self) {
+        let addr = self.kernel.pop() as usize;
+        let xt = self.kernel.get(addr);
+        if xt == 0 {
+            self.u_defer_unset(addr);
+        } else {
+            self.kernel.push(xt);
+            self.f_execute();
+        }
+    }
+
+    /// u_defer_unset reports a deferred word that has not been given an xt with IS
+    ///
+    fn u_defer_unset(&mut self, addr: usize) {
+        let nfa = self.kernel.get(addr - 2) as usize & ADDRESS_MASK;
+        let name = self.kernel.string_get(nfa);
+        self.msg
+            .error("defer", "Deferred word has not been set", Some(name));
+        self.set_error(ForthError::DeferUnset);
+        self.f_abort();
+    }
+
+    /// Runs the does> code of a word made by a defining word, with the word's data address on the stack
+    ///
+    ///    [ index of i_does ] [ address of the does> code ] [ data ... ]
+    ///
+    pub fn i_does(&mut self) {
+        let addr = self.kernel.pop() as usize;
+        let code = self.kernel.get(addr);
+        self.kernel.push(addr as i64 + 1);
+        self.kernel.push(code);
+        self.i_definition();
     }
 
     /// Places the number in data[d] on the stack
@@ -74,13 +171,17 @@ impl ForthRuntime {
     pub fn i_definition(&mut self) {
         let mut pc = self.kernel.pop() as usize; // This is the start of the definition: first word after the inner interpreter opcode
         let mut call_depth: usize = 1;
-        self.kernel.push(0); // this is how we know when we're done
-        self.f_to_r();
+        let base = self.kernel.get_return_ptr(); // a nested call, e.g. from EXECUTE, keeps its caller's frames
+        self.u_push_r("i_definition", 0); // this is how we know when we're done
         loop {
             // each time round the loop should be one word
-            if pc == 0 || self.get_abort_flag() {
+            if pc == 0 {
+                self.kernel.set_return_ptr(base);
+                return; // we've completed the last exit
+            }
+            if self.check_interrupt() || self.u_stack_overflow() || self.get_abort_flag() {
                 self.kernel.set_return_ptr(RET_START); // clear the return stack
-                return; // we've completed the last exit or encountered an error
+                return; // we've encountered an error
             }
             let code = if pc < DATA_SIZE {
                 self.kernel.get(pc)
@@ -92,22 +193,48 @@ impl ForthRuntime {
                 BUILTIN => {
                     self.msg
                         .error("i_definition", "Found BUILTIN???", Some(code));
-                    self.f_r_from();
-                    pc = self.kernel.pop() as usize;
+                    pc = self.u_pop_r("exit").unwrap_or(0) as usize;
                 }
-                VARIABLE | ARRAY => {
-                    // this means we've pushed into a variable or an array reference
+                VARIABLE => {
+                    // this means we've pushed into a variable reference
                     pc += 1;
                     self.kernel.push(pc as i64); // the address of the variable's data
-                    self.f_r_from();
-                    pc = self.kernel.pop() as usize;
+                    pc = self.u_pop_r("exit").unwrap_or(0) as usize;
+                    call_depth -= 1;
+                }
+                ARRAY => {
+                    // an array reference, which turns the index on the stack into an element address
+                    // a bad index aborts, which is picked up at the top of the loop
+                    if let Some(element) = self.u_array_element("array", pc + 1) {
+                        self.kernel.push(element as i64);
+                        pc = self.u_pop_r("exit").unwrap_or(0) as usize;
+                        call_depth -= 1;
+                    }
                 }
                 CONSTANT => {
                     pc += 1;
                     let val = self.kernel.get(pc);
                     self.kernel.push(val); // the value of the constant
-                    self.f_r_from();
-                    pc = self.kernel.pop() as usize;
+                    pc = self.u_pop_r("exit").unwrap_or(0) as usize;
+                    call_depth -= 1;
+                }
+                DEFER => {
+                    // jump to the stored xt, which returns straight to our caller
+                    let xt = self.kernel.get(pc + 1);
+                    if xt == 0 {
+                        self.u_defer_unset(pc + 1);
+                    } else if xt as usize & BUILTIN_FLAG != 0 {
+                        self.builtin(xt as usize & ADDRESS_MASK);
+                        pc = self.u_pop_r("exit").unwrap_or(0) as usize;
+                        call_depth -= 1;
+                    } else {
+                        pc = xt as usize;
+                    }
+                }
+                DOES => {
+                    // push the data address and jump to the does> code, which returns to our caller
+                    self.kernel.push(pc as i64 + 2);
+                    pc = self.kernel.get(pc + 1) as usize;
                 }
                 LITERAL => {
                     pc += 1;
@@ -117,7 +244,7 @@ impl ForthRuntime {
                 }
                 STRLIT => {
                     pc += 1;
-                    let val = self.kernel.get(pc) as i64;
+                    let val = self.kernel.get(pc);
                     self.kernel.push(val); // the string address of the data
                     pc += 1;
                 }
@@ -131,7 +258,7 @@ impl ForthRuntime {
                     pc += 1;
                     let offset = self.kernel.get(pc);
                     if offset < 0 {
-                        pc -= offset.abs() as usize;
+                        pc -= offset.unsigned_abs() as usize;
                     } else {
                         pc += offset as usize;
                     }
@@ -141,7 +268,7 @@ impl ForthRuntime {
                     if self.kernel.pop() == 0 {
                         let offset = self.kernel.get(pc);
                         if offset < 0 {
-                            pc -= offset.abs() as usize;
+                            pc -= offset.unsigned_abs() as usize;
                         } else {
                             pc += offset as usize;
                         }
@@ -150,19 +277,21 @@ impl ForthRuntime {
                     }
                 }
                 ABORT => {
+                    self.set_error(ForthError::Aborted);
                     self.f_abort();
                     break;
                 }
                 EXIT => {
                     // Current definition is finished, so pop the PC from the return stack
-                    self.f_r_from();
-                    pc = self.kernel.pop() as usize;
+                    self.u_release_locals();
+                    pc = self.u_pop_r("exit").unwrap_or(0) as usize;
                     call_depth -= 1;
                 }
                 BREAK => {
                     // Breaks out of a word by popping the PC from the return stack
-                    self.f_r_from();
-                    pc = self.kernel.pop() as usize;
+                    self.u_release_locals();
+                    pc = self.u_pop_r("exit").unwrap_or(0) as usize;
+                    call_depth -= 1;
                 }
                 EXEC => {
                     self.f_execute();
@@ -170,17 +299,29 @@ impl ForthRuntime {
                 }
                 _ => {
                     // we have a word address
+                    if self.profiling {
+                        *self.profile.entry(code as usize).or_insert(0) += 1;
+                    }
                     // see if it's a builtin:
                     let builtin_flag = code as usize & BUILTIN_FLAG;
                     let address = code as usize & ADDRESS_MASK;
                     if builtin_flag != 0 && (address <= self.kernel.max_builtin()) {
                         self.builtin(address);
                         pc += 1;
-                    } else {
-                        call_depth += 1;
-                        self.kernel.push(pc as i64 + 1); // the return address is the next object in the list
-                        self.f_to_r(); // save it on the return stack
+                    } else if self.kernel.get(pc + 1) == EXIT
+                        && self.kernel.get(self.tail_calls_ptr) != FALSE
+                    {
+                        // a tail call: EXIT would pop straight back to our caller, so reuse this frame
+                        // only when asked for, since a callee that works on the return stack sees the difference
+                        self.u_release_locals();
                         pc = code as usize;
+                    } else {
+                        // save the return address, the next object in the list, on the return stack
+                        // an overflow aborts, which is picked up at the top of the loop
+                        if self.u_push_r("call", pc as i64 + 1) {
+                            call_depth += 1;
+                            pc = code as usize;
+                        }
                     }
                 }
             }
@@ -1,4 +1,5 @@
 /// A simple Message handler that respects the debug level set by the user.
+use crate::internals::files::Output;
 use std::fmt::Debug;
 
 #[derive(Debug, Clone)]
@@ -9,17 +10,49 @@ pub enum DebugLevel {
     Debug,
 }
 
+/// The kind of failure behind the most recent error, kept so it can be inspected after the
+///     interpreter has recovered to the prompt
+#[derive(Debug, Clone, PartialEq)]
+pub enum ForthError {
+    StackUnderflow,
+    StackOverflow,
+    ReturnStack, // the return stack under- or overflowed
+    DivByZero,
+    BadAddress,
+    BadIndex, // a count or index out of range, e.g. for pick, an array or a timer
+    BadValue, // an argument that isn't acceptable, e.g. a color or environment variable name
+    BadXt,    // not an execution token
+    DictionaryFull,
+    Protected, // a write to the system area, or a redefinition of a protected builtin
+    DeferUnset,
+    CompileError, // a word used where it can't compile, e.g. does> outside a definition
+    NotFound { name: String },
+    FileError,
+    ShellError,
+    Interrupted,
+    Aborted, // abort, or abort" with a true flag
+    Thrown(i64),
+}
+
 #[derive(Debug, Clone)]
 pub struct Msg {
     debug_level: DebugLevel,
+    out: Output, // where messages are printed: the runtime's output, or stdout of its own
 }
 
 /// A simple message processing system that allows the user to set message levels
 ///
 impl Msg {
     pub fn new() -> Msg {
+        Msg::with_output(Output::new())
+    }
+
+    /// with_output makes a message handler that prints through out
+    ///
+    pub fn with_output(out: Output) -> Msg {
         Msg {
             debug_level: DebugLevel::Error,
+            out,
         }
     }
     pub fn set_level(&mut self, lev: DebugLevel) {
@@ -33,27 +66,32 @@ impl Msg {
     /* pub fn debug<T: Debug>(&self, context: &str, text: &str, val: Option<T>) {
            if let DebugLevel::Debug = self.debug_level {
                match val {
-                   Some(val) => println!("DEBUG: {context}: {text}: {:?}", val),
-                   None => println!("DEBUG: {context}: {text}"),
+                   Some(val) => self.out.write_line(&format!("DEBUG: {context}: {text}: {:?}", val)),
+                   None => self.out.write_line(&format!("DEBUG: {context}: {text}")),
                }
            }
        }
-
-       pub fn info<T: Debug>(&self, context: &str, text: &str, val: Option<T>) {
-           match self.debug_level {
-               DebugLevel::Info | DebugLevel::Debug => match val {
-                   Some(val) => println!("INFO: {context}: {text}: {:?}", val),
-                   None => println!("INFO: {context}: {text}"),
-               },
-               _ => {}
-           }
-       }
     */
+
+    pub fn info<T: Debug>(&self, context: &str, text: &str, val: Option<T>) {
+        match self.debug_level {
+            DebugLevel::Info | DebugLevel::Debug => match val {
+                Some(val) => self
+                    .out
+                    .write_line(&format!("INFO: {context}: {text}: {:?}", val)),
+                None => self.out.write_line(&format!("INFO: {context}: {text}")),
+            },
+            _ => {}
+        }
+    }
+
     pub fn warning<T: Debug>(&self, context: &str, text: &str, val: Option<T>) {
         match self.debug_level {
             DebugLevel::Warning | DebugLevel::Info | DebugLevel::Debug => match val {
-                Some(val) => println!("WARNING: {context}: {text}: {:?}", val),
-                None => println!("WARNING: {context}: {text}"),
+                Some(val) => self
+                    .out
+                    .write_line(&format!("WARNING: {context}: {text}: {:?}", val)),
+                None => self.out.write_line(&format!("WARNING: {context}: {text}")),
             },
             _ => {}
         }
@@ -61,8 +99,10 @@ impl Msg {
 
     pub fn error<T: Debug>(&self, context: &str, text: &str, val: Option<T>) {
         match val {
-            Some(val) => println!("ERROR: {context}: {text}: {:?}", val),
-            None => println!("ERROR: {context}: {text}"),
+            Some(val) => self
+                .out
+                .write_line(&format!("ERROR: {context}: {text}: {:?}", val)),
+            None => self.out.write_line(&format!("ERROR: {context}: {text}")),
         }
     }
 }
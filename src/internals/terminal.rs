@@ -1,4 +1,9 @@
-use crossterm::terminal::{disable_raw_mode, enable_raw_mode, is_raw_mode_enabled};
+use crossterm::cursor::MoveTo;
+use crossterm::queue;
+use crossterm::style::{Color, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, is_raw_mode_enabled, size, Clear, ClearType,
+};
 use std::io;
 
 /// Enable raw mode for the terminal
@@ -15,3 +20,72 @@ pub fn disable_raw() -> io::Result<()> {
 pub fn get_raw_mode() -> io::Result<bool> {
     is_raw_mode_enabled()
 }
+
+/// The escape sequence that clears the screen and moves the cursor to the top left corner
+pub fn clear_sequence() -> Vec<u8> {
+    let mut sequence = Vec::new();
+    let _ = queue!(sequence, Clear(ClearType::All), MoveTo(0, 0));
+    sequence
+}
+
+/// The escape sequence that moves the cursor to a column and row, counting from 0 at the top left
+pub fn move_sequence(col: u16, row: u16) -> Vec<u8> {
+    let mut sequence = Vec::new();
+    let _ = queue!(sequence, MoveTo(col, row));
+    sequence
+}
+
+/// The size of the terminal window in columns and rows, or 80 x 24 if it can't be found
+pub fn window_size() -> (u16, u16) {
+    match size() {
+        Ok((cols, rows)) if cols > 0 && rows > 0 => (cols, rows),
+        _ => (80, 24),
+    }
+}
+
+/// Convert Forth column and row values to screen coordinates, clamping them to the valid range
+pub fn clamp_xy(col: i64, row: i64) -> (u16, u16) {
+    (
+        col.clamp(0, u16::MAX as i64) as u16,
+        row.clamp(0, u16::MAX as i64) as u16,
+    )
+}
+
+/// The 16 standard terminal colors, in ANSI order: the 8 normal colors, then their bright versions
+const STANDARD_COLORS: [Color; 16] = [
+    Color::Black,
+    Color::DarkRed,
+    Color::DarkGreen,
+    Color::DarkYellow,
+    Color::DarkBlue,
+    Color::DarkMagenta,
+    Color::DarkCyan,
+    Color::Grey,
+    Color::DarkGrey,
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::White,
+];
+
+/// The escape sequence that sets the foreground or background to standard color n, if n is 0 to 15
+pub fn color_sequence(n: i64, background: bool) -> Option<Vec<u8>> {
+    let color = *STANDARD_COLORS.get(usize::try_from(n).ok()?)?;
+    let mut sequence = Vec::new();
+    if background {
+        queue!(sequence, SetBackgroundColor(color)).ok()?;
+    } else {
+        queue!(sequence, SetForegroundColor(color)).ok()?;
+    }
+    Some(sequence)
+}
+
+/// The escape sequence that restores the default colors
+pub fn reset_sequence() -> Vec<u8> {
+    let mut sequence = Vec::new();
+    let _ = queue!(sequence, ResetColor);
+    sequence
+}
@@ -7,6 +7,7 @@
 /// Specifically it manages the main data area (heap) in an FK (Forth Kernel) struct.
 ///
 use crate::internals::builtin::BuiltInFn;
+use std::collections::HashMap;
 
 // DATA AREA constants
 pub const DATA_SIZE: usize = 10000;
@@ -18,6 +19,13 @@ pub const RET_START: usize = DATA_SIZE - 1; // return stack counts downwards
 pub const WORD_START: usize = 0; // data area counts up from the bottom (builtins, words, variables etc.)
 pub const ADDRESS_MASK: usize = 0x00FFFFFFFFFFFFFF; // to get rid of flags
 
+/// count_byte is the count byte for a string of len bytes. It saturates at 255, and the
+///     bytes beyond it are still stored, so a word that returns the length can reach them.
+///
+fn count_byte(len: usize) -> u8 {
+    len.min(u8::MAX as usize) as u8
+}
+
 /// The primary data structure for the Forth engine
 ///
 ///     Forth's main data structure is a fixed array of integers (overloaded with characters and unsigned values).
@@ -33,25 +41,32 @@ pub const ADDRESS_MASK: usize = 0x00FFFFFFFFFFFFFF; // to get rid of flags
 //#[derive(Debug)]
 pub struct Kernel {
     heap: [i64; DATA_SIZE],
-    strings: [u8; STRING_SIZE], // storage for strings
-    builtins: Vec<BuiltInFn>,   // the dictionary of builtins
-    stack_ptr: usize,           // top of the linear space stack
-    return_ptr: usize,          // top of the return stack
-    string_ptr: usize,          // pointer to the next free string space
-                                //pub return_stack: Vec<i64>,     // for do loops etc.
+    strings: [u8; STRING_SIZE],                // storage for strings
+    builtins: Vec<BuiltInFn>,                  // the dictionary of builtins
+    stack_ptr: usize,                          // top of the linear space stack
+    return_ptr: usize,                         // top of the return stack
+    string_ptr: usize,                         // pointer to the next free string space
+    here_ptr: usize,                           // address of HERE, the top of the dictionary
+    underflow: Option<(String, String, bool)>, // the word, the problem, and whether it was the return stack, from the last failed stack check
+    overflow: bool, // set when a push was refused because the stack reached the dictionary
+    interned: HashMap<String, usize>, // addresses of strings made by string_intern
+                    //pub return_stack: Vec<i64>,     // for do loops etc.
 }
 
 impl Kernel {
     pub fn new() -> Kernel {
-        let kernel = Kernel {
+        Kernel {
             heap: [0; DATA_SIZE],
             strings: [b' '; STRING_SIZE],
             builtins: Vec::new(),
             stack_ptr: STACK_START,
             return_ptr: RET_START,
             string_ptr: 0,
-        };
-        kernel
+            here_ptr: 0,
+            underflow: None,
+            overflow: false,
+            interned: HashMap::new(),
+        }
     }
 
     #[inline(always)]
@@ -99,11 +114,9 @@ impl Kernel {
     /// Safe stack accessors
     #[inline(always)]
     pub fn push(&mut self, val: i64) {
-        if self.stack_ptr <= 0 {
-            panic!(
-                "Stack corruption detected: cannot push, stack_ptr ({}) <= 0",
-                self.stack_ptr
-            );
+        if self.stack_ptr <= self.heap[self.here_ptr] as usize {
+            self.overflow = true; // the value is dropped, for the runtime to report with take_overflow
+            return;
         }
         self.stack_ptr -= 1;
         self.heap[self.stack_ptr] = val;
@@ -149,25 +162,50 @@ impl Kernel {
     }
 
     /// stack_check checks if there are enough items on the stack for an operation
+    ///     A shortfall is a programming error rather than corruption, so it doesn't panic.
+    ///     It's recorded for the runtime to report with take_underflow, and the check fails.
     #[inline(always)]
-    pub fn stack_check(&self, needed: usize, word: &str) -> bool {
+    pub fn stack_check(&mut self, needed: usize, word: &str) -> bool {
         let available = STACK_START - self.stack_ptr;
         if available < needed {
-            panic!(
-                "{}: Stack underflow: need {}, have {}",
-                word, needed, available
-            );
+            self.underflow = Some((
+                word.to_string(),
+                format!("Stack underflow: need {needed}, have {available}"),
+                false,
+            ));
+            return false;
         }
         true
     }
 
-    pub fn print_return_stack(&mut self) {
-        print!("Return-stack pointer = {}:", self.return_ptr);
-        print!("{{ ");
-        for i in (self.return_ptr..DATA_SIZE - 1).rev() {
-            print!("{} ", self.heap[i])
+    /// take_underflow returns and clears the shortfall recorded by the last failed stack check
+    pub fn take_underflow(&mut self) -> Option<(String, String, bool)> {
+        self.underflow.take()
+    }
+
+    /// take_overflow returns and clears the flag set when a push would have overwritten the dictionary
+    pub fn take_overflow(&mut self) -> bool {
+        std::mem::take(&mut self.overflow)
+    }
+
+    /// return_stack_text describes the return stack pointer and contents, for .rs
+    pub fn return_stack_text(&self) -> String {
+        let mut text = format!("Return-stack pointer = {}:{{ ", self.return_ptr);
+        for val in self.return_stack() {
+            text.push_str(&format!("{val} "));
         }
-        print!("}} ");
+        text.push_str("} ");
+        text
+    }
+
+    /// return_stack returns a copy of the return stack, oldest entry first
+    ///     The return stack grows downwards from RET_START.
+    ///
+    pub fn return_stack(&self) -> Vec<i64> {
+        (self.return_ptr..RET_START)
+            .rev()
+            .map(|i| self.heap[i])
+            .collect()
     }
 
     #[inline(always)]
@@ -184,13 +222,15 @@ impl Kernel {
     }
 
     #[inline(always)]
-    pub fn stack_check_r(&self, needed: usize, word: &str) -> bool {
+    pub fn stack_check_r(&mut self, needed: usize, word: &str) -> bool {
         let depth = DATA_SIZE - self.return_ptr - 1;
-        if DATA_SIZE - self.return_ptr - 1 < needed {
-            panic!(
-                "{}: Return stack underflow: need {}, have {}",
-                word, needed, depth
-            );
+        if depth < needed {
+            self.underflow = Some((
+                word.to_string(),
+                format!("Return stack underflow: need {needed}, have {depth}"),
+                true,
+            ));
+            return false;
         }
         true
     }
@@ -199,12 +239,10 @@ impl Kernel {
     where
         F: Fn(i64, i64) -> i64,
     {
-        if self.stack_check(2, "pop2_push1") {
+        if self.stack_check(2, word) {
             let j = self.pop();
             let k = self.pop();
             self.push(f(k, j));
-        } else {
-            panic!("{}: Stack underflow in pop2_push1", word);
         }
     }
 
@@ -214,7 +252,7 @@ impl Kernel {
     {
         if self.stack_check(1, word) {
             let x = self.pop();
-            self.push(f(x) as i64);
+            self.push(f(x));
         }
     }
 
@@ -231,44 +269,56 @@ impl Kernel {
         // place a new str into string space and update the free pointer string_ptr
         let mut ptr = self.heap[self.string_ptr] as usize;
         let result_ptr = ptr;
-        self.strings[ptr] = string.len() as u8;
+        self.strings[ptr] = count_byte(string.len());
         ptr += 1;
-        for (i, c) in string.chars().enumerate() {
-            self.strings[ptr + i] = c as u8;
+        for (i, c) in string.bytes().enumerate() {
+            self.strings[ptr + i] = c;
         }
         self.heap[self.string_ptr] = (ptr + string.len()) as i64;
         result_ptr
     }
 
+    /// string_intern returns the address of an interned copy of string, adding one if needed
+    ///     Entries are checked before reuse, as forget may have released or overwritten their space.
+    ///     Only use it for strings that are never modified in place, such as word names.
+    ///
+    pub fn string_intern(&mut self, string: &str) -> usize {
+        if let Some(&addr) = self.interned.get(string) {
+            if addr < self.heap[self.string_ptr] as usize && self.string_get(addr) == string {
+                return addr;
+            }
+        }
+        let addr = self.string_new(string);
+        self.interned.insert(string.to_string(), addr);
+        addr
+    }
+
     /// copy a string slice into string space adding a count byte
     ///
     pub fn string_save(&mut self, from: &str, to: usize) {
-        self.strings[to] = from.len() as u8; // count byte
-        for (i, c) in from.chars().enumerate() {
-            self.strings[to + i + 1] = c as u8;
+        self.strings[to] = count_byte(from.len());
+        for (i, c) in from.bytes().enumerate() {
+            self.strings[to + i + 1] = c;
         }
     }
 
     /// string_get returns a string from a Forth string address
     /// Assumes the source string is counted (i.e. has its length in the first byte)
+    /// Strings are stored as UTF-8 bytes, so the count is in bytes, not characters
     ///
     pub fn string_get(&mut self, addr: usize) -> String {
         let str_addr = (addr & ADDRESS_MASK) + 1; //
         let last = str_addr + self.strings[addr] as usize;
-        let mut result = String::new();
-        for i in str_addr..last {
-            result.push(self.strings[i] as char);
-        }
-        result
+        String::from_utf8_lossy(&self.strings[str_addr..last]).into_owned()
     }
 
     /// string_set saves a counted string to a Forth string address
     ///
     pub fn string_set(&mut self, addr: usize, string: &str) {
         let str_addr = addr & ADDRESS_MASK;
-        self.strings[str_addr] = string.len() as u8; // count byte
-        for (i, c) in string.chars().enumerate() {
-            self.strings[str_addr + i + 1] = c as u8;
+        self.strings[str_addr] = count_byte(string.len());
+        for (i, c) in string.bytes().enumerate() {
+            self.strings[str_addr + i + 1] = c;
         }
     }
 
@@ -277,7 +327,7 @@ impl Kernel {
     ///     Can work with source strings counted or uncounted
     ///
     pub fn string_copy(&mut self, from: usize, to: usize, length: usize, counted: bool) {
-        self.strings[to] = length as u8; // write count byte
+        self.strings[to] = count_byte(length);
         let offset = if counted { 1 } else { 0 };
         for i in 0..length {
             self.strings[to + i + 1] = self.strings[from + i + offset];
@@ -302,6 +352,7 @@ impl Kernel {
     /// Return the length of a counted string
     /// This is the first byte of the string, so it is very fast
     ///
+    #[allow(dead_code)]
     pub fn string_length(&self, addr: usize) -> usize {
         self.strings[addr] as usize
     }
@@ -360,6 +411,11 @@ impl Kernel {
         self.string_ptr = val;
     }
 
+    /// set_here_ptr tells the kernel where HERE lives, so the data stack can't grow into the dictionary
+    pub fn set_here_ptr(&mut self, val: usize) {
+        self.here_ptr = val;
+    }
+
     pub fn get_stack_ptr(&self) -> usize {
         self.stack_ptr
     }
@@ -386,6 +442,20 @@ mod tests {
         assert_eq!(k.string_get(addr), "hello");
     }
 
+    #[test]
+    fn test_string_intern_reuses_address() {
+        let mut k = kernel_with_string_ptr(100);
+        let first = k.string_intern("dup");
+        let free = k.heap[k.string_ptr];
+        assert_eq!(k.string_intern("dup"), first);
+        assert_eq!(k.heap[k.string_ptr], free);
+        assert_ne!(k.string_intern("drop"), first);
+        // released space is not reused
+        k.heap[k.string_ptr] = first as i64;
+        assert_eq!(k.string_intern("dup"), first);
+        assert_eq!(k.heap[k.string_ptr], free);
+    }
+
     #[test]
     fn test_string_save_and_get() {
         let mut k = Kernel::new();
@@ -441,6 +511,19 @@ mod tests {
         assert_eq!(k.string_length(400), 5);
     }
 
+    #[test]
+    fn test_long_string_keeps_its_bytes() {
+        let mut k = kernel_with_string_ptr(100);
+        let text: String = (0..500).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+        let addr = k.string_new(&text);
+        assert_eq!(k.string_length(addr), 255); // the count byte saturates
+        assert_eq!(k.string_slice(addr + 1, 500), text.as_bytes());
+        assert_eq!(k.heap[k.string_ptr] as usize, 100 + 1 + 500);
+        let next = k.string_new("after");
+        assert_eq!(k.string_get(next), "after");
+        assert_eq!(k.string_slice(addr + 1, 500), text.as_bytes());
+    }
+
     #[test]
     fn test_byte_get_and_set() {
         let mut k = Kernel::new();
@@ -22,45 +22,127 @@ mod config;
 mod kernel;
 mod runtime;
 
-use config::{Config, DEFAULT_CORE, VERSION};
+use config::{search_path, Config, CORE_FILE, DEFAULT_CORE, PATH_VARIABLE, VERSION};
+use internals::console::u_expand_home;
 use kernel::STACK_START;
 use runtime::ForthRuntime;
+use std::env;
+use std::ffi::OsStr;
 use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
 
 const WELCOME_MESSAGE: &str = "Welcome to f3.";
 const EXIT_MESSAGE: &str = "Finished";
 
-fn boot_forth(config: &Config) -> ForthRuntime {
-    fn load_file(interpreter: &mut ForthRuntime, file_name: &str) {
-        let addr = interpreter.kernel.get(interpreter.tmp_ptr) as usize;
-        //println!("Loading file: {}", file_name);
-        //println!("DEBUG: stack_ptr before loading {}: {}", file_name, interpreter.kernel.get_stack_ptr());
-        interpreter.kernel.string_set(addr, file_name);
-        let tmp = interpreter.kernel.get(interpreter.tmp_ptr);
-        interpreter.kernel.push(tmp);
-        //println!("DEBUG: stack_ptr after pushing tmp: {}", interpreter.kernel.get_stack_ptr());
-        interpreter.f_include_file();
-        //println!("DEBUG: stack_ptr after f_include_file: {}", interpreter.kernel.get_stack_ptr());
-        // Don't assert here as the stack might legitimately have content from the file
+/// banner returns the welcome message, which quiet mode leaves out
+fn banner(config: &Config) -> Option<String> {
+    if config.quiet {
+        None
+    } else {
+        Some(format!("{WELCOME_MESSAGE} Version {VERSION}"))
+    }
+}
+
+/// boot_message reports a step of the boot process
+///     In quiet mode it goes to the message system, at a level that is hidden by default.
+fn boot_message(forth: &ForthRuntime, config: &Config, text: &str, name: &str) {
+    if config.quiet {
+        forth.msg.info("boot", text, Some(name));
+    } else {
+        println!("{text}: {name}");
+    }
+}
+
+/// core_files lists the core libraries to load
+///     The first corelib.fs in an F3PATH directory is used instead of the defaults;
+///     otherwise each default that exists is loaded. Missing files are left out, so they are not reported one by one.
+fn core_files(search: &[PathBuf], defaults: &[&str], home: Option<&OsStr>) -> Vec<PathBuf> {
+    if let Some(path) = search
+        .iter()
+        .map(|dir| dir.join(CORE_FILE))
+        .find(|path| path.is_file())
+    {
+        return vec![path];
+    }
+    defaults
+        .iter()
+        .map(|name| u_expand_home(name, home))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// no_core_warning explains what is missing when no core library could be loaded
+fn no_core_warning(defaults: &[&str]) -> String {
+    format!(
+        "No core library loaded (looked for {}). Only builtin words are available: \
+         control structures, variables and most stack words are missing. \
+         Set {PATH_VARIABLE}, use -f to load a file, or -n to start without a core.",
+        defaults.join(", ")
+    )
+}
+
+/// load_core includes each core file, returning the number loaded
+///     If none loaded, the result is a single warning rather than a failure per file.
+fn load_core(
+    forth: &mut ForthRuntime,
+    config: &Config,
+    files: &[PathBuf],
+) -> Result<usize, String> {
+    let mut loaded = 0;
+    for path in files {
+        let path = path.to_string_lossy();
+        load_file(forth, &path);
+        if forth.kernel.pop() != 0 {
+            boot_message(forth, config, "Loaded core file", &path);
+            loaded += 1;
+        }
+    }
+    if loaded == 0 {
+        Err(no_core_warning(DEFAULT_CORE))
+    } else {
+        Ok(loaded)
     }
+}
+
+fn load_file(interpreter: &mut ForthRuntime, file_name: &str) {
+    let addr = interpreter.kernel.get(interpreter.tmp_ptr) as usize;
+    //println!("Loading file: {}", file_name);
+    //println!("DEBUG: stack_ptr before loading {}: {}", file_name, interpreter.kernel.get_stack_ptr());
+    interpreter.kernel.string_set(addr, file_name);
+    let tmp = interpreter.kernel.get(interpreter.tmp_ptr);
+    interpreter.kernel.push(tmp);
+    //println!("DEBUG: stack_ptr after pushing tmp: {}", interpreter.kernel.get_stack_ptr());
+    interpreter.f_include_file();
+    //println!("DEBUG: stack_ptr after f_include_file: {}", interpreter.kernel.get_stack_ptr());
+    // Don't assert here as the stack might legitimately have content from the file
+}
 
+fn boot_forth(config: &Config) -> ForthRuntime {
     let mut forth = ForthRuntime::new();
+    forth.args = config.args.clone();
+    forth.search_path = search_path();
 
     // --- Bootstrapping Phase ---
     let boot_result = catch_unwind(AssertUnwindSafe(|| {
         forth.cold_start();
 
-        if !config.no_core {
-            for path in DEFAULT_CORE {
-                load_file(&mut forth, &path);
-                let result = forth.kernel.pop();
-                // println!("DEBUG: After popping result for {}, stack_ptr: {}", path, forth.kernel.get_stack_ptr());
-                if result != 0 {
-                    println!("Loaded core file: {}", path);
-                } else {
-                    // println!("Failed to load core file: {}", path);
+        if let Some(image) = &config.image {
+            match forth.u_load_image(image) {
+                Ok(()) => boot_message(&forth, config, "Loaded image", image),
+                Err(e) => {
+                    eprintln!("❌ Failed to load image {}: {}", image, e);
+                    std::process::exit(1);
                 }
             }
+        } else if !config.no_core {
+            let candidates = core_files(
+                &forth.search_path,
+                DEFAULT_CORE,
+                env::var_os("HOME").as_deref(),
+            );
+            if let Err(warning) = load_core(&mut forth, config, &candidates) {
+                eprintln!("⚠️  {warning}");
+            }
         }
 
         if let Some(file) = &config.loaded_file {
@@ -68,7 +150,7 @@ fn boot_forth(config: &Config) -> ForthRuntime {
             let result = forth.kernel.pop();
             // println!("DEBUG: After popping result for {}, stack_ptr: {}", file, forth.kernel.get_stack_ptr());
             if result != 0 {
-                println!("Loaded user file: {}", file);
+                boot_message(&forth, config, "Loaded user file", file);
             } else {
                 // println!("Failed to load user file: {}", file);
             }
@@ -91,38 +173,42 @@ fn boot_forth(config: &Config) -> ForthRuntime {
     forth // Return the initialized interpreter
 }
 
-fn run_forth(forth: &mut ForthRuntime) {
-    println!("{WELCOME_MESSAGE} Version {VERSION}");
+fn run_forth(forth: &mut ForthRuntime, config: &Config) {
+    if let Some(banner) = banner(config) {
+        println!("{banner}");
+    }
+    forth.install_interrupt_handler(); // Ctrl-C aborts to the prompt instead of exiting
 
     // --- Interactive Loop Phase ---
-    loop {
-        let result = catch_unwind(AssertUnwindSafe(|| {
-            forth.f_dot_s();
-            forth.set_abort_flag(false);
-            // println!("Entering f_quit");
-            forth.f_quit(); // main interpreter loop
-        }));
-
-        match result {
-            Ok(_) => {
+    // Programmer errors abort back to the prompt inside f_quit, so a panic here means the
+    // interpreter's own state is corrupt: report it and re-raise rather than carry on.
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        forth.f_dot_s();
+        forth.set_abort_flag(false);
+        // println!("Entering f_quit");
+        forth.f_quit(); // main interpreter loop
+    }));
+
+    match result {
+        Ok(_) => {
+            if !config.quiet {
                 println!("{EXIT_MESSAGE}");
-                break;
             }
-            Err(err) => {
-                eprintln!("⚠️  Error during execution. Resetting interpreter to prompt.");
-
-                if let Some(msg) = err.downcast_ref::<&str>() {
-                    eprintln!("panic message: {}", msg);
-                } else if let Some(msg) = err.downcast_ref::<String>() {
-                    eprintln!("panic message: {}", msg);
-                } else {
-                    eprintln!("panic payload is not a string.");
-                }
+        }
+        Err(err) => {
+            eprintln!("⚠️  Internal error during execution.");
 
-                // Print the backtrace if RUST_BACKTRACE is set
-                // Optionally re-raise to get Rust's full backtrace output
-                std::panic::resume_unwind(err);
+            if let Some(msg) = err.downcast_ref::<&str>() {
+                eprintln!("panic message: {}", msg);
+            } else if let Some(msg) = err.downcast_ref::<String>() {
+                eprintln!("panic message: {}", msg);
+            } else {
+                eprintln!("panic payload is not a string.");
             }
+
+            // Print the backtrace if RUST_BACKTRACE is set
+            // Optionally re-raise to get Rust's full backtrace output
+            std::panic::resume_unwind(err);
         }
     }
 }
@@ -132,5 +218,42 @@ fn main() {
     config.process_args();
 
     let mut interpreter = boot_forth(&config);
-    run_forth(&mut interpreter);
+    run_forth(&mut interpreter, &config);
+    std::process::exit(interpreter.exit_code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quiet_has_no_banner() {
+        let mut config = Config::new();
+        assert!(banner(&config).unwrap().starts_with(WELCOME_MESSAGE));
+        config.quiet = true;
+        assert_eq!(banner(&config), None);
+    }
+
+    #[test]
+    fn test_missing_core_gives_one_warning() {
+        let missing = ["/nonexistent/corelib.fs", "~/nonexistent/corelib.fs"];
+        let search = [PathBuf::from("/nonexistent")];
+        let files = core_files(&search, &missing, Some(OsStr::new("/nonexistent")));
+        assert!(files.is_empty());
+
+        let mut config = Config::new();
+        config.quiet = true;
+        let mut forth = ForthRuntime::new();
+        forth.cold_start();
+        let warning = load_core(&mut forth, &config, &files).unwrap_err();
+        assert!(warning.starts_with("No core library loaded"));
+        for path in DEFAULT_CORE {
+            assert_eq!(warning.matches(path).count(), 1);
+        }
+        assert_eq!(forth.kernel.get_stack_ptr(), STACK_START);
+
+        // a core that loads gives no warning
+        let files = core_files(&[], &["src/forth/corelib.fs"], None);
+        assert_eq!(load_core(&mut forth, &config, &files), Ok(1));
+    }
 }
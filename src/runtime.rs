@@ -1,7 +1,8 @@
 //////////////////////////////////////////////////////////////////
 use crate::internals::builtin::BuiltInFn;
-use crate::internals::files::{FType, FileHandle, FileMode}; // Import FileHandle and FType for file handling
-use crate::internals::messages::Msg;
+use crate::internals::files::{FType, FileHandle, FileMode, Output}; // Import FileHandle and FType for file handling
+use crate::internals::general::u_seed_state;
+use crate::internals::messages::{ForthError, Msg};
 use crate::internals::terminal;
 /// runtime.rs
 ///
@@ -12,13 +13,26 @@ use crate::internals::terminal;
 /// // It also provides methods for initializing the runtime (cold_start).
 ///
 use crate::kernel::{Kernel, BUF_SIZE, WORD_START};
-use std::time::Instant;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 // STRING AREA constants
 pub const TIB_START: usize = 0; // Text input buffer, used by readers
 pub const PAD_START: usize = TIB_START + BUF_SIZE; // Scratchpad buffer, used by PARSE and friends
 pub const TMP_START: usize = PAD_START + BUF_SIZE; // Temporary buffer, used for string input
-pub const STR_START: usize = TMP_START + BUF_SIZE; // Free space for additional strings
+pub const WORDBUF_START: usize = TMP_START + BUF_SIZE; // Result buffer for WORD, kept apart from PAD
+pub const ARGBUF_START: usize = WORDBUF_START + BUF_SIZE; // Result buffer for ARG
+pub const ENVBUF_START: usize = ARGBUF_START + BUF_SIZE; // Result buffer for GETENV
+pub const SRCBUF_START: usize = ENVBUF_START + BUF_SIZE; // Result buffer for SOURCE-NAME
+pub const STR_START: usize = SRCBUF_START + BUF_SIZE; // Free space for additional strings
+
+// Runtime limits
+pub const MAX_LOCALS: usize = 32; // Most locals a definition can declare
+pub const TIMERS: usize = 8; // Number of independent timers
 
 // Indices into builtins to drive execution of each data type
 pub const BUILTIN: i64 = 100000;
@@ -34,18 +48,24 @@ pub const EXIT: i64 = 100009; // returns from a word
 pub const BREAK: i64 = 100010; // breaks out of a word
 pub const EXEC: i64 = 100011; // calls the word with address on the stack
 pub const ARRAY: i64 = 100012;
+pub const DEFER: i64 = 100013; // runs the execution token stored in the next cell
+pub const DOES: i64 = 100014; // runs the does> code at the address in the next cell
+pub const ALIAS: i64 = 100015; // another name for the word whose cfa is in the next cell
 
 pub const MARK_BEGIN: i64 = 200000; // marks the beginning of a control structure
 pub const MARK_WHILE: i64 = 200001; // marks the beginning of a WHILE control structure
 pub const MARK_FOR: i64 = 200002; // marks the beginning of a FOR control structure
 pub const MARK_CASE: i64 = 200003; // marks the beginning of a CASE control structure
 pub const MARK_OF: i64 = 200004; // marks the beginning of an OF control structure
+pub const MARK_DO: i64 = 200005; // marks the beginning of a DO loop
+pub const MARK_QDO: i64 = 200006; // marks the unresolved forward branch of a ?DO loop
 
 // GENERAL constants
 pub const TRUE: i64 = -1; // forth convention for true and false
 pub const FALSE: i64 = 0;
 pub const IMMEDIATE_FLAG: usize = 0x4000000000000000; // the immediate flag bit
 pub const BUILTIN_FLAG: usize = 0x2000000000000000; // the builtin flag bit
+pub const INLINE_FLAG: usize = 0x1000000000000000; // the inline flag bit
 pub const ADDRESS_MASK: usize = 0x00FFFFFFFFFFFFFF; // to get rid of flags
 pub const FILEMODE_RO: i64 = 0; // Read-only file mode
 
@@ -56,6 +76,8 @@ pub enum ControlMarker {
     For(usize),   // address of FOR loop
     Case(usize),  // address of CASE
     Of(usize),    // address of OF
+    Do(usize),    // address of the start of a DO loop body
+    QDo(usize),   // unresolved BRANCH0 location of ?DO
 }
 
 pub struct ForthRuntime {
@@ -74,17 +96,53 @@ pub struct ForthRuntime {
     pub tib_size_ptr: usize,
     pub tib_in_ptr: usize,
     pub exit_flag: bool, // set when the "bye" word is executed.
+    pub exit_code: i32,  // the status returned to the operating system, set by "bye-code"
     pub msg: Msg,
+    pub out: Output, // console output: stdout, or a file after redirect>. Shared with msg
     pub reader: Vec<FileHandle>, // allows for nested file processing
-    pub files: Vec<FileHandle>,  // keeps track of open files
-    pub show_stack: bool,        // show the stack at the completion of a line of interaction
-    pub stepper_ptr: usize,      // indicates trace, step, or continuous execution
-    pub step_depth_ptr: usize,   // number of levels deep to step or trace
-    pub timer: Instant,          // for timing things
+    pub files: Vec<FileHandle>, // keeps track of open files
+    pub show_stack: bool, // show the stack at the completion of a line of interaction
+    pub protect_builtins: bool, // make redefining a builtin an error
+    pub watermark: usize, // ! and , refuse to write below this, except to settable system variables
+    pub special_keys: bool, // KEY returns codes for arrow keys, function keys etc.
+    pub colors: bool, // send color escape sequences. Starts on when stdout is a terminal
+    pub stepper_ptr: usize, // indicates trace, step, or continuous execution
+    pub step_depth_ptr: usize, // number of levels deep to step or trace
+    pub step_rstack_ptr: usize, // show the return stack while stepping or tracing
+    pub prompt_ptr: usize, // string address of the prompt printed by QUIT
+    pub emit_vector_ptr: usize, // xt run by EMIT, (emit) by default
+    pub key_vector_ptr: usize, // xt run by KEY, (key) by default
+    pub optimize_ptr: usize, // true if constant expressions are folded as they are compiled
+    pub tail_calls_ptr: usize, // true if a call followed by EXIT reuses the caller's return frame
+    pub timers: [Instant; TIMERS], // for timing things. NOW, MICROS and MILLIS use timer 0
+    pub interrupt: Arc<AtomicBool>, // set asynchronously by the SIGINT (Ctrl-C) handler
+    pub breakpoints: HashSet<usize>, // words that force the stepper into single step mode
+    pub watches: Vec<(usize, i64)>, // (address, last_value) of variables displayed by the stepper
+    pub profiling: bool, // count word invocations in the inner interpreter
+    pub profile: HashMap<usize, u64>, // invocation counts, keyed by compiled reference (cfa or builtin index)
+    pub test_depth: usize,            // stack depth when t{ started the current test
+    pub test_results: Vec<i64>,       // results captured by -> for comparison by }t
+    pub test_passes: usize,           // number of }t comparisons that passed
+    pub test_failures: usize,         // number of }t comparisons that failed
+    pub args: Vec<String>,            // command line arguments left over after the recognized flags
+    pub search_path: Vec<PathBuf>, // directories from F3PATH, searched for files that aren't found as named
+    pub rng_state: u64,            // xorshift64* state for RANDOM, never zero
+    pub compile_locals: Vec<String>, // names of the locals declared in the definition being compiled
+    pub literal_run: Vec<usize>, // addresses of the literals just compiled back to back, for folding
+    pub no_fold: bool,           // set by no-fold, so the definition being compiled isn't folded
+    pub while_branches: HashSet<usize>, // BRANCH0s compiled by while, which see can't otherwise tell from an if
+    pub locals: Vec<i64>,               // values of the locals of all active definitions
+    pub local_frames: Vec<(usize, usize)>, // (start in locals, return_ptr) for each active definition with locals
+    pub loop_frames: Vec<usize>, // return stack address of the index of each active DO or FOR loop, innermost last
+    pub definition_mark: Option<(i64, i64)>, // S-HERE and CONTEXT as the current definition started, to reclaim strings on abort
+    pub error: Option<ForthError>,           // the most recent error, until a new line is read
+    pub error_source: Option<String>, // the file and line the most recent error came from, if not typed
+    pub word_index: HashMap<String, Vec<usize>>, // name -> nfas of every header with that name, oldest first
 }
 
 impl ForthRuntime {
     pub fn new() -> ForthRuntime {
+        let out = Output::new();
         let mut runtime = ForthRuntime {
             kernel: Kernel::new(),
             control_stack: Vec::new(),
@@ -101,19 +159,61 @@ impl ForthRuntime {
             tib_size_ptr: 0,
             tib_in_ptr: 0,
             exit_flag: false,
-            msg: Msg::new(),
+            exit_code: 0,
+            msg: Msg::with_output(out.clone()),
+            out,
             reader: Vec::new(),
             files: Vec::new(),
             show_stack: true,
+            protect_builtins: false,
+            watermark: 0,
+            special_keys: false,
+            colors: io::stdout().is_terminal(),
             stepper_ptr: 0,
             step_depth_ptr: 1,
-            timer: Instant::now(),
+            step_rstack_ptr: 0,
+            prompt_ptr: 0,
+            emit_vector_ptr: 0,
+            key_vector_ptr: 0,
+            optimize_ptr: 0,
+            tail_calls_ptr: 0,
+            timers: [Instant::now(); TIMERS],
+            interrupt: Arc::new(AtomicBool::new(false)),
+            breakpoints: HashSet::new(),
+            watches: Vec::new(),
+            profiling: false,
+            profile: HashMap::new(),
+            test_depth: 0,
+            test_results: Vec::new(),
+            test_passes: 0,
+            test_failures: 0,
+            args: Vec::new(),
+            search_path: Vec::new(),
+            rng_state: u_seed_state(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_nanos() as u64),
+            ),
+            compile_locals: Vec::new(),
+            literal_run: Vec::new(),
+            no_fold: false,
+            while_branches: HashSet::new(),
+            locals: Vec::new(),
+            local_frames: Vec::new(),
+            loop_frames: Vec::new(),
+            definition_mark: None,
+            error: None,
+            error_source: None,
+            word_index: HashMap::new(),
         };
         let fh = FileHandle {
             source: FType::Stdin, // Use standard input
             file_mode: FileMode::RO,
             file_size: 0,
             file_position: 0,
+            name: "stdin".to_string(),
+            line_number: 0,
+            msg: runtime.msg.clone(),
         };
         runtime.reader.push(fh); // Set fh as the active reader
         runtime
@@ -129,9 +229,29 @@ impl ForthRuntime {
     /// Functionally equivalent to push() and comma().
     ///
     pub fn emit_cell(&mut self, value: i64) {
-        let addr = self.here();
-        self.kernel.set(addr, value);
-        self.kernel.incr(self.here_ptr);
+        if self.u_dictionary_room("compile", 1) {
+            let addr = self.here();
+            self.kernel.set(addr, value);
+            self.kernel.incr(self.here_ptr);
+        }
+    }
+
+    /// u_dictionary_room checks that HERE can move up by cells without reaching the data stack
+    ///     Reports an error and aborts if there isn't room.
+    ///
+    pub fn u_dictionary_room(&mut self, word: &str, cells: i64) -> bool {
+        let top = self.here() as i64 + cells;
+        if top >= self.kernel.get_stack_ptr() as i64 {
+            self.msg.error(
+                word,
+                "Dictionary full: data space would overwrite the stack",
+                Some(top),
+            );
+            self.set_error(ForthError::DictionaryFull);
+            self.f_abort();
+            return false;
+        }
+        true
     }
 
     fn f_to_c(&mut self) {
@@ -143,6 +263,8 @@ impl ForthRuntime {
             MARK_FOR => ControlMarker::For(addr),
             MARK_CASE => ControlMarker::Case(addr),
             MARK_OF => ControlMarker::Of(addr),
+            MARK_DO => ControlMarker::Do(addr),
+            MARK_QDO => ControlMarker::QDo(addr),
             _ => panic!(">c: unknown control tag {}", tag),
         };
         //println!(">c pushing {:?}", marker);
@@ -176,6 +298,14 @@ impl ForthRuntime {
                 self.kernel.push(addr as i64);
                 self.kernel.push(MARK_OF);
             }
+            Some(ControlMarker::Do(addr)) => {
+                self.kernel.push(addr as i64);
+                self.kernel.push(MARK_DO);
+            }
+            Some(ControlMarker::QDo(addr)) => {
+                self.kernel.push(addr as i64);
+                self.kernel.push(MARK_QDO);
+            }
             None => self.msg.error("c>", "control stack underflow", None::<()>),
         }
     }
@@ -184,6 +314,11 @@ impl ForthRuntime {
     pub fn cold_start(&mut self) {
         self.insert_variables();
         self.compile_builtins();
+        self.watermark = self.here(); // protect the system variables and builtin headers
+        let emit = self.u_builtin_xt("(emit)").unwrap_or(0);
+        self.kernel.set(self.emit_vector_ptr, emit);
+        let key = self.u_builtin_xt("(key)").unwrap_or(0);
+        self.kernel.set(self.key_vector_ptr, key);
         self.kernel.set(self.state_ptr, FALSE);
         self.insert_code(); // allows forth code to be run prior to presenting a prompt.
     }
@@ -193,11 +328,7 @@ impl ForthRuntime {
     ///     In this version, the STATE variable is used directly.
     ///
     pub fn get_compile_mode(&mut self) -> bool {
-        if self.kernel.get(self.state_ptr) == FALSE {
-            false
-        } else {
-            true
-        }
+        self.kernel.get(self.state_ptr) != FALSE
     }
 
     /// set_compile_mode turns on compilation mode
@@ -218,9 +349,72 @@ impl ForthRuntime {
         self.msg
             .warning("ABORT", "Terminating execution", None::<bool>);
         self.f_clear();
+        self.locals.clear();
+        self.local_frames.clear();
+        self.loop_frames.clear();
+        self.u_reclaim_strings();
+        self.u_abandon_definition();
+        self.set_compile_mode(false);
         self.set_abort_flag(true);
     }
 
+    /// abort ( -- ) ends execution of the current word and clears the stack, recording Aborted
+    ///
+    pub fn f_abort_word(&mut self) {
+        self.set_error(ForthError::Aborted);
+        self.f_abort();
+    }
+
+    /// throw ( n -- ) aborts with error n if n is not zero, otherwise does nothing
+    ///     There is no catch yet, so a throw always returns to the prompt, leaving Thrown(n) as the error.
+    ///
+    pub fn f_throw(&mut self) {
+        if self.kernel.stack_check(1, "throw") {
+            let n = self.kernel.pop();
+            if n != 0 {
+                self.msg.error("throw", "Uncaught exception", Some(n));
+                self.set_error(ForthError::Thrown(n));
+                self.f_abort();
+            }
+        }
+    }
+
+    /// u_mark_definition remembers S-HERE and CONTEXT as a definition starts, so an abort can
+    ///     reclaim its strings
+    ///
+    pub fn u_mark_definition(&mut self) {
+        let free = self.kernel.get(self.kernel.get_string_ptr());
+        let context = self.kernel.get(self.context_ptr);
+        self.definition_mark = Some((free, context));
+    }
+
+    /// u_reclaim_strings moves S-HERE back to where the definition being compiled started,
+    ///     dropping its name and string literals.
+    ///     Nothing is reclaimed if a word was completed since then, because it may use those strings.
+    ///
+    fn u_reclaim_strings(&mut self) {
+        if let Some((free, context)) = self.definition_mark.take() {
+            let string_ptr = self.kernel.get_string_ptr();
+            if context == self.kernel.get(self.context_ptr) && free < self.kernel.get(string_ptr) {
+                self.kernel.set(string_ptr, free);
+            }
+        }
+    }
+
+    /// u_abandon_definition drops the header of a definition that was being compiled, so the
+    ///     next word links to the last completed one instead of a half-built entry
+    ///
+    fn u_abandon_definition(&mut self) {
+        let last = self.kernel.get(self.last_ptr);
+        if self.get_compile_mode() && last != self.kernel.get(self.context_ptr) {
+            self.kernel.set(self.here_ptr, last);
+            self.while_branches.retain(|&addr| addr < last as usize);
+            let context = self.kernel.get(self.context_ptr);
+            self.kernel.set(self.last_ptr, context);
+            self.u_unindex_above(context as usize);
+        }
+    }
+
     /// f_clear resets the stack and return stack pointers to their initial values
     ///
     pub fn f_clear(&mut self) {
@@ -230,7 +424,7 @@ impl ForthRuntime {
 
     /// make-variable creates a variable, returning the address of the variable's value
     fn make_variable(&mut self, name: &str) -> usize {
-        let code_ptr = self.make_word(&name, &[VARIABLE, 0]); // install the name
+        let code_ptr = self.make_word(name, &[VARIABLE, 0]); // install the name
         code_ptr + 1 // the location of the variable's value
     }
 
@@ -247,13 +441,12 @@ impl ForthRuntime {
     ///     return pointer to first parameter field - the code field pointer or cfa
     ///     This is used for making headers for words, variables, and constants.
     ///
-
     fn make_word(&mut self, name: &str, args: &[i64]) -> usize {
         // println!("Making word: {}", name);
         let back = self.kernel.get(self.here_ptr) as usize - 1; // the top-of-stack back pointer's location
         let mut ptr = back + 1;
-        let val = self.kernel.string_new(name) as i64;
-        self.kernel.set(ptr, val as i64);
+        let val = self.kernel.string_intern(name) as i64;
+        self.kernel.set(ptr, val);
         for val in args {
             ptr += 1;
             self.kernel.set(ptr, *val);
@@ -262,6 +455,7 @@ impl ForthRuntime {
         self.kernel.set(ptr, back as i64); // the new back pointer
         self.kernel.set(self.here_ptr, ptr as i64 + 1); // start of free space = HERE
         self.kernel.set(self.context_ptr, back as i64 + 1); // context is the name_pointer field of this word
+        self.u_index_word(back + 1);
         back + 2 // address of first parameter field
     }
 
@@ -290,6 +484,7 @@ impl ForthRuntime {
         self.kernel.set(8, 10); // the value of HERE
         self.kernel.set(9, 5); // back pointer
         self.here_ptr = 8; // the address of the HERE variable
+        self.kernel.set_here_ptr(self.here_ptr);
 
         // hand craft CONTEXT, because it's needed by make_word
         let str_addr = self.kernel.string_new("context");
@@ -299,6 +494,7 @@ impl ForthRuntime {
         self.kernel.set(13, 9); // back pointer
         self.context_ptr = 12;
         self.kernel.set(self.here_ptr, 14);
+        self.u_rebuild_index(); // pick up the hand crafted words
 
         self.pad_ptr = self.make_variable("pad");
         self.kernel.set(self.pad_ptr, PAD_START as i64);
@@ -319,6 +515,14 @@ impl ForthRuntime {
         self.state_ptr = self.make_variable("state");
         self.stepper_ptr = self.make_variable("stepper"); // turns the stepper on or off
         self.step_depth_ptr = self.make_variable("stepper-depth"); // turns the stepper on or off
+        self.step_rstack_ptr = self.make_variable("stepper-rstack"); // adds the return stack to the display
+        self.prompt_ptr = self.make_variable("prompt");
+        let prompt = self.kernel.string_new("ok> ");
+        self.kernel.set(self.prompt_ptr, prompt as i64);
+        self.emit_vector_ptr = self.make_variable("'emit"); // set once the builtins exist
+        self.key_vector_ptr = self.make_variable("'key");
+        self.optimize_ptr = self.make_variable("optimize"); // off unless asked for
+        self.tail_calls_ptr = self.make_variable("tail-calls"); // off unless asked for
         self.kernel.set(self.abort_ptr, FALSE);
     }
 
@@ -392,6 +596,31 @@ impl ForthRuntime {
             ForthRuntime::f_equal,
             "= ( j k -- b ) If j == k push true else false",
         );
+        self.add_builtin(
+            ">",
+            ForthRuntime::f_greater,
+            "> ( j k -- b ) If j > k push true else false",
+        );
+        self.add_builtin(
+            "<=",
+            ForthRuntime::f_less_equal,
+            "<= ( j k -- b ) If j <= k push true else false",
+        );
+        self.add_builtin(
+            ">=",
+            ForthRuntime::f_greater_equal,
+            ">= ( j k -- b ) If j >= k push true else false",
+        );
+        self.add_builtin(
+            "<>",
+            ForthRuntime::f_not_equal,
+            "<> ( j k -- b ) If j != k push true else false",
+        );
+        self.add_builtin(
+            "within",
+            ForthRuntime::f_within,
+            "within ( n lo hi -- b ) If lo <= n < hi push true else false. The range may wrap around",
+        );
         self.add_builtin(
             "0=",
             ForthRuntime::f_0equal,
@@ -402,6 +631,21 @@ impl ForthRuntime {
             ForthRuntime::f_0less,
             "( j k -- j/k ) If j < 0 push true else false",
         );
+        self.add_builtin(
+            "0<>",
+            ForthRuntime::f_0not_equal,
+            "0<> ( n -- flag ) If n is not zero push true else false",
+        );
+        self.add_builtin(
+            "not",
+            ForthRuntime::f_not,
+            "not ( flag -- flag' ) Logical not: true if flag is zero, otherwise false",
+        );
+        self.add_builtin(
+            "invert",
+            ForthRuntime::f_invert,
+            "invert ( n -- ~n ) Bitwise not: flip every bit of n",
+        );
         self.add_builtin(
             ".s",
             ForthRuntime::f_dot_s,
@@ -427,6 +671,16 @@ impl ForthRuntime {
             ForthRuntime::f_flush,
             "flush: forces pending output to appear on the terminal",
         );
+        self.add_builtin(
+            "redirect>",
+            ForthRuntime::f_redirect,
+            "redirect> ( s u -- ) sends console output to the named file",
+        );
+        self.add_builtin(
+            "redirect-off",
+            ForthRuntime::f_redirect_off,
+            "redirect-off ( -- ) closes the redirect file and returns output to the terminal",
+        );
         self.add_builtin(
             "clear",
             ForthRuntime::f_clear,
@@ -438,11 +692,21 @@ impl ForthRuntime {
             ForthRuntime::f_bye,
             "bye: exits to the operating system",
         );
+        self.add_builtin(
+            "bye-code",
+            ForthRuntime::f_bye_code,
+            "bye-code ( n -- ) exits to the operating system with exit status n",
+        );
         self.add_builtin(
             "dup",
             ForthRuntime::f_dup,
             "dup ( n -- n n ) Push a second copy of the top of stack",
         );
+        self.add_builtin(
+            "?dup",
+            ForthRuntime::f_q_dup,
+            "?dup ( n -- n n | 0 ) Duplicate the top of stack if it is not zero",
+        );
         self.add_builtin(
             "drop",
             ForthRuntime::f_drop,
@@ -473,6 +737,11 @@ impl ForthRuntime {
             ForthRuntime::f_roll,
             "roll ( .. n -- .. v ) Rotate the nth item on the stack (after removing n) to the top",
         );
+        self.add_builtin(
+            "nrot",
+            ForthRuntime::f_nrot,
+            "nrot ( x_n .. x_1 n -- x_1 x_n .. x_2 ) Move the top item down under the n - 1 items below it",
+        );
         self.add_builtin(
             "and",
             ForthRuntime::f_and,
@@ -493,36 +762,131 @@ impl ForthRuntime {
             ForthRuntime::f_store,
             "!: ( v a -- ) stores v at address a",
         );
+        self.add_builtin(
+            "watermark",
+            ForthRuntime::f_watermark,
+            "watermark ( -- a ) the address below which ! and , refuse to write, except to settable system variables",
+        );
+        self.add_builtin(
+            "set-watermark",
+            ForthRuntime::f_set_watermark,
+            "set-watermark ( a -- ) protect data space below a from ! and ,. 0 turns protection off",
+        );
+        self.add_builtin(
+            "b@",
+            ForthRuntime::f_b_get,
+            "b@ ( buf i -- n ) fetches cell i of a buffer made by buffer:, aborting if i is out of range",
+        );
+        self.add_builtin(
+            "b!",
+            ForthRuntime::f_b_store,
+            "b! ( n buf i -- ) stores n in cell i of a buffer made by buffer:, aborting if i is out of range",
+        );
         self.add_builtin(
             "i",
             ForthRuntime::f_i,
-            "Pushes the current FOR - NEXT loop index",
+            "Pushes the current FOR - NEXT or DO - LOOP index",
         );
         self.add_builtin(
             "j",
             ForthRuntime::f_j,
             "Pushes the second-level (outer) loop index",
         );
+        self.add_builtin(
+            "(for)",
+            ForthRuntime::f_for_p,
+            "(for) ( n -- ) Runtime for FOR: move the count to the return stack",
+        );
+        self.add_builtin(
+            "(next)",
+            ForthRuntime::f_next_p,
+            "(next) ( -- flag ) Runtime for NEXT: decrement the count, returning TRUE when it reaches zero",
+        );
+        self.add_builtin(
+            "(do)",
+            ForthRuntime::f_do_p,
+            "(do) ( limit start -- ) Runtime for DO: move the loop parameters to the return stack",
+        );
+        self.add_builtin(
+            "(?do)",
+            ForthRuntime::f_q_do_p,
+            "(?do) ( limit start -- flag ) Runtime for ?DO: start the loop and return TRUE, unless limit = start",
+        );
+        self.add_builtin(
+            "(loop)",
+            ForthRuntime::f_loop_p,
+            "(loop) ( -- flag ) Runtime for LOOP: increment the index, returning TRUE at the limit",
+        );
+        self.add_builtin(
+            "(+loop)",
+            ForthRuntime::f_plus_loop_p,
+            "(+loop) ( n -- flag ) Runtime for +LOOP: add n to the index, returning TRUE on crossing the limit",
+        );
+        self.add_builtin(
+            "unloop",
+            ForthRuntime::f_unloop,
+            "unloop ( -- ) Discard the parameters of the current DO loop from the return stack",
+        );
         self.add_builtin(
             "abort",
-            ForthRuntime::f_abort,
+            ForthRuntime::f_abort_word,
             "abort ( -- ) Ends execution of the current word and clears the stack",
         );
+        self.add_builtin(
+            "throw",
+            ForthRuntime::f_throw,
+            "throw ( n -- ) Aborts with error code n, unless n is zero",
+        );
+        self.add_builtin(
+            "(abort\")",
+            ForthRuntime::f_abort_quote_p,
+            "(abort\") ( flag s -- ) Print the string s and abort if flag is true",
+        );
         self.add_builtin(
             "depth",
             ForthRuntime::f_stack_depth,
             "depth: Pushes the current stack depth",
         );
         self.add_builtin(
-            "key",
+            "?stack",
+            ForthRuntime::f_q_stack,
+            "?stack ( -- ) Abort with an error if either stack is out of bounds",
+        );
+        self.add_builtin(
+            "(key)",
             ForthRuntime::f_key,
-            "key ( -- c | 0 ) get a character and push on the stack, or zero if none available",
+            "(key) ( -- c | 0 ) get a character from the terminal, or zero if none available",
         );
         self.add_builtin(
             "key?",
             ForthRuntime::f_key_q,
             "key? ( -- b ) returns TRUE if a character is available, otherwise false",
         );
+        self.add_builtin(
+            "special-keys-on",
+            ForthRuntime::f_special_keys_on,
+            "special-keys-on ( -- ) In raw mode, make key return negative codes for arrows, function keys etc.",
+        );
+        self.add_builtin(
+            "special-keys-off",
+            ForthRuntime::f_special_keys_off,
+            "special-keys-off ( -- ) Make key ignore keys that aren't characters",
+        );
+        self.add_builtin(
+            "evaluate",
+            ForthRuntime::f_evaluate,
+            "evaluate ( s u -- ) Interpret the text at s, length u",
+        );
+        self.add_builtin(
+            "source-line",
+            ForthRuntime::f_source_line,
+            "source-line ( -- n ) The number of the line being interpreted from the current source",
+        );
+        self.add_builtin(
+            "source-name",
+            ForthRuntime::f_source_name,
+            "source-name ( -- s u ) The name of the current source: a file name, or stdin",
+        );
         self.add_builtin(
             "include-file",
             ForthRuntime::f_include_file,
@@ -550,6 +914,21 @@ impl ForthRuntime {
             ForthRuntime::f_r_get,
             "r@ ( -- n ) Push the value on the top of the return stack to the calculation stack",
         );
+        self.add_builtin(
+            "2>r",
+            ForthRuntime::f_2to_r,
+            "2>r ( a b -- ) Move a pair to the return stack, with b on top",
+        );
+        self.add_builtin(
+            "2r>",
+            ForthRuntime::f_2r_from,
+            "2r> ( -- a b ) Move a pair from the return stack to the calculation stack",
+        );
+        self.add_builtin(
+            "2r@",
+            ForthRuntime::f_2r_get,
+            "2r@ ( -- a b ) Copy the pair on top of the return stack to the calculation stack",
+        );
         self.add_builtin(
             ".rs",
             ForthRuntime::f_dot_rs,
@@ -560,6 +939,11 @@ impl ForthRuntime {
             ForthRuntime::f_immediate,
             "immediate sets the immediate flag on the most recently defined word",
         );
+        self.add_builtin(
+            "inline",
+            ForthRuntime::f_inline,
+            "inline marks the most recent definition to be copied into later definitions instead of called",
+        );
         self.add_builtin(
             "quit",
             ForthRuntime::f_quit,
@@ -586,17 +970,38 @@ impl ForthRuntime {
             ForthRuntime::f_q_unique,
             "?unique ( a -- b ) tests to see if the name TOS points to is in the dictionary",
         );
+        self.add_builtin(
+            "protect-builtins",
+            ForthRuntime::f_protect_builtins,
+            "protect-builtins ( -- ) Make redefining a builtin an error instead of a warning",
+        );
+        self.add_builtin(
+            "unprotect-builtins",
+            ForthRuntime::f_unprotect_builtins,
+            "unprotect-builtins ( -- ) Allow builtins to be redefined, with a warning",
+        );
         self.add_builtin(
             "find",
             ForthRuntime::f_find,
             "FIND (s -- a | F ) Search the dictionary for the token indexed through s.
         Return it's address or FALSE if not found",
         );
+        self.add_builtin(
+            "find-word",
+            ForthRuntime::f_find_word,
+            "find-word ( s -- s 0 | cfa 1 | cfa -1 ) Standard FIND: the sign shows whether the word is immediate (1) or not (-1)",
+        );
         self.add_builtin(
             "(')",
             ForthRuntime::f_tick_p,
             "(') <name> ( -- a ) searches the dictionary for a (postfix) word, returning its address",
         );
+        self.add_builtin(
+            "postpone",
+            ForthRuntime::f_postpone,
+            "postpone <name> ( -- ) Compiles name's compile-time behaviour into the current definition",
+        );
+        self.f_immediate();
         self.add_builtin(
             "query",
             ForthRuntime::f_query,
@@ -613,6 +1018,11 @@ impl ForthRuntime {
             ForthRuntime::f_parse_to,
             "parse-to ( b c -- b u ) Get a c-delimited token from TIB, and return counted string in string buffer b",
         );
+        self.add_builtin(
+            "word",
+            ForthRuntime::f_word,
+            "word ( c -- s ) Get the next c-delimited token from TIB, and return it as a counted string",
+        );
         self.add_builtin(
             "(parse)",
             ForthRuntime::f_parse_p,
@@ -638,13 +1048,94 @@ impl ForthRuntime {
             ForthRuntime::f_comma,
             ", ( n -- ) copies the top of the stack to the top of the dictionary",
         );
+        self.add_builtin(
+            "literal",
+            ForthRuntime::f_literal,
+            "literal ( n -- ) compiles n into the current definition",
+        );
+        self.f_immediate();
+        self.add_builtin(
+            "no-fold",
+            ForthRuntime::f_no_fold,
+            "no-fold ( -- ) Immediate: don't fold constant expressions in the rest of this definition",
+        );
+        self.f_immediate();
+        self.add_builtin(
+            "[",
+            ForthRuntime::f_left_bracket,
+            "[ ( -- ) switches to interpret mode inside a definition",
+        );
+        self.f_immediate();
+        self.add_builtin(
+            "]",
+            ForthRuntime::f_right_bracket,
+            "] ( -- ) switches back to compile mode",
+        );
+        self.add_builtin(
+            "allot",
+            ForthRuntime::f_allot,
+            "allot ( n -- ) Reserve n cells of data space, or release them if n is negative",
+        );
         self.add_builtin(
             ";",
             ForthRuntime::f_semicolon,
             "; ( -- ) terminate a definition, resetting to interpret mode",
         );
         self.f_immediate(); // set the immediate flag on the most recent word
+        self.add_builtin(
+            "{",
+            ForthRuntime::f_locals,
+            "{ a b | c -- } Declare locals a and b, taken from the stack, and c, set to zero",
+        );
+        self.f_immediate();
+        self.add_builtin(
+            "to",
+            ForthRuntime::f_to,
+            "to <name> ( n -- ) Store n in the local <name>",
+        );
+        self.f_immediate();
+        self.add_builtin(
+            "does>",
+            ForthRuntime::f_does,
+            "does> ( -- ) In a defining word, starts the code run by the words it creates, with their data address",
+        );
+        self.f_immediate();
+        self.add_builtin(
+            "(does>)",
+            ForthRuntime::f_does_p,
+            "(does>) ( addr -- ) Gives the most recently created word the does> code at addr",
+        );
+        self.add_builtin(
+            "(locals)",
+            ForthRuntime::f_locals_p,
+            "(locals) ( x1 .. xn n m -- ) Start a frame of m locals, the first n taken from the stack",
+        );
+        self.add_builtin(
+            "(local@)",
+            ForthRuntime::f_local_fetch,
+            "(local@) ( k -- n ) Fetch local k of the current frame",
+        );
+        self.add_builtin(
+            "(local!)",
+            ForthRuntime::f_local_store,
+            "(local!) ( n k -- ) Store n in local k of the current frame",
+        );
 
+        self.add_builtin(
+            "(set-dictionary)",
+            ForthRuntime::f_set_dictionary,
+            "(set-dictionary) ( a nfa -- ) Set HERE to a, and CONTEXT and LAST to nfa",
+        );
+        self.add_builtin(
+            "(s-forget)",
+            ForthRuntime::f_s_forget,
+            "(s-forget) ( nfa -- ) Release the name space of a word being forgotten, unless it is shared",
+        );
+        self.add_builtin(
+            "(while)",
+            ForthRuntime::f_while_p,
+            "(while) ( -- ) Record that the BRANCH0 just compiled was compiled by while, for see",
+        );
         self.add_builtin(
             "immed?",
             ForthRuntime::f_immediate_q,
@@ -655,6 +1146,26 @@ impl ForthRuntime {
             ForthRuntime::f_see,
             "see <name> decompiles and prints a word",
         );
+        self.add_builtin(
+            "see>string",
+            ForthRuntime::f_see_to_string,
+            "see>string ( xt dst -- len ) renders the decompilation of xt as a counted string at dst",
+        );
+        self.add_builtin(
+            "alias",
+            ForthRuntime::f_alias,
+            "alias <newname> <oldname> ( -- ) Give an existing word a second name",
+        );
+        self.add_builtin(
+            "help",
+            ForthRuntime::f_help,
+            "help <name> ( -- ) Print a builtin's documentation, or decompile a definition",
+        );
+        self.add_builtin(
+            "apropos",
+            ForthRuntime::f_apropos,
+            "apropos <text> ( -- ) List the builtins whose name or documentation contains text",
+        );
         self.add_builtin(
             "s-create",
             ForthRuntime::f_s_create,
@@ -665,6 +1176,11 @@ impl ForthRuntime {
             ForthRuntime::f_s_copy,
             "s-copy ( source dest -- ) Copy a counted string from source to dest",
         );
+        self.add_builtin(
+            "utf8@",
+            ForthRuntime::f_utf8_get,
+            "utf8@ ( s u -- c n ) Decode the UTF-8 character at s, of at most u bytes, leaving it and its length",
+        );
         self.add_builtin(
             "c@",
             ForthRuntime::f_c_get,
@@ -675,6 +1191,121 @@ impl ForthRuntime {
             ForthRuntime::f_c_store,
             "c! ( c s -- ) Copy character c to string address s",
         );
+        self.add_builtin(
+            "s+",
+            ForthRuntime::f_s_plus,
+            "s+ ( s1 u1 s2 u2 dest -- dest u ) Concatenate two string regions into dest, returning the combined length",
+        );
+        self.add_builtin(
+            "cmove",
+            ForthRuntime::f_cmove,
+            "cmove ( src dst len -- ) Copy len bytes of string space from src to dst, lowest address first",
+        );
+        self.add_builtin(
+            "cmove>",
+            ForthRuntime::f_cmove_up,
+            "cmove> ( src dst len -- ) Copy len bytes of string space from src to dst, highest address first",
+        );
+        self.add_builtin(
+            "sort",
+            ForthRuntime::f_sort,
+            "sort ( addr count -- ) Sort count cells starting at addr into ascending order",
+        );
+        self.add_builtin(
+            "bsearch",
+            ForthRuntime::f_bsearch,
+            "bsearch ( key addr count -- index flag ) Find key in count sorted cells at addr, or where it would go",
+        );
+        self.add_builtin(
+            "search",
+            ForthRuntime::f_search,
+            "search ( s u s2 u2 -- s3 u3 flag ) Find s2 u2 within s u, returning the remainder from the match and TRUE, or s u and FALSE",
+        );
+        self.add_builtin(
+            "-trailing",
+            ForthRuntime::f_dash_trailing,
+            "-trailing ( s u -- s u2 ) Shorten a string region to exclude trailing spaces",
+        );
+        self.add_builtin(
+            "crc32",
+            ForthRuntime::f_crc32,
+            "crc32 ( s u -- crc ) Compute the CRC-32 checksum of a string region",
+        );
+        self.add_builtin(
+            "upcase",
+            ForthRuntime::f_upcase,
+            "upcase ( s u -- ) Change the ASCII letters in a string region to upper case",
+        );
+        self.add_builtin(
+            "downcase",
+            ForthRuntime::f_downcase,
+            "downcase ( s u -- ) Change the ASCII letters in a string region to lower case",
+        );
+        self.add_builtin(
+            ">hex",
+            ForthRuntime::f_to_hex,
+            ">hex ( src u dst -- dst u2 ) Write the hex encoding of a string region to dst",
+        );
+        self.add_builtin(
+            "hex>",
+            ForthRuntime::f_hex_from,
+            "hex> ( src u dst -- dst u2 ) Decode hex text at src into bytes at dst",
+        );
+        self.add_builtin(
+            ">base64",
+            ForthRuntime::f_to_base64,
+            ">base64 ( src u dst -- dst u2 ) Write the Base64 encoding of a string region to dst",
+        );
+        self.add_builtin(
+            "base64>",
+            ForthRuntime::f_base64_from,
+            "base64> ( src u dst -- dst u2 ) Decode Base64 text at src into bytes at dst, ignoring whitespace",
+        );
+        self.add_builtin(
+            "/string",
+            ForthRuntime::f_slash_string,
+            "/string ( s u n -- s+n u-n ) Remove n characters from the start of a string region",
+        );
+        self.add_builtin(
+            "compare",
+            ForthRuntime::f_compare,
+            "compare ( s1 u1 s2 u2 -- n ) Compare two string regions, returning -1, 0 or 1",
+        );
+        self.add_builtin(
+            ">number",
+            ForthRuntime::f_to_number,
+            ">number ( ud1 s u -- ud2 s2 u2 ) Accumulate the digits of s u in the current base, stopping at the first non-digit",
+        );
+        self.add_builtin(
+            "digit?",
+            ForthRuntime::f_digit_q,
+            "digit? ( c -- n true | c false ) Convert character c to its value as a digit in the current base",
+        );
+        self.add_builtin(
+            "alpha?",
+            ForthRuntime::f_alpha_q,
+            "alpha? ( c -- flag ) True if c is a letter",
+        );
+        self.add_builtin(
+            "space?",
+            ForthRuntime::f_space_q,
+            "space? ( c -- flag ) True if c is white space",
+        );
+        self.add_builtin(
+            "environment?",
+            ForthRuntime::f_environment_q,
+            "environment? ( s u -- false | n true ) Query a system limit such as MAX-N or DATA-SPACE-SIZE",
+        );
+        self.add_builtin(
+            "argc",
+            ForthRuntime::f_argc,
+            "argc ( -- n ) The number of command line arguments after the recognized flags",
+        );
+        self.add_builtin(
+            "arg",
+            ForthRuntime::f_arg,
+            "arg ( i -- s u ) Copy command line argument i to a scratch buffer",
+        );
         self.add_builtin("now", ForthRuntime::f_now, "c! ( -- ) Start a timers");
         self.add_builtin(
             "micros",
@@ -686,16 +1317,60 @@ impl ForthRuntime {
             ForthRuntime::f_millis,
             "millis ( -- n ) Milliseconds since NOW was called",
         );
+        self.add_builtin(
+            "start-timer",
+            ForthRuntime::f_start_timer,
+            "start-timer ( n -- ) Start timer n, from 0 to 7. Timer 0 is the one used by NOW",
+        );
+        self.add_builtin(
+            "timer-micros",
+            ForthRuntime::f_timer_micros,
+            "timer-micros ( n -- t ) Microseconds since timer n was started",
+        );
+        self.add_builtin(
+            "timer-millis",
+            ForthRuntime::f_timer_millis,
+            "timer-millis ( n -- t ) Milliseconds since timer n was started",
+        );
+        self.add_builtin(
+            "time&date",
+            ForthRuntime::f_time_and_date,
+            "time&date ( -- sec min hour day month year ) The current date and time in UTC",
+        );
         self.add_builtin("open-file", ForthRuntime::f_open_file, "open-file ( s u fam -- file-id ior ) Open the file named at s, length u, with file access mode fam.
         Returns a file handle and 0 if successful.");
+        self.add_builtin(
+            "save-image",
+            ForthRuntime::f_save_image,
+            "save-image ( s u -- ior ) Save the dictionary to the file named s u, for loading with load-image or --image",
+        );
+        self.add_builtin(
+            "load-image",
+            ForthRuntime::f_load_image,
+            "load-image ( s u -- ior ) Replace the dictionary with an image saved by save-image",
+        );
         self.add_builtin(
             "close-file",
             ForthRuntime::f_close_file,
             "close-file ( file-id -- ior ) Close a file, returning the I/O status code.",
         );
-        self.add_builtin("read-line", ForthRuntime::f_read_line, "read-line ( s u file-id -- u flag ior ) Read up to u characters from a file.
+        self.add_builtin("read-line", ForthRuntime::f_read_line, "read-line ( s u file-id -- u flag ior ) Read up to u characters from a file into s, without the line ending.
         Returns the number of characters read, a flag indicating success or failure, and an i/o result code.
         Starts from FILE_POSITION, and updates FILE_POSITION on completion.");
+        self.add_builtin("read-file", ForthRuntime::f_read_file, "read-file ( s u dst -- u ior ) Read the whole file named at s, length u, into string space at dst.
+        Returns the number of bytes read and an i/o result code.");
+        self.add_builtin("write-file", ForthRuntime::f_write_file, "write-file ( s u name u -- ior ) Write u bytes from s to the named file, replacing its contents.");
+        self.add_builtin(
+            "file-exists?",
+            ForthRuntime::f_file_exists,
+            "file-exists? ( s u -- flag ) Returns true if the named file exists.",
+        );
+        self.add_builtin(
+            "delete-file",
+            ForthRuntime::f_delete_file,
+            "delete-file ( s u -- ior ) Delete the named file, returning an i/o result code.",
+        );
+        self.add_builtin("flush-file", ForthRuntime::f_flush_file, "flush-file ( file-id -- ior ) Push pending writes to a file out to the operating system.");
         self.add_builtin("write-line", ForthRuntime::f_write_line, "write-line ( s u file-id -- ior ) Write u characters from s to a file, returning an i/o result code.");
         self.add_builtin("file-position", ForthRuntime::f_file_position, "file-position ( file-id -- u ior ) Returns the current file position and an i/o result");
         self.add_builtin("file-size", ForthRuntime::f_file_size, "file-size ( file-id -- u ior ) Returns the size in characters of the file, plus an i/o result code");
@@ -705,11 +1380,41 @@ impl ForthRuntime {
             "(system) ( s -- ) Execute a shell command, using string s.
         Output is channeled to stdout",
         );
+        self.add_builtin(
+            "system",
+            ForthRuntime::f_system,
+            "system ( s u -- n ) Execute a shell command, pushing its exit status",
+        );
+        self.add_builtin(
+            "$system",
+            ForthRuntime::f_system_capture,
+            "$system ( s u dst -- n ) Execute a shell command, capturing its output as a counted string at dst",
+        );
+        self.add_builtin(
+            "getenv",
+            ForthRuntime::f_getenv,
+            "getenv ( s u -- s2 u2 flag ) Read environment variable s u into a scratch buffer, with TRUE if it is set",
+        );
+        self.add_builtin(
+            "setenv",
+            ForthRuntime::f_setenv,
+            "setenv ( s1 u1 s2 u2 -- ) Set environment variable s1 u1 to s2 u2 for this process",
+        );
         self.add_builtin(
             "ms",
             ForthRuntime::f_ms,
             "sleep ( ms -- ) Puts the current thread to sleep for ms milliseconds",
         );
+        self.add_builtin(
+            "random",
+            ForthRuntime::f_random,
+            "random ( n -- r ) A pseudo-random number from 0 to n - 1, or 0 if n is not positive",
+        );
+        self.add_builtin(
+            "seed",
+            ForthRuntime::f_seed,
+            "seed ( n -- ) Seed the random number generator, making its sequence repeatable",
+        );
         self.add_builtin(
             "raw-mode-on",
             ForthRuntime::f_raw_mode_on,
@@ -725,6 +1430,46 @@ impl ForthRuntime {
             ForthRuntime::f_raw_mode_q,
             "raw-mode? ( -- f ) Returns true if in raw mode",
         );
+        self.add_builtin(
+            "page",
+            ForthRuntime::f_page,
+            "page ( -- ) Clear the screen and move the cursor to the top left corner",
+        );
+        self.add_builtin(
+            "at-xy",
+            ForthRuntime::f_at_xy,
+            "at-xy ( col row -- ) Move the cursor to col and row, counting from 0 at the top left",
+        );
+        self.add_builtin(
+            "term-size",
+            ForthRuntime::f_term_size,
+            "term-size ( -- cols rows ) The size of the terminal window, or 80 24 if it can't be found",
+        );
+        self.add_builtin(
+            "fg",
+            ForthRuntime::f_fg,
+            "fg ( n -- ) Set the text color to standard color n, from 0 to 15",
+        );
+        self.add_builtin(
+            "bg",
+            ForthRuntime::f_bg,
+            "bg ( n -- ) Set the background color to standard color n, from 0 to 15",
+        );
+        self.add_builtin(
+            "color-reset",
+            ForthRuntime::f_color_reset,
+            "color-reset ( -- ) Restore the default text and background colors",
+        );
+        self.add_builtin(
+            "colors-on",
+            ForthRuntime::f_colors_on,
+            "colors-on ( -- ) Let fg, bg and color-reset send escape sequences to a terminal",
+        );
+        self.add_builtin(
+            "colors-off",
+            ForthRuntime::f_colors_off,
+            "colors-off ( -- ) Make fg, bg and color-reset do nothing, keeping output clean",
+        );
         self.add_builtin(
             ">c",
             ForthRuntime::f_to_c,
@@ -740,6 +1485,51 @@ impl ForthRuntime {
             ForthRuntime::f_builtin_name,
             "builtin-name ( index -- s_addr ) Returns the name of the builtin at the index",
         );
+        self.add_builtin(
+            "break-at",
+            ForthRuntime::f_break_at,
+            "break-at <name> ( -- ) Drop into single step mode whenever <name> is about to execute",
+        );
+        self.add_builtin(
+            "clear-breaks",
+            ForthRuntime::f_clear_breaks,
+            "clear-breaks ( -- ) Remove all breakpoints set by break-at",
+        );
+        self.add_builtin(
+            "watch",
+            ForthRuntime::f_watch,
+            "watch <name> ( -- ) Report changes to the variable <name> while stepping or tracing",
+        );
+        self.add_builtin(
+            "profile-on",
+            ForthRuntime::f_profile_on,
+            "profile-on ( -- ) Clear the profile counts and start counting word invocations",
+        );
+        self.add_builtin(
+            "profile-off",
+            ForthRuntime::f_profile_off,
+            "profile-off ( -- ) Stop counting word invocations",
+        );
+        self.add_builtin(
+            "profile-dump",
+            ForthRuntime::f_profile_dump,
+            "profile-dump ( -- ) Print each profiled word with its call count, most frequent first",
+        );
+        self.add_builtin(
+            "t{",
+            ForthRuntime::f_test_start,
+            "t{ ( -- ) Start a test: the code up to -> computes the results",
+        );
+        self.add_builtin(
+            "->",
+            ForthRuntime::f_test_arrow,
+            "-> ( .. -- ) Capture the test results and remove them from the stack",
+        );
+        self.add_builtin(
+            "}t",
+            ForthRuntime::f_test_end,
+            "}t ( .. -- ) Compare the expected values with the captured results, printing PASS or FAIL",
+        );
     }
 
     /// set_abort_flag allows the abort condition to be made globally visible
@@ -752,10 +1542,56 @@ impl ForthRuntime {
     ///
     pub fn get_abort_flag(&mut self) -> bool {
         let val = self.kernel.get(self.abort_ptr);
-        if val == FALSE {
-            false
-        } else {
+        val != FALSE
+    }
+
+    /// set_error records the kind of failure, and where it came from, replacing any earlier one
+    ///
+    pub fn set_error(&mut self, error: ForthError) {
+        self.error = Some(error);
+        self.error_source = self.u_source_location();
+    }
+
+    /// clear_error forgets the most recent error
+    ///
+    pub fn clear_error(&mut self) {
+        self.error = None;
+        self.error_source = None;
+    }
+
+    /// u_source_location gives name:line for the line being interpreted, or None at the terminal
+    ///
+    pub fn u_source_location(&self) -> Option<String> {
+        match self.reader.last() {
+            Some(reader) if !matches!(reader.source, FType::Stdin) => {
+                Some(format!("{}:{}", reader.name, reader.line_number))
+            }
+            _ => None,
+        }
+    }
+
+    /// install_interrupt_handler routes SIGINT (Ctrl-C in cooked mode) to the interrupt flag
+    ///     rather than letting it terminate the process.
+    ///     In raw mode Ctrl-C arrives as a key event instead, and is handled by the reader.
+    ///
+    pub fn install_interrupt_handler(&mut self) {
+        if let Err(e) =
+            signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&self.interrupt))
+        {
+            self.msg.error("interrupt", &e.to_string(), None::<bool>);
+        }
+    }
+
+    /// check_interrupt converts a pending Ctrl-C into an abort, returning true if one was pending.
+    ///     Polled by the inner interpreter and between lines by quit.
+    ///
+    pub fn check_interrupt(&mut self) -> bool {
+        if self.interrupt.swap(false, Ordering::Relaxed) {
+            self.set_error(ForthError::Interrupted);
+            self.f_abort();
             true
+        } else {
+            false
         }
     }
 
@@ -767,9 +1603,17 @@ impl ForthRuntime {
     }
 
     pub fn f_bye(&mut self) {
+        self.exit_code = 0;
         self.exit_flag = true;
     }
 
+    pub fn f_bye_code(&mut self) {
+        if self.kernel.stack_check(1, "bye-code") {
+            self.exit_code = self.kernel.pop() as i32;
+            self.exit_flag = true;
+        }
+    }
+
     pub fn f_raw_mode_on(&mut self) {
         if let Err(e) = terminal::enable_raw() {
             self.msg.error("raw-mode-on", &e.to_string(), None::<bool>);
@@ -788,17 +1632,195 @@ impl ForthRuntime {
             Err(e) => self.msg.error("raw-mode?", &e.to_string(), None::<bool>),
         }
     }
+
+    pub fn f_page(&mut self) {
+        self.u_terminal_write("page", &terminal::clear_sequence());
+    }
+
+    pub fn f_at_xy(&mut self) {
+        if self.kernel.stack_check(2, "at-xy") {
+            let row = self.kernel.pop();
+            let col = self.kernel.pop();
+            let (col, row) = terminal::clamp_xy(col, row);
+            self.u_terminal_write("at-xy", &terminal::move_sequence(col, row));
+        }
+    }
+
+    /// u_terminal_write sends a cursor or screen sequence to the console output
+    ///
+    fn u_terminal_write(&mut self, word: &str, sequence: &[u8]) {
+        if let Err(e) = self.out.write_all(sequence).and_then(|_| self.out.flush()) {
+            self.msg.error(word, &e.to_string(), None::<bool>);
+        }
+    }
+
+    pub fn f_term_size(&mut self) {
+        let (cols, rows) = terminal::window_size();
+        self.kernel.push(cols as i64);
+        self.kernel.push(rows as i64);
+    }
+
+    pub fn f_fg(&mut self) {
+        self.u_color("fg", false);
+    }
+
+    pub fn f_bg(&mut self) {
+        self.u_color("bg", true);
+    }
+
+    pub fn f_color_reset(&mut self) {
+        self.u_color_write(&terminal::reset_sequence());
+    }
+
+    pub fn f_colors_on(&mut self) {
+        self.colors = true;
+    }
+
+    pub fn f_colors_off(&mut self) {
+        self.colors = false;
+    }
+
+    /// u_color pops a color number for fg or bg, aborting if it isn't one of the 16 standard colors
+    ///
+    fn u_color(&mut self, word: &str, background: bool) {
+        if self.kernel.stack_check(1, word) {
+            let n = self.kernel.pop();
+            match terminal::color_sequence(n, background) {
+                Some(sequence) => {
+                    self.u_color_write(&sequence);
+                }
+                None => {
+                    self.msg.error(word, "Color must be from 0 to 15", Some(n));
+                    self.set_error(ForthError::BadValue);
+                    self.f_abort();
+                }
+            }
+        }
+    }
+
+    /// u_color_write sends a color sequence, returning the number of bytes written
+    ///     Nothing is written when colors are off or output isn't going to a terminal.
+    ///
+    pub fn u_color_write(&mut self, sequence: &[u8]) -> usize {
+        if !self.colors || !io::stdout().is_terminal() {
+            return 0;
+        }
+        match io::stdout().write_all(sequence) {
+            Ok(()) => sequence.len(),
+            Err(_) => 0,
+        }
+    }
 }
 
 /////////////////////////
 /// TESTS
 ///
-
 #[cfg(test)]
 mod tests {
     use super::*;
     //use crate::kernel::{RET_START, STACK_START};
 
+    #[test]
+    fn test_error_kinds() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        let cases = [
+            ("1 +", ForthError::StackUnderflow),
+            ("1 0 /", ForthError::DivByZero),
+            ("7 0 mod", ForthError::DivByZero),
+            ("-5 @", ForthError::BadAddress),
+            ("1 12345678 !", ForthError::BadAddress),
+            (
+                "no-such-word",
+                ForthError::NotFound {
+                    name: "no-such-word".to_string(),
+                },
+            ),
+            ("include /no/such/dir/x.fs", ForthError::FileError),
+            ("42 throw", ForthError::Thrown(42)),
+            ("r>", ForthError::ReturnStack),
+            ("2r@", ForthError::ReturnStack),
+            ("-1 pick", ForthError::BadIndex),
+            ("1 2 -1 nrot", ForthError::BadIndex),
+            ("99 start-timer", ForthError::BadIndex),
+            ("999 arg", ForthError::BadIndex),
+            ("3 buffer: eb  eb 5 b@", ForthError::BadIndex),
+            ("99 fg", ForthError::BadValue),
+            ("pad 0 s\" x\" setenv", ForthError::BadValue),
+            ("12345 pad see>string", ForthError::BadXt),
+            ("100000 allot", ForthError::DictionaryFull),
+            ("0 8 !", ForthError::Protected),
+            ("-100000 allot", ForthError::Protected),
+            ("protect-builtins : dup 1 ;", ForthError::Protected),
+            ("defer ed  ed", ForthError::DeferUnset),
+            ("i", ForthError::CompileError),
+            ("does>", ForthError::CompileError),
+            (": el { a b ;", ForthError::CompileError),
+            (": et 5 to q ;", ForthError::CompileError),
+            (
+                ": en 1 no-such-word ;",
+                ForthError::NotFound {
+                    name: "no-such-word".to_string(),
+                },
+            ),
+            ("abort", ForthError::Aborted),
+            (": ea abort ; ea", ForthError::Aborted),
+            (": eq abort\" stop\" ; 1 eq", ForthError::Aborted),
+        ];
+        for (source, error) in cases {
+            rt.eval_str(source);
+            assert_eq!(rt.error, Some(error), "{source}");
+            rt.f_clear();
+        }
+        // each line starts with no error
+        rt.eval_str("0 throw 6 3 /");
+        assert_eq!(rt.error, None);
+        assert_eq!(rt.kernel.pop(), 2);
+    }
+
+    #[test]
+    fn test_abort_reclaims_strings() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        let s_here = rt.kernel.get_string_ptr();
+        rt.eval_str(": broken-0 no-such-word ;");
+        let free = rt.kernel.get(s_here);
+        for i in 1..20 {
+            rt.eval_str(&format!(": broken-{i} abort\" oops\" no-such-word ;"));
+            assert!(rt.get_abort_flag());
+            assert_eq!(rt.kernel.get(s_here), free);
+        }
+        // strings used by a word completed earlier on the line are kept
+        rt.eval_str(": greet .\" hello\" ; : broken no-such-word ;");
+        assert!(rt.kernel.get(s_here) > free);
+        rt.set_abort_flag(false);
+        assert_eq!(rt.with_captured_output(|rt| rt.eval_str("greet")), "hello");
+        // a string made outside a definition is kept, even when the line then aborts
+        rt.eval_str("variable v");
+        let free = rt.kernel.get(s_here);
+        rt.eval_str("s\" kept\" drop 1- s-create v ! abort");
+        assert!(rt.get_abort_flag());
+        assert!(rt.kernel.get(s_here) > free);
+        rt.set_abort_flag(false);
+        assert_eq!(
+            rt.with_captured_output(|rt| rt.eval_str("v @ type")),
+            "kept"
+        );
+        // a definition abandoned on a later line gives back the strings of its first line
+        let free = rt.kernel.get(s_here);
+        rt.eval_str(": two-lines .\" first\"\n .\" second\" no-such-word ;");
+        assert!(rt.get_abort_flag());
+        assert_eq!(rt.kernel.get(s_here), free);
+        // an abandoned header is dropped, so the words defined after it can still be found
+        rt.eval_str(": broken-locals { a b");
+        assert!(rt.get_abort_flag());
+        rt.eval_str(": after-1 1 ;\n: after-2 2 ;\nafter-1 after-2");
+        assert_eq!(rt.kernel.pop(), 2);
+        assert_eq!(rt.kernel.pop(), 1);
+    }
+
     // Access the kernel directly for testing purposes
     #[test]
     fn test_stack_push_and_pop() {
@@ -820,6 +1842,31 @@ mod tests {
         assert!(!runtime.exit_flag);
     }
 
+    #[test]
+    fn test_bye_code() {
+        let mut runtime = ForthRuntime::new();
+        runtime.cold_start();
+        runtime.kernel.push(3);
+        runtime.f_bye_code();
+        assert!(runtime.should_exit());
+        assert_eq!(runtime.exit_code, 3);
+        runtime.f_bye();
+        assert_eq!(runtime.exit_code, 0);
+    }
+
+    #[test]
+    fn test_vectored_emit() {
+        let mut runtime = ForthRuntime::new();
+        runtime.cold_start();
+        runtime.load_core();
+        runtime.eval_str("variable out  8000 out !");
+        runtime.eval_str(": capture ( c -- ) out @ c! 1 out +! ;");
+        runtime.eval_str("' capture 'emit !  65 emit s\" hi\" tell cr  ' (emit) 'emit !");
+        assert_eq!(runtime.kernel.string_slice(8000, 4), b"Ahi\n");
+        runtime.eval_str("out @");
+        assert_eq!(runtime.kernel.pop(), 8004);
+    }
+
     #[test]
     fn test_cold_start() {
         let mut runtime = ForthRuntime::new();
@@ -867,12 +1914,36 @@ mod tests {
         let addr = rt.add_builtin("add", sample_add, "Add two numbers");
         rt.kernel.push(10);
         rt.kernel.push(32);
-        let cfa = rt.kernel.get(addr as usize) as usize & ADDRESS_MASK;
+        let cfa = rt.kernel.get(addr) as usize & ADDRESS_MASK;
         rt.builtin(cfa);
 
         assert_eq!(rt.kernel.pop(), 42);
     }
 
+    #[test]
+    fn test_return_stack_string() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        assert_eq!(rt.u_return_stack_string(), "R{ } ");
+
+        // snapshot the return stack onto the data stack from inside a nested call
+        fn r_snap(rt: &mut ForthRuntime) {
+            let text = rt.u_return_stack_string();
+            let addr = rt.kernel.string_new(&text);
+            rt.kernel.push(addr as i64);
+        }
+        rt.add_builtin("r-snap", r_snap, "");
+        rt.eval_str(": inner r-snap ;");
+        rt.eval_str(": outer inner ;");
+        rt.eval_str("(') outer");
+        let outer = rt.kernel.pop();
+        rt.eval_str("outer");
+        let addr = rt.kernel.pop() as usize;
+        // the end-of-run marker, then the return address into outer after the call to inner
+        let expected = format!("R{{ 0 {} }} ", outer + 2);
+        assert_eq!(rt.kernel.string_get(addr), expected);
+    }
+
     #[test]
     fn test_insert_variables() {
         let mut runtime = ForthRuntime::new();
@@ -892,7 +1963,7 @@ mod tests {
         runtime.cold_start();
 
         runtime.compile_builtins();
-        assert_eq!(runtime.kernel.get_builtin(5).name, "<".to_string());
+        assert_eq!(runtime.kernel.get_builtin(7).name, "<".to_string());
         assert_eq!(runtime.kernel.get_builtin(0).name, "+".to_string());
     }
 
@@ -918,6 +1989,55 @@ mod tests {
         assert_eq!(runtime.kernel.get(runtime.state_ptr), 0);
     }
 
+    #[test]
+    fn test_at_xy_clamps() {
+        assert_eq!(terminal::clamp_xy(-5, -1), (0, 0));
+        assert_eq!(terminal::clamp_xy(10, 1 << 20), (10, u16::MAX));
+        let mut runtime = ForthRuntime::new();
+        runtime.cold_start();
+        let text = runtime.with_captured_output(|rt| {
+            rt.kernel.push(-5);
+            rt.kernel.push(-1);
+            rt.f_at_xy();
+            rt.kernel.push(10);
+            rt.kernel.push(3);
+            rt.f_at_xy();
+            rt.f_page();
+        });
+        assert_eq!(text, "\x1b[1;1H\x1b[4;11H\x1b[2J\x1b[1;1H");
+        assert_eq!(runtime.kernel.stack_len(), 0);
+    }
+
+    #[test]
+    fn test_term_size() {
+        let mut runtime = ForthRuntime::new();
+        runtime.cold_start();
+        runtime.f_term_size();
+        let rows = runtime.kernel.pop();
+        let cols = runtime.kernel.pop();
+        assert!(cols > 0 && rows > 0);
+    }
+
+    #[test]
+    fn test_colors_off_writes_nothing() {
+        let sequence = terminal::color_sequence(1, false).unwrap();
+        assert!(sequence.starts_with(b"\x1b["));
+        assert!(terminal::color_sequence(16, true).is_none());
+        assert!(terminal::color_sequence(-1, false).is_none());
+        let mut runtime = ForthRuntime::new();
+        runtime.cold_start();
+        runtime.f_colors_off();
+        assert_eq!(runtime.u_color_write(&sequence), 0);
+        assert_eq!(runtime.u_color_write(&terminal::reset_sequence()), 0);
+        runtime.kernel.push(4);
+        runtime.f_fg();
+        assert_eq!(runtime.kernel.stack_len(), 0);
+        assert!(!runtime.get_abort_flag());
+        runtime.kernel.push(99);
+        runtime.f_bg();
+        assert!(runtime.get_abort_flag());
+    }
+
     #[test]
     fn test_set_abort_flag() {
         let mut runtime = ForthRuntime::new();
@@ -966,6 +2086,68 @@ mod tests {
         assert_eq!(runtime.kernel.stack_len(), 0); // stack should be cleared
     }
 
+    #[test]
+    fn test_interrupt_stops_definition() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+
+        fn ctrl_c(rt: &mut ForthRuntime) {
+            rt.interrupt.store(true, Ordering::Relaxed);
+        }
+        let addr = rt.add_builtin("ctrl-c", ctrl_c, "Simulate a Ctrl-C");
+        let xt = rt.kernel.get(addr);
+        // : spin  begin ctrl-c again ;  -- loops forever unless the interrupt is honored
+        let cfa = rt.make_word("spin", &[DEFINITION, xt, BRANCH, -2, EXIT]);
+        rt.kernel.push(99);
+        rt.kernel.push(cfa as i64 + 1);
+        rt.i_definition();
+
+        assert!(rt.get_abort_flag());
+        assert_eq!(rt.error, Some(ForthError::Interrupted));
+        assert!(!rt.interrupt.load(Ordering::Relaxed));
+        assert_eq!(rt.kernel.stack_len(), 0);
+        assert_eq!(rt.kernel.get_return_ptr(), crate::kernel::RET_START);
+    }
+
+    #[test]
+    fn test_tail_call_reuses_frame() {
+        let mut rt = ForthRuntime::new();
+        rt.cold_start();
+        rt.load_core();
+        // far more iterations than the return stack can hold if each call pushed a frame
+        rt.eval_str(": countdown dup 0= if exit then 1- recurse ;");
+        rt.eval_str("100000 countdown");
+        assert!(rt.get_abort_flag()); // each call keeps its frame until tail-calls is set
+        rt.set_abort_flag(false);
+        rt.f_clear();
+        rt.eval_str("-1 tail-calls !  100000 countdown");
+        assert!(!rt.get_abort_flag());
+        assert_eq!(rt.kernel.pop(), 0);
+        assert_eq!(rt.kernel.get_return_ptr(), crate::kernel::RET_START);
+
+        // mutual recursion through a tail call to a word defined later
+        rt.eval_str("defer odd?");
+        rt.eval_str(": even? dup 0= if drop -1 exit then 1- odd? ;");
+        rt.eval_str(": (odd?) dup 0= if drop 0 exit then 1- even? ;");
+        rt.eval_str("' (odd?) is odd?");
+        rt.eval_str("50001 even?");
+        assert!(!rt.get_abort_flag());
+        assert_eq!(rt.kernel.pop(), FALSE);
+
+        // a call that is not followed by EXIT still needs its frame
+        rt.eval_str(": down dup if 1- recurse 1+ then ;");
+        rt.eval_str("100000 down");
+        assert!(rt.get_abort_flag());
+        rt.set_abort_flag(false);
+        rt.f_clear();
+
+        // with tail calls off, a callee that drops its return address returns past its caller
+        rt.eval_str("0 tail-calls !");
+        rt.eval_str(": up r> drop ;  : mid up ;  : top mid 2 ;  top");
+        assert_eq!(rt.kernel.pop(), 2);
+        assert_eq!(rt.kernel.stack_len(), 0);
+    }
+
     #[test]
     fn test_f_get_compile_mode() {
         let mut runtime = ForthRuntime::new();